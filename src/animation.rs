@@ -0,0 +1,413 @@
+use crate::model::ModelHandle;
+use crate::node::Node;
+use crate::pose::{self, Pose};
+use crate::renderer::RendererState;
+use legion::*;
+use na::{Quaternion, UnitQuaternion, Vector3};
+use std::time::Instant;
+
+/// How values are interpolated between two keyframes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    /// The value of the previous keyframe is held until the next one.
+    Step,
+    /// Hermite spline, every keyframe stores an in tangent, the value and an out tangent.
+    CubicSpline,
+}
+
+/// The property of a node a channel animates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Property {
+    Translation,
+    /// Stored as x, y, z, w.
+    Rotation,
+    Scale,
+    MorphWeights,
+}
+
+/// The keyframes of a single property of a single node.
+pub struct Channel {
+    /// Index of the animated node within its model.
+    pub node: usize,
+    pub property: Property,
+    pub interpolation: Interpolation,
+    /// Time of every keyframe in seconds, in increasing order.
+    pub times: Vec<f32>,
+    /// `components` values per keyframe, three times as many for cubic splines.
+    pub values: Vec<f32>,
+    /// Number of values the property is made of, e.g. 3 for a translation.
+    pub components: usize,
+}
+
+impl Channel {
+    pub fn new(
+        node: usize,
+        property: Property,
+        interpolation: Interpolation,
+        times: Vec<f32>,
+        values: Vec<f32>,
+        components: usize,
+    ) -> Self {
+        Self {
+            node,
+            property,
+            interpolation,
+            times,
+            values,
+            components,
+        }
+    }
+
+    /// Time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.times.last().copied().unwrap_or(0.0)
+    }
+
+    // The values of a keyframe, with the tangents skipped for cubic splines
+    fn keyframe(&self, keyframe: usize) -> &[f32] {
+        let start = match self.interpolation {
+            Interpolation::CubicSpline => (keyframe * 3 + 1) * self.components,
+            Interpolation::Linear | Interpolation::Step => keyframe * self.components,
+        };
+        &self.values[start..start + self.components]
+    }
+
+    fn in_tangent(&self, keyframe: usize) -> &[f32] {
+        let start = keyframe * 3 * self.components;
+        &self.values[start..start + self.components]
+    }
+
+    fn out_tangent(&self, keyframe: usize) -> &[f32] {
+        let start = (keyframe * 3 + 2) * self.components;
+        &self.values[start..start + self.components]
+    }
+
+    /// Evaluates the channel at a time in seconds. Times outside of the keyframes hold the first or
+    /// last value.
+    pub fn sample(&self, time: f32) -> Vec<f32> {
+        let last = match self.times.len() {
+            0 => return Vec::new(),
+            count => count - 1,
+        };
+
+        if time <= self.times[0] {
+            return self.keyframe(0).to_vec();
+        }
+        if time >= self.times[last] {
+            return self.keyframe(last).to_vec();
+        }
+
+        let previous = self
+            .times
+            .iter()
+            .rposition(|keyframe_time| *keyframe_time <= time)
+            .unwrap_or(0);
+        let next = previous + 1;
+        let delta = self.times[next] - self.times[previous];
+        let t = if delta > 0.0 {
+            (time - self.times[previous]) / delta
+        } else {
+            0.0
+        };
+
+        match self.interpolation {
+            Interpolation::Step => self.keyframe(previous).to_vec(),
+            Interpolation::Linear if self.property == Property::Rotation => {
+                let interpolated = pose::slerp(
+                    &rotation(self.keyframe(previous)),
+                    &rotation(self.keyframe(next)),
+                    t,
+                );
+                vec![
+                    interpolated.i,
+                    interpolated.j,
+                    interpolated.k,
+                    interpolated.w,
+                ]
+            }
+            Interpolation::Linear => self
+                .keyframe(previous)
+                .iter()
+                .zip(self.keyframe(next).iter())
+                .map(|(a, b)| a + (b - a) * t)
+                .collect(),
+            Interpolation::CubicSpline => {
+                let t2 = t * t;
+                let t3 = t2 * t;
+                let (start, end) = (self.keyframe(previous), self.keyframe(next));
+                let (out_tangent, in_tangent) = (self.out_tangent(previous), self.in_tangent(next));
+                (0..self.components)
+                    .map(|i| {
+                        (2.0 * t3 - 3.0 * t2 + 1.0) * start[i]
+                            + (t3 - 2.0 * t2 + t) * delta * out_tangent[i]
+                            + (-2.0 * t3 + 3.0 * t2) * end[i]
+                            + (t3 - t2) * delta * in_tangent[i]
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+// Rotations are stored as x, y, z, w
+fn rotation(value: &[f32]) -> UnitQuaternion<f32> {
+    UnitQuaternion::from_quaternion(Quaternion::new(value[3], value[0], value[1], value[2]))
+}
+
+/// A keyframe animation of the nodes of a model.
+pub struct Animation {
+    pub name: Option<String>,
+    pub channels: Vec<Channel>,
+    /// Time of the last keyframe of any channel.
+    pub duration: f32,
+}
+
+impl Animation {
+    pub fn new(name: Option<String>, channels: Vec<Channel>) -> Self {
+        let duration = channels
+            .iter()
+            .map(|channel| channel.duration())
+            .fold(0.0, f32::max);
+
+        Self {
+            name,
+            channels,
+            duration,
+        }
+    }
+
+    /// Writes the animated properties at a time in seconds into a pose, everything else is left
+    /// as is.
+    pub fn sample_pose(&self, time: f32, pose: &mut Pose) {
+        for channel in self.channels.iter() {
+            let value = channel.sample(time);
+            if value.len() < channel.components {
+                continue;
+            }
+
+            let node_pose = &mut pose.nodes[channel.node];
+            match channel.property {
+                Property::Translation => {
+                    node_pose.translation = Vector3::new(value[0], value[1], value[2]);
+                }
+                Property::Rotation => {
+                    node_pose.rotation = rotation(&value);
+                }
+                Property::Scale => {
+                    node_pose.scale = Vector3::new(value[0], value[1], value[2]);
+                }
+                Property::MorphWeights => {
+                    node_pose.weights = value;
+                }
+            }
+        }
+    }
+
+    /// Poses the nodes as they are at a time in seconds. Only animated properties are changed.
+    pub fn apply(&self, time: f32, nodes: &mut [Node]) {
+        let mut pose = Pose::from_nodes(nodes);
+        self.sample_pose(time, &mut pose);
+        pose.apply(nodes);
+    }
+
+    /// Wraps or clamps a time in seconds into the animation.
+    pub fn clip_time(&self, time: f32, looping: bool) -> f32 {
+        if looping && self.duration > 0.0 {
+            time.rem_euclid(self.duration)
+        } else {
+            time.max(0.0).min(self.duration)
+        }
+    }
+}
+
+/// Plays one of the animations of the model it's attached to.
+pub struct AnimationPlayer {
+    /// Index of the animation within the model.
+    pub animation: usize,
+    /// Current position within the animation in seconds.
+    pub time: f32,
+    /// Multiplier of how fast time passes, negative plays backwards.
+    pub speed: f32,
+    /// Wraps around at the end instead of stopping.
+    pub looping: bool,
+    pub playing: bool,
+    last_update: Option<Instant>,
+}
+
+impl AnimationPlayer {
+    pub fn new(animation: usize) -> Self {
+        Self {
+            animation,
+            time: 0.0,
+            speed: 1.0,
+            looping: true,
+            playing: true,
+            last_update: None,
+        }
+    }
+
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    /// Pauses and rewinds to the start.
+    pub fn stop(&mut self) {
+        self.playing = false;
+        self.time = 0.0;
+    }
+
+    /// Switches to another animation, starting it from the beginning.
+    pub fn set_animation(&mut self, animation: usize) {
+        self.animation = animation;
+        self.time = 0.0;
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    /// Moves time forward by `delta` seconds. Animations which don't loop stop at either end.
+    pub fn advance(&mut self, delta: f32, duration: f32) {
+        if !self.playing {
+            return;
+        }
+
+        self.time += delta * self.speed;
+        if self.looping && duration > 0.0 {
+            self.time = self.time.rem_euclid(duration);
+        } else if self.time > duration {
+            self.time = duration;
+            self.playing = false;
+        } else if self.time < 0.0 {
+            self.time = 0.0;
+            self.playing = false;
+        }
+    }
+}
+
+#[system(for_each)]
+pub fn update_animations(
+    model_handle: &ModelHandle,
+    player: &mut AnimationPlayer,
+    #[resource] rs: &mut RendererState,
+) {
+    let now = Instant::now();
+    let delta = player
+        .last_update
+        .map(|last_update| (now - last_update).as_secs_f32())
+        .unwrap_or(0.0);
+    player.last_update = Some(now);
+
+    let model = rs.get_mut_model(model_handle);
+    let animation = match model.animations.get(player.animation) {
+        Some(animation) => animation,
+        None => return,
+    };
+
+    player.advance(delta, animation.duration);
+    animation.apply(player.time, &mut model.nodes);
+    model.update_mesh_transformations();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn scalar_channel(interpolation: Interpolation, times: Vec<f32>, values: Vec<f32>) -> Channel {
+        Channel::new(0, Property::MorphWeights, interpolation, times, values, 1)
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1.0e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn step_holds_the_previous_keyframe() {
+        let channel = scalar_channel(
+            Interpolation::Step,
+            vec![0.0, 1.0, 2.0],
+            vec![0.0, 10.0, 20.0],
+        );
+
+        assert_eq!(channel.sample(0.5), vec![0.0]);
+        assert_eq!(channel.sample(1.0), vec![10.0]);
+        assert_eq!(channel.sample(1.99), vec![10.0]);
+    }
+
+    #[test]
+    fn linear_interpolates_between_keyframes() {
+        let channel = scalar_channel(
+            Interpolation::Linear,
+            vec![0.0, 1.0, 3.0],
+            vec![0.0, 10.0, 20.0],
+        );
+
+        assert_close(channel.sample(0.5)[0], 5.0);
+        assert_close(channel.sample(2.0)[0], 15.0);
+    }
+
+    #[test]
+    fn times_outside_the_keyframes_hold_the_ends() {
+        let channel = scalar_channel(Interpolation::Linear, vec![1.0, 2.0], vec![3.0, 4.0]);
+
+        assert_eq!(channel.sample(0.0), vec![3.0]);
+        assert_eq!(channel.sample(5.0), vec![4.0]);
+        assert!(
+            scalar_channel(Interpolation::Linear, Vec::new(), Vec::new())
+                .sample(0.0)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn linear_rotations_are_slerped() {
+        let quarter_turn = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), FRAC_PI_2);
+        let channel = Channel::new(
+            0,
+            Property::Rotation,
+            Interpolation::Linear,
+            vec![0.0, 1.0],
+            vec![
+                0.0,
+                0.0,
+                0.0,
+                1.0,
+                quarter_turn.i,
+                quarter_turn.j,
+                quarter_turn.k,
+                quarter_turn.w,
+            ],
+            4,
+        );
+
+        let sampled = rotation(&channel.sample(0.5));
+        let eighth_turn = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), FRAC_PI_2 / 2.0);
+        assert!(sampled.angle_to(&eighth_turn) < 1.0e-4);
+    }
+
+    #[test]
+    fn cubic_spline_follows_the_tangents() {
+        // In tangent, value and out tangent of each keyframe
+        let channel = scalar_channel(
+            Interpolation::CubicSpline,
+            vec![0.0, 2.0],
+            vec![0.0, 0.0, 1.0, 0.0, 1.0, 0.0],
+        );
+
+        assert_eq!(channel.sample(0.0), vec![0.0]);
+        assert_eq!(channel.sample(2.0), vec![1.0]);
+        // Tangents are scaled by the two seconds between the keyframes
+        assert_close(channel.sample(1.0)[0], 0.5 + 0.25);
+        assert_close(channel.sample(0.5)[0], 0.15625 + 0.28125);
+    }
+}
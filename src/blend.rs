@@ -0,0 +1,606 @@
+use crate::animation::Animation;
+use crate::model::ModelHandle;
+use crate::pose::Pose;
+use crate::renderer::RendererState;
+use legion::*;
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// A node of a blend tree, evaluating one or more animations into a single pose.
+pub enum BlendNode {
+    /// A single animation of the model, played `speed` times as fast.
+    Clip { animation: usize, speed: f32 },
+    /// Blends between children placed along a parameter, e.g. idle at 0.0, walk at 1.5 and run at
+    /// 4.0 for a speed parameter. Children have to be sorted by their threshold.
+    Blend1D {
+        parameter: String,
+        children: Vec<(f32, BlendNode)>,
+    },
+    /// Layers an animation on top of a base, relative to the animation's first frame. The
+    /// parameter is the weight of the layer.
+    Additive {
+        base: Box<BlendNode>,
+        animation: usize,
+        speed: f32,
+        parameter: String,
+    },
+}
+
+impl BlendNode {
+    /// Evaluates the tree `time` seconds into the state it belongs to. Nodes which aren't
+    /// animated keep their rest pose.
+    pub fn evaluate(
+        &self,
+        time: f32,
+        looping: bool,
+        animations: &[Animation],
+        rest_pose: &Pose,
+        parameters: &HashMap<String, f32>,
+    ) -> Pose {
+        match self {
+            BlendNode::Clip { animation, speed } => {
+                sample_clip(*animation, time * speed, looping, animations, rest_pose)
+            }
+            BlendNode::Blend1D {
+                parameter,
+                children,
+            } => {
+                let value = parameters.get(parameter).copied().unwrap_or(0.0);
+                let next = children
+                    .iter()
+                    .position(|(threshold, _)| *threshold > value)
+                    .unwrap_or(children.len());
+
+                // Outside of the thresholds the closest child plays on its own
+                if next == 0 || next == children.len() {
+                    return match children.get(next.min(children.len().saturating_sub(1))) {
+                        Some((_, child)) => {
+                            child.evaluate(time, looping, animations, rest_pose, parameters)
+                        }
+                        None => rest_pose.clone(),
+                    };
+                }
+
+                let (previous_threshold, previous_child) = &children[next - 1];
+                let (next_threshold, next_child) = &children[next];
+                let factor = (value - previous_threshold) / (next_threshold - previous_threshold);
+                previous_child
+                    .evaluate(time, looping, animations, rest_pose, parameters)
+                    .blend(
+                        &next_child.evaluate(time, looping, animations, rest_pose, parameters),
+                        factor,
+                    )
+            }
+            BlendNode::Additive {
+                base,
+                animation,
+                speed,
+                parameter,
+            } => {
+                let weight = parameters.get(parameter).copied().unwrap_or(0.0);
+                let base = base.evaluate(time, looping, animations, rest_pose, parameters);
+                let additive =
+                    sample_clip(*animation, time * speed, looping, animations, rest_pose);
+                let reference = sample_clip(*animation, 0.0, looping, animations, rest_pose);
+                base.add(&additive, &reference, weight)
+            }
+        }
+    }
+
+    /// Length of the longest animation played by the tree, ignoring additive layers.
+    pub fn duration(&self, animations: &[Animation]) -> f32 {
+        match self {
+            BlendNode::Clip { animation, speed } => animations
+                .get(*animation)
+                .filter(|_| *speed != 0.0)
+                .map(|animation| animation.duration / speed.abs())
+                .unwrap_or(0.0),
+            BlendNode::Blend1D { children, .. } => children
+                .iter()
+                .map(|(_, child)| child.duration(animations))
+                .fold(0.0, f32::max),
+            BlendNode::Additive { base, .. } => base.duration(animations),
+        }
+    }
+}
+
+fn sample_clip(
+    animation: usize,
+    time: f32,
+    looping: bool,
+    animations: &[Animation],
+    rest_pose: &Pose,
+) -> Pose {
+    let mut pose = rest_pose.clone();
+    if let Some(animation) = animations.get(animation) {
+        animation.sample_pose(animation.clip_time(time, looping), &mut pose);
+    }
+    pose
+}
+
+pub struct State {
+    pub name: String,
+    pub motion: BlendNode,
+    /// Non looping states hold their last frame once finished.
+    pub looping: bool,
+}
+
+impl State {
+    pub fn new(name: &str, motion: BlendNode, looping: bool) -> Self {
+        Self {
+            name: name.to_string(),
+            motion,
+            looping,
+        }
+    }
+}
+
+/// A condition which has to hold for a transition to be taken.
+pub enum Condition {
+    Greater(String, f32),
+    Less(String, f32),
+    /// The current state played all of its animations through.
+    Finished,
+}
+
+pub struct Transition {
+    /// Index of the state the transition leaves, `None` for any state.
+    pub from: Option<usize>,
+    pub to: usize,
+    /// All of the conditions have to hold.
+    pub conditions: Vec<Condition>,
+    /// Length of the crossfade in seconds.
+    pub duration: f32,
+}
+
+impl Transition {
+    pub fn new(from: Option<usize>, to: usize, conditions: Vec<Condition>, duration: f32) -> Self {
+        Self {
+            from,
+            to,
+            conditions,
+            duration,
+        }
+    }
+}
+
+// The state being faded out of during a transition
+struct Crossfade {
+    state: usize,
+    time: f32,
+    elapsed: f32,
+    duration: f32,
+    // A crossfade into `state` which was still going on when this one started
+    previous: Option<Box<Crossfade>>,
+}
+
+impl Crossfade {
+    // Returns whether the crossfade is over
+    fn advance(&mut self, delta: f32) -> bool {
+        self.time += delta;
+        self.elapsed += delta;
+        if let Some(previous) = &mut self.previous {
+            if previous.advance(delta) {
+                self.previous = None;
+            }
+        }
+        self.elapsed >= self.duration
+    }
+
+    fn factor(&self) -> f32 {
+        if self.duration > 0.0 {
+            (self.elapsed / self.duration).min(1.0)
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Switches between states of blend trees depending on parameters, crossfading from one to the
+/// next. Attach it next to a `ModelHandle` to drive the model's nodes.
+pub struct StateMachine {
+    pub states: Vec<State>,
+    pub transitions: Vec<Transition>,
+    pub parameters: HashMap<String, f32>,
+    current: usize,
+    time: f32,
+    crossfade: Option<Crossfade>,
+    last_update: Option<Instant>,
+}
+
+impl StateMachine {
+    pub fn new(states: Vec<State>, transitions: Vec<Transition>, initial_state: usize) -> Self {
+        Self {
+            states,
+            transitions,
+            parameters: HashMap::new(),
+            current: initial_state,
+            time: 0.0,
+            crossfade: None,
+            last_update: None,
+        }
+    }
+
+    pub fn set_parameter(&mut self, name: &str, value: f32) {
+        self.parameters.insert(name.to_string(), value);
+    }
+
+    /// Index of the state being played, or faded into.
+    pub fn current_state(&self) -> usize {
+        self.current
+    }
+
+    /// Starts fading into another state regardless of the transitions. A crossfade which is still
+    /// going on keeps going, the new one fades out of the pose it blends.
+    pub fn crossfade_to(&mut self, state: usize, duration: f32) {
+        self.crossfade = Some(Crossfade {
+            state: self.current,
+            time: self.time,
+            elapsed: 0.0,
+            duration,
+            previous: self.crossfade.take().map(Box::new),
+        });
+        self.current = state;
+        self.time = 0.0;
+    }
+
+    /// Moves time forward by `delta` seconds and takes the first transition whose conditions
+    /// hold. Transitions aren't taken while a crossfade is still going on.
+    pub fn update(&mut self, delta: f32, animations: &[Animation]) {
+        self.time += delta;
+
+        if let Some(crossfade) = &mut self.crossfade {
+            if crossfade.advance(delta) {
+                self.crossfade = None;
+            }
+            return;
+        }
+
+        let finished = !self.states[self.current].looping
+            && self.time >= self.states[self.current].motion.duration(animations);
+        let transition = self.transitions.iter().find(|transition| {
+            transition.from.unwrap_or(self.current) == self.current
+                && transition.to != self.current
+                && transition
+                    .conditions
+                    .iter()
+                    .all(|condition| match condition {
+                        Condition::Greater(parameter, value) => {
+                            self.parameters.get(parameter).copied().unwrap_or(0.0) > *value
+                        }
+                        Condition::Less(parameter, value) => {
+                            self.parameters.get(parameter).copied().unwrap_or(0.0) < *value
+                        }
+                        Condition::Finished => finished,
+                    })
+        });
+
+        if let Some(transition) = transition {
+            let (to, duration) = (transition.to, transition.duration);
+            self.crossfade_to(to, duration);
+        }
+    }
+
+    /// Evaluates the current state, blended with the previous one while crossfading.
+    pub fn pose(&self, animations: &[Animation], rest_pose: &Pose) -> Pose {
+        let pose = self.evaluate(self.current, self.time, animations, rest_pose);
+        match &self.crossfade {
+            Some(crossfade) => self
+                .faded_out_pose(crossfade, animations, rest_pose)
+                .blend(&pose, crossfade.factor()),
+            None => pose,
+        }
+    }
+
+    // The pose a crossfade fades out of, itself blended if it interrupted another crossfade
+    fn faded_out_pose(
+        &self,
+        crossfade: &Crossfade,
+        animations: &[Animation],
+        rest_pose: &Pose,
+    ) -> Pose {
+        let pose = self.evaluate(crossfade.state, crossfade.time, animations, rest_pose);
+        match &crossfade.previous {
+            Some(previous) => self
+                .faded_out_pose(previous, animations, rest_pose)
+                .blend(&pose, previous.factor()),
+            None => pose,
+        }
+    }
+
+    fn evaluate(
+        &self,
+        state: usize,
+        time: f32,
+        animations: &[Animation],
+        rest_pose: &Pose,
+    ) -> Pose {
+        let state = &self.states[state];
+        state
+            .motion
+            .evaluate(time, state.looping, animations, rest_pose, &self.parameters)
+    }
+}
+
+#[system(for_each)]
+pub fn update_state_machines(
+    model_handle: &ModelHandle,
+    state_machine: &mut StateMachine,
+    #[resource] rs: &mut RendererState,
+) {
+    let now = Instant::now();
+    let delta = state_machine
+        .last_update
+        .map(|last_update| (now - last_update).as_secs_f32())
+        .unwrap_or(0.0);
+    state_machine.last_update = Some(now);
+
+    let model = rs.get_mut_model(model_handle);
+    state_machine.update(delta, &model.animations);
+    let pose = state_machine.pose(&model.animations, &model.rest_pose);
+    pose.apply(&mut model.nodes);
+    model.update_mesh_transformations();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::{Channel, Interpolation, Property};
+    use crate::pose::NodePose;
+    use na::{UnitQuaternion, Vector3};
+
+    // Moves the single node along x, from `start` at 0.0 seconds to `end` at 1.0 seconds
+    fn slide(start: f32, end: f32) -> Animation {
+        Animation::new(
+            None,
+            vec![Channel::new(
+                0,
+                Property::Translation,
+                Interpolation::Linear,
+                vec![0.0, 1.0],
+                vec![start, 0.0, 0.0, end, 0.0, 0.0],
+                3,
+            )],
+        )
+    }
+
+    fn rest_pose() -> Pose {
+        Pose {
+            nodes: vec![NodePose {
+                translation: Vector3::zeros(),
+                rotation: UnitQuaternion::identity(),
+                scale: Vector3::repeat(1.0),
+                weights: Vec::new(),
+            }],
+        }
+    }
+
+    fn clip(animation: usize) -> BlendNode {
+        BlendNode::Clip {
+            animation,
+            speed: 1.0,
+        }
+    }
+
+    // Position of the single node along x, read from its pose matrix
+    fn sampled_x(pose: &Pose) -> f32 {
+        pose.nodes[0].local_transformation()[(0, 3)]
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1.0e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn clip_samples_at_its_speed() {
+        let animations = vec![slide(0.0, 4.0)];
+        let parameters = HashMap::new();
+        let motion = BlendNode::Clip {
+            animation: 0,
+            speed: 2.0,
+        };
+
+        let pose = motion.evaluate(0.25, false, &animations, &rest_pose(), &parameters);
+        assert_close(sampled_x(&pose), 2.0);
+        assert_close(motion.duration(&animations), 0.5);
+    }
+
+    #[test]
+    fn clip_of_a_missing_animation_keeps_the_rest_pose() {
+        let pose = clip(3).evaluate(0.5, false, &[], &rest_pose(), &HashMap::new());
+        assert_eq!(pose, rest_pose());
+    }
+
+    #[test]
+    fn blend_1d_weights_children_by_the_parameter() {
+        let animations = vec![slide(0.0, 0.0), slide(2.0, 2.0), slide(10.0, 10.0)];
+        let motion = BlendNode::Blend1D {
+            parameter: "speed".to_string(),
+            children: vec![(0.0, clip(0)), (1.0, clip(1)), (3.0, clip(2))],
+        };
+
+        let sample = |speed: f32| {
+            let mut parameters = HashMap::new();
+            parameters.insert("speed".to_string(), speed);
+            sampled_x(&motion.evaluate(0.5, true, &animations, &rest_pose(), &parameters))
+        };
+
+        assert_close(sample(0.0), 0.0);
+        assert_close(sample(0.5), 1.0);
+        assert_close(sample(1.0), 2.0);
+        assert_close(sample(2.0), 6.0);
+        assert_close(sample(3.0), 10.0);
+        // Outside of the thresholds the closest child plays on its own
+        assert_close(sample(-1.0), 0.0);
+        assert_close(sample(5.0), 10.0);
+    }
+
+    #[test]
+    fn blend_1d_without_the_parameter_uses_zero() {
+        let animations = vec![slide(4.0, 4.0), slide(8.0, 8.0)];
+        let motion = BlendNode::Blend1D {
+            parameter: "speed".to_string(),
+            children: vec![(-1.0, clip(0)), (1.0, clip(1))],
+        };
+
+        let pose = motion.evaluate(0.0, true, &animations, &rest_pose(), &HashMap::new());
+        assert_close(sampled_x(&pose), 6.0);
+    }
+
+    #[test]
+    fn blend_1d_without_children_keeps_the_rest_pose() {
+        let motion = BlendNode::Blend1D {
+            parameter: "speed".to_string(),
+            children: Vec::new(),
+        };
+
+        let pose = motion.evaluate(0.5, true, &[], &rest_pose(), &HashMap::new());
+        assert_eq!(pose, rest_pose());
+    }
+
+    #[test]
+    fn additive_layers_the_change_since_the_first_frame() {
+        let animations = vec![slide(1.0, 1.0), slide(3.0, 7.0)];
+        let motion = BlendNode::Additive {
+            base: Box::new(clip(0)),
+            animation: 1,
+            speed: 1.0,
+            parameter: "layer".to_string(),
+        };
+
+        let mut parameters = HashMap::new();
+        parameters.insert("layer".to_string(), 0.5);
+        // The layer moved from 3.0 to 5.0, half of which is added onto the base at 1.0
+        let pose = motion.evaluate(0.5, false, &animations, &rest_pose(), &parameters);
+        assert_close(sampled_x(&pose), 2.0);
+
+        parameters.insert("layer".to_string(), 0.0);
+        let pose = motion.evaluate(0.5, false, &animations, &rest_pose(), &parameters);
+        assert_close(sampled_x(&pose), 1.0);
+    }
+
+    fn machine(transitions: Vec<Transition>) -> StateMachine {
+        StateMachine::new(
+            vec![
+                State::new("idle", clip(0), true),
+                State::new("walk", clip(1), true),
+                State::new("run", clip(2), false),
+            ],
+            transitions,
+            0,
+        )
+    }
+
+    fn machine_animations() -> Vec<Animation> {
+        vec![slide(0.0, 0.0), slide(2.0, 2.0), slide(10.0, 10.0)]
+    }
+
+    #[test]
+    fn transition_crossfades_into_the_next_state() {
+        let animations = machine_animations();
+        let mut state_machine = machine(vec![Transition::new(
+            Some(0),
+            1,
+            vec![Condition::Greater("speed".to_string(), 0.5)],
+            1.0,
+        )]);
+
+        state_machine.update(0.1, &animations);
+        assert_eq!(state_machine.current_state(), 0);
+
+        state_machine.set_parameter("speed", 1.0);
+        state_machine.update(0.1, &animations);
+        assert_eq!(state_machine.current_state(), 1);
+        assert_close(
+            sampled_x(&state_machine.pose(&animations, &rest_pose())),
+            0.0,
+        );
+
+        state_machine.update(0.25, &animations);
+        assert_close(
+            sampled_x(&state_machine.pose(&animations, &rest_pose())),
+            0.5,
+        );
+
+        state_machine.update(0.75, &animations);
+        assert_close(
+            sampled_x(&state_machine.pose(&animations, &rest_pose())),
+            2.0,
+        );
+    }
+
+    #[test]
+    fn transitions_wait_for_the_crossfade_to_finish() {
+        let animations = machine_animations();
+        let mut state_machine = machine(vec![Transition::new(
+            None,
+            2,
+            vec![Condition::Greater("speed".to_string(), 2.0)],
+            0.5,
+        )]);
+
+        state_machine.crossfade_to(1, 1.0);
+        state_machine.set_parameter("speed", 3.0);
+        state_machine.update(0.25, &animations);
+        assert_eq!(state_machine.current_state(), 1);
+
+        // The crossfade ends on this update, the transition is taken on the next
+        state_machine.update(0.75, &animations);
+        assert_eq!(state_machine.current_state(), 1);
+        state_machine.update(0.0, &animations);
+        assert_eq!(state_machine.current_state(), 2);
+    }
+
+    #[test]
+    fn finished_condition_waits_for_non_looping_states() {
+        let animations = machine_animations();
+        let mut state_machine = StateMachine::new(
+            vec![
+                State::new("jump", clip(1), false),
+                State::new("idle", clip(0), true),
+            ],
+            vec![Transition::new(Some(0), 1, vec![Condition::Finished], 0.0)],
+            0,
+        );
+
+        state_machine.update(0.5, &animations);
+        assert_eq!(state_machine.current_state(), 0);
+        state_machine.update(0.5, &animations);
+        assert_eq!(state_machine.current_state(), 1);
+    }
+
+    #[test]
+    fn crossfade_to_during_a_crossfade_fades_out_of_the_blended_pose() {
+        let animations = machine_animations();
+        let mut state_machine = machine(Vec::new());
+
+        state_machine.crossfade_to(1, 1.0);
+        state_machine.update(0.5, &animations);
+        assert_close(
+            sampled_x(&state_machine.pose(&animations, &rest_pose())),
+            1.0,
+        );
+
+        // The pose doesn't jump, the first crossfade keeps going underneath the second
+        state_machine.crossfade_to(2, 1.0);
+        assert_eq!(state_machine.current_state(), 2);
+        assert_close(
+            sampled_x(&state_machine.pose(&animations, &rest_pose())),
+            1.0,
+        );
+
+        // 0.75 of the way from idle at 0.0 to walk at 2.0, a quarter of the way to run at 10.0
+        state_machine.update(0.25, &animations);
+        assert_close(
+            sampled_x(&state_machine.pose(&animations, &rest_pose())),
+            3.625,
+        );
+
+        // The first crossfade has finished, walk fades into run on its own
+        state_machine.update(0.25, &animations);
+        assert_close(
+            sampled_x(&state_machine.pose(&animations, &rest_pose())),
+            6.0,
+        );
+    }
+}
@@ -1,16 +1,65 @@
-use nalgebra::{IsometryMatrix3, Perspective3, Point3, Vector3};
+use nalgebra::{IsometryMatrix3, Matrix4, Orthographic3, Perspective3, Point3, Vector3};
+
+/// Aspect ratio of the default camera, also used by imported cameras which don't specify one.
+pub const DEFAULT_ASPECT_RATIO: f32 = 4.0 / 3.0;
+
+#[derive(Copy, Clone)]
+pub enum Projection {
+    Perspective(Perspective3<f32>),
+    Orthographic(Orthographic3<f32>),
+    /// A perspective projection without a far plane.
+    InfinitePerspective { aspect: f32, fovy: f32, znear: f32 },
+}
+
+impl Projection {
+    pub fn to_homogeneous(&self) -> Matrix4<f32> {
+        match self {
+            Projection::Perspective(perspective) => perspective.to_homogeneous(),
+            Projection::Orthographic(orthographic) => orthographic.to_homogeneous(),
+            Projection::InfinitePerspective {
+                aspect,
+                fovy,
+                znear,
+            } => {
+                let focal_length = 1.0 / (fovy / 2.0).tan();
+                Matrix4::new(
+                    focal_length / aspect,
+                    0.0,
+                    0.0,
+                    0.0,
+                    0.0,
+                    focal_length,
+                    0.0,
+                    0.0,
+                    0.0,
+                    0.0,
+                    -1.0,
+                    -2.0 * znear,
+                    0.0,
+                    0.0,
+                    -1.0,
+                    0.0,
+                )
+            }
+        }
+    }
+}
 
 #[derive(Copy, Clone)]
 pub struct Camera {
     view_matrix: IsometryMatrix3<f32>,
-    projection_matrix: Perspective3<f32>,
+    projection_matrix: Projection,
 }
 
 impl Camera {
     pub fn new(view_matrix: IsometryMatrix3<f32>, projection_matrix: Perspective3<f32>) -> Self {
+        Self::from_projection(view_matrix, Projection::Perspective(projection_matrix))
+    }
+
+    pub fn from_projection(view_matrix: IsometryMatrix3<f32>, projection: Projection) -> Self {
         Self {
             view_matrix,
-            projection_matrix,
+            projection_matrix: projection,
         }
     }
 
@@ -51,7 +100,7 @@ impl Camera {
     }
 
     fn default_projection_matrix() -> Perspective3<f32> {
-        let projection_aspect = 4.0 / 3.0;
+        let projection_aspect = DEFAULT_ASPECT_RATIO;
         let projection_fov = 1.047198;
         let projection_near = 0.1;
         let projection_far = 100.0;
@@ -69,7 +118,7 @@ impl Default for Camera {
     fn default() -> Self {
         Self {
             view_matrix: Self::default_view_matrix(),
-            projection_matrix: Self::default_projection_matrix(),
+            projection_matrix: Projection::Perspective(Self::default_projection_matrix()),
         }
     }
 }
@@ -0,0 +1,1519 @@
+//! Texture data as stored in .dds and .ktx2 files, and a CPU decoder for the block compressed
+//! formats, used when the driver can't sample a format or the data has to end up uncompressed.
+
+/// The pixel formats read from .dds and .ktx2 files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureFormat {
+    /// Uncompressed, four bytes per pixel.
+    Rgba8,
+    /// Colour with 1 bit alpha, also known as DXT1.
+    Bc1,
+    /// Colour with 4 bit alpha, also known as DXT3.
+    Bc2,
+    /// Colour with interpolated alpha, also known as DXT5.
+    Bc3,
+    /// One channel, decoded into red.
+    Bc4,
+    /// Two channels, decoded into red and green.
+    Bc5,
+    /// Unsigned half float colour.
+    Bc6hUnsigned,
+    /// Signed half float colour.
+    Bc6hSigned,
+    /// Colour and alpha with more precision than BC3.
+    Bc7,
+}
+
+impl TextureFormat {
+    /// Bytes per 4x4 block, or per pixel for uncompressed formats.
+    pub fn block_size(self) -> usize {
+        match self {
+            TextureFormat::Rgba8 => 4,
+            TextureFormat::Bc1 | TextureFormat::Bc4 => 8,
+            _ => 16,
+        }
+    }
+
+    pub fn is_block_compressed(self) -> bool {
+        self != TextureFormat::Rgba8
+    }
+
+    /// The size in bytes of one image of the given dimensions, `None` if it's too large to
+    /// address.
+    pub fn image_size(self, width: u32, height: u32) -> Option<usize> {
+        let (columns, rows) = if self.is_block_compressed() {
+            (block_count(width), block_count(height))
+        } else {
+            (width as usize, height as usize)
+        };
+        columns.checked_mul(rows)?.checked_mul(self.block_size())
+    }
+}
+
+/// One mip level of a texture file.
+pub struct TextureLevel {
+    pub width: u32,
+    pub height: u32,
+    /// One image for flat textures, six for cubemaps in the order +x, -x, +y, -y, +z, -z.
+    pub faces: Vec<Vec<u8>>,
+}
+
+/// A texture read from a .dds or .ktx2 file.
+pub struct TextureFile {
+    pub format: TextureFormat,
+    /// Whether the colour is stored in srgb.
+    pub srgb: bool,
+    /// The largest level first, each level half the size of the previous one.
+    pub levels: Vec<TextureLevel>,
+}
+
+impl TextureFile {
+    pub fn width(&self) -> u32 {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.levels[0].height
+    }
+
+    pub fn is_cubemap(&self) -> bool {
+        self.levels[0].faces.len() == 6
+    }
+}
+
+// The number of blocks along a side of an image. Rounded up by hand, u32::div_ceil needs rustc
+// 1.73 and the crate still builds with older toolchains.
+#[allow(clippy::manual_div_ceil)]
+fn block_count(size: u32) -> usize {
+    ((size as usize) + 3) / 4
+}
+
+/// The size of mip level `level` of a texture `size` pixels wide or high.
+pub fn level_size(size: u32, level: usize) -> u32 {
+    (size >> level).max(1)
+}
+
+/// The number of levels of a full mip chain, files claiming more are cut down to it.
+pub fn max_level_count(width: u32, height: u32) -> usize {
+    (32 - width.max(height).leading_zeros()) as usize
+}
+
+/// Decodes an image into 8 bit rgba. Half float colours are clamped into [0, 1].
+pub fn decode_rgba8(format: TextureFormat, data: &[u8], width: u32, height: u32) -> Vec<u8> {
+    if format == TextureFormat::Rgba8 {
+        return data.to_vec();
+    }
+
+    let mut pixels = vec![0u8; width as usize * height as usize * 4];
+    for_each_block(format, data, width, height, |x, y, block| {
+        let texel = block_texel(format, block);
+        for i in 0..16 {
+            let (pixel_x, pixel_y) = (x + i % 4, y + i / 4);
+            if pixel_x < width as usize && pixel_y < height as usize {
+                let start = (pixel_y * width as usize + pixel_x) * 4;
+                pixels[start..start + 4].copy_from_slice(&texel.rgba8(i));
+            }
+        }
+    });
+    pixels
+}
+
+/// Decodes an image into float rgba, 8 bit channels are mapped into [0, 1].
+pub fn decode_rgba_f32(format: TextureFormat, data: &[u8], width: u32, height: u32) -> Vec<f32> {
+    if format == TextureFormat::Rgba8 {
+        return data.iter().map(|value| f32::from(*value) / 255.0).collect();
+    }
+
+    let mut pixels = vec![0.0; width as usize * height as usize * 4];
+    for_each_block(format, data, width, height, |x, y, block| {
+        let texel = block_texel(format, block);
+        for i in 0..16 {
+            let (pixel_x, pixel_y) = (x + i % 4, y + i / 4);
+            if pixel_x < width as usize && pixel_y < height as usize {
+                let start = (pixel_y * width as usize + pixel_x) * 4;
+                pixels[start..start + 4].copy_from_slice(&texel.rgba_f32(i));
+            }
+        }
+    });
+    pixels
+}
+
+// Calls `decode` with the top left pixel of every block, missing blocks are left black
+fn for_each_block<D>(format: TextureFormat, data: &[u8], width: u32, height: u32, mut decode: D)
+where
+    D: FnMut(usize, usize, &[u8]),
+{
+    let block_size = format.block_size();
+    let blocks_wide = block_count(width);
+    for (i, block) in data.chunks_exact(block_size).enumerate() {
+        let (x, y) = (i % blocks_wide * 4, i / blocks_wide * 4);
+        if y >= height as usize {
+            break;
+        }
+        decode(x, y, block);
+    }
+}
+
+// The 16 pixels of a decoded block, row by row
+enum Texels {
+    Unorm([[u8; 4]; 16]),
+    Float([[f32; 3]; 16]),
+}
+
+impl Texels {
+    fn rgba8(&self, i: usize) -> [u8; 4] {
+        match self {
+            Texels::Unorm(texels) => texels[i],
+            Texels::Float(texels) => {
+                let [red, green, blue] = texels[i];
+                let to_u8 = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+                [to_u8(red), to_u8(green), to_u8(blue), 255]
+            }
+        }
+    }
+
+    fn rgba_f32(&self, i: usize) -> [f32; 4] {
+        match self {
+            Texels::Unorm(texels) => {
+                let [red, green, blue, alpha] = texels[i];
+                let to_f32 = |value: u8| f32::from(value) / 255.0;
+                [to_f32(red), to_f32(green), to_f32(blue), to_f32(alpha)]
+            }
+            Texels::Float(texels) => {
+                let [red, green, blue] = texels[i];
+                [red, green, blue, 1.0]
+            }
+        }
+    }
+}
+
+fn block_texel(format: TextureFormat, block: &[u8]) -> Texels {
+    match format {
+        TextureFormat::Rgba8 => unreachable!("rgba8 isn't block compressed"),
+        TextureFormat::Bc1 => Texels::Unorm(decode_bc1(block, true)),
+        TextureFormat::Bc2 => {
+            let mut texels = decode_bc1(&block[8..16], false);
+            for (i, texel) in texels.iter_mut().enumerate() {
+                let alpha = (block[i / 2] >> (i % 2 * 4)) & 0xF;
+                texel[3] = alpha * 17;
+            }
+            Texels::Unorm(texels)
+        }
+        TextureFormat::Bc3 => {
+            let mut texels = decode_bc1(&block[8..16], false);
+            let alphas = decode_bc4(&block[0..8]);
+            for (texel, alpha) in texels.iter_mut().zip(alphas.iter()) {
+                texel[3] = *alpha;
+            }
+            Texels::Unorm(texels)
+        }
+        TextureFormat::Bc4 => {
+            let reds = decode_bc4(block);
+            let mut texels = [[0, 0, 0, 255]; 16];
+            for (texel, red) in texels.iter_mut().zip(reds.iter()) {
+                texel[0] = *red;
+            }
+            Texels::Unorm(texels)
+        }
+        TextureFormat::Bc5 => {
+            let reds = decode_bc4(&block[0..8]);
+            let greens = decode_bc4(&block[8..16]);
+            let mut texels = [[0, 0, 0, 255]; 16];
+            for (i, texel) in texels.iter_mut().enumerate() {
+                texel[0] = reds[i];
+                texel[1] = greens[i];
+            }
+            Texels::Unorm(texels)
+        }
+        TextureFormat::Bc6hUnsigned => Texels::Float(decode_bc6h(block, false)),
+        TextureFormat::Bc6hSigned => Texels::Float(decode_bc6h(block, true)),
+        TextureFormat::Bc7 => Texels::Unorm(decode_bc7(block)),
+    }
+}
+
+fn rgb565(colour: u16) -> [u8; 4] {
+    let red = ((colour >> 11) & 0x1F) as u8;
+    let green = ((colour >> 5) & 0x3F) as u8;
+    let blue = (colour & 0x1F) as u8;
+    [
+        (red << 3) | (red >> 2),
+        (green << 2) | (green >> 4),
+        (blue << 3) | (blue >> 2),
+        255,
+    ]
+}
+
+// The colour block shared by BC1, BC2 and BC3. Only BC1 has the three colour mode with a
+// transparent fourth colour.
+fn decode_bc1(block: &[u8], has_alpha_mode: bool) -> [[u8; 4]; 16] {
+    let colour_0 = u16::from_le_bytes([block[0], block[1]]);
+    let colour_1 = u16::from_le_bytes([block[2], block[3]]);
+    let (first, second) = (rgb565(colour_0), rgb565(colour_1));
+
+    let mix = |weight_first: u16, weight_second: u16, divisor: u16| {
+        let mut colour = [0, 0, 0, 255];
+        for channel in 0..3 {
+            colour[channel] = ((u16::from(first[channel]) * weight_first
+                + u16::from(second[channel]) * weight_second)
+                / divisor) as u8;
+        }
+        colour
+    };
+    let palette = if colour_0 > colour_1 || !has_alpha_mode {
+        [first, second, mix(2, 1, 3), mix(1, 2, 3)]
+    } else {
+        [first, second, mix(1, 1, 2), [0, 0, 0, 0]]
+    };
+
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    let mut texels = [[0; 4]; 16];
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[(indices >> (i * 2)) as usize & 0x3];
+    }
+    texels
+}
+
+// A single interpolated channel, also the alpha block of BC3
+fn decode_bc4(block: &[u8]) -> [u8; 16] {
+    let (first, second) = (u32::from(block[0]), u32::from(block[1]));
+    let mut palette = [block[0], block[1], 0, 0, 0, 0, 0, 255];
+    if first > second {
+        for (i, value) in palette.iter_mut().enumerate().skip(2) {
+            let i = i as u32;
+            *value = (((8 - i) * first + (i - 1) * second) / 7) as u8;
+        }
+    } else {
+        for (i, value) in palette.iter_mut().enumerate().take(6).skip(2) {
+            let i = i as u32;
+            *value = (((6 - i) * first + (i - 1) * second) / 5) as u8;
+        }
+    }
+
+    let mut index_bytes = [0u8; 8];
+    index_bytes[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(index_bytes);
+    let mut values = [0; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[(indices >> (i * 3)) as usize & 0x7];
+    }
+    values
+}
+
+// Reads the bits of a 16 byte block from the least significant bit of the first byte on
+struct BitReader {
+    bits: u128,
+    position: u32,
+}
+
+impl BitReader {
+    fn new(block: &[u8]) -> Self {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&block[..16]);
+        Self {
+            bits: u128::from_le_bytes(bytes),
+            position: 0,
+        }
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        let value = (self.bits >> self.position) as u32 & ((1u64 << count) - 1) as u32;
+        self.position += count;
+        value
+    }
+}
+
+const WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn weights(index_bits: u32) -> &'static [u32] {
+    match index_bits {
+        2 => &WEIGHTS_2,
+        3 => &WEIGHTS_3,
+        _ => &WEIGHTS_4,
+    }
+}
+
+// Bit i is set when pixel i belongs to the second subset
+const PARTITIONS_2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80, 0xC800, 0xFFEC, 0xFE80, 0xE800,
+    0xFFE8, 0xFF00, 0xFFF0, 0xF000, 0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE,
+    0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C, 0xAAAA, 0xF0F0, 0x5A5A, 0x33CC,
+    0x3C3C, 0x55AA, 0x9696, 0xA55A, 0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C, 0x9336, 0x9CC6, 0x817E, 0xE718,
+    0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+const PARTITIONS_3: [[u8; 16]; 64] = [
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 1, 2, 2, 2, 2],
+    [0, 0, 0, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 2, 0, 0, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 1, 0, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2],
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2],
+    [0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2, 1, 2, 2, 2],
+    [0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0, 2, 2, 2, 0],
+    [0, 0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2],
+    [0, 1, 1, 1, 0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0],
+    [0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2, 0, 2, 2, 2],
+    [0, 0, 0, 1, 0, 0, 0, 1, 2, 2, 2, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 0, 0, 1, 1, 0, 0, 2, 2, 1, 0, 2, 2, 1, 0],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1, 0, 0, 0, 0],
+    [0, 0, 1, 2, 0, 0, 1, 2, 1, 1, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1, 0, 1, 1, 0],
+    [0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1],
+    [0, 0, 2, 2, 1, 1, 0, 2, 1, 1, 0, 2, 0, 0, 2, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 0, 0, 2, 2, 2, 2, 2],
+    [0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 0, 0, 2, 0, 0, 0, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 2, 2, 2],
+    [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 2, 0, 0, 2, 2, 0, 2, 2, 2],
+    [0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0],
+    [0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0],
+    [0, 1, 2, 0, 2, 0, 1, 2, 1, 2, 0, 1, 0, 1, 2, 0],
+    [0, 0, 1, 1, 2, 2, 0, 0, 1, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0, 1, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 0, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 1, 1],
+    [0, 2, 2, 0, 1, 2, 2, 1, 0, 2, 2, 0, 1, 2, 2, 1],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 0, 1, 0, 1],
+    [0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 2, 2, 2, 0, 1, 1, 1],
+    [0, 0, 0, 2, 1, 1, 1, 2, 0, 0, 0, 2, 1, 1, 1, 2],
+    [0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2],
+    [0, 0, 0, 2, 1, 1, 1, 2, 1, 1, 1, 2, 0, 0, 0, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2],
+    [0, 0, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2],
+    [0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1],
+    [0, 2, 2, 2, 1, 2, 2, 2, 0, 2, 2, 2, 1, 2, 2, 2],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 1, 2, 0, 1, 1, 2, 2, 0, 1, 2, 2, 2, 0],
+];
+
+// The pixel of the second subset whose index is stored with one bit less
+const ANCHORS_2: [usize; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2,
+    2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+// The same for the second and third subsets of three subset partitions
+const ANCHORS_3_SECOND: [usize; 64] = [
+    3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, 3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5,
+    15, 15, 8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, 3, 15, 5, 5, 5, 8, 5, 10, 5,
+    10, 8, 13, 15, 12, 3, 3,
+];
+
+const ANCHORS_3_THIRD: [usize; 64] = [
+    15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, 15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6,
+    10, 15, 15, 10, 8, 15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, 15, 3, 15, 15, 15,
+    15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+];
+
+fn subset(subset_count: usize, partition: usize, pixel: usize) -> usize {
+    match subset_count {
+        1 => 0,
+        2 => (PARTITIONS_2[partition] >> pixel) as usize & 1,
+        _ => PARTITIONS_3[partition][pixel] as usize,
+    }
+}
+
+fn is_anchor(subset_count: usize, partition: usize, pixel: usize) -> bool {
+    pixel == 0
+        || match subset_count {
+            1 => false,
+            2 => ANCHORS_2[partition] == pixel,
+            _ => ANCHORS_3_SECOND[partition] == pixel || ANCHORS_3_THIRD[partition] == pixel,
+        }
+}
+
+fn interpolate(first: u32, second: u32, weight: u32) -> u32 {
+    (first * (64 - weight) + second * weight + 32) >> 6
+}
+
+// How the fields of each BC7 mode are laid out
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    colour_bits: u32,
+    alpha_bits: u32,
+    // One p-bit per endpoint, or one per subset shared by both of its endpoints
+    endpoint_p_bits: bool,
+    shared_p_bits: bool,
+    index_bits: u32,
+    secondary_index_bits: u32,
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode {
+        subsets: 3,
+        partition_bits: 4,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        colour_bits: 4,
+        alpha_bits: 0,
+        endpoint_p_bits: true,
+        shared_p_bits: false,
+        index_bits: 3,
+        secondary_index_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 2,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        colour_bits: 6,
+        alpha_bits: 0,
+        endpoint_p_bits: false,
+        shared_p_bits: true,
+        index_bits: 3,
+        secondary_index_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 3,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        colour_bits: 5,
+        alpha_bits: 0,
+        endpoint_p_bits: false,
+        shared_p_bits: false,
+        index_bits: 2,
+        secondary_index_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 2,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        colour_bits: 7,
+        alpha_bits: 0,
+        endpoint_p_bits: true,
+        shared_p_bits: false,
+        index_bits: 2,
+        secondary_index_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 1,
+        partition_bits: 0,
+        rotation_bits: 2,
+        index_selection_bits: 1,
+        colour_bits: 5,
+        alpha_bits: 6,
+        endpoint_p_bits: false,
+        shared_p_bits: false,
+        index_bits: 2,
+        secondary_index_bits: 3,
+    },
+    Bc7Mode {
+        subsets: 1,
+        partition_bits: 0,
+        rotation_bits: 2,
+        index_selection_bits: 0,
+        colour_bits: 7,
+        alpha_bits: 8,
+        endpoint_p_bits: false,
+        shared_p_bits: false,
+        index_bits: 2,
+        secondary_index_bits: 2,
+    },
+    Bc7Mode {
+        subsets: 1,
+        partition_bits: 0,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        colour_bits: 7,
+        alpha_bits: 7,
+        endpoint_p_bits: true,
+        shared_p_bits: false,
+        index_bits: 4,
+        secondary_index_bits: 0,
+    },
+    Bc7Mode {
+        subsets: 2,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        colour_bits: 5,
+        alpha_bits: 5,
+        endpoint_p_bits: true,
+        shared_p_bits: false,
+        index_bits: 2,
+        secondary_index_bits: 0,
+    },
+];
+
+fn decode_bc7(block: &[u8]) -> [[u8; 4]; 16] {
+    // The mode is the number of zero bits before the first set bit, blocks without one are
+    // invalid and decode to transparent black
+    if block[0] == 0 {
+        return [[0; 4]; 16];
+    }
+    let mode_index = block[0].trailing_zeros() as usize;
+    let mode = &BC7_MODES[mode_index];
+
+    let mut reader = BitReader::new(block);
+    reader.read(mode_index as u32 + 1);
+    let partition = reader.read(mode.partition_bits) as usize;
+    let rotation = reader.read(mode.rotation_bits);
+    let index_selection = reader.read(mode.index_selection_bits);
+
+    // Two endpoints per subset, rgba each
+    let endpoint_count = mode.subsets * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+    for channel in 0..3 {
+        for endpoint in endpoints.iter_mut().take(endpoint_count) {
+            endpoint[channel] = reader.read(mode.colour_bits);
+        }
+    }
+    for endpoint in endpoints.iter_mut().take(endpoint_count) {
+        endpoint[3] = reader.read(mode.alpha_bits);
+    }
+
+    let mut p_bits = [0u32; 6];
+    if mode.endpoint_p_bits {
+        for p_bit in p_bits.iter_mut().take(endpoint_count) {
+            *p_bit = reader.read(1);
+        }
+    } else if mode.shared_p_bits {
+        for subset in 0..mode.subsets {
+            let p_bit = reader.read(1);
+            p_bits[subset * 2] = p_bit;
+            p_bits[subset * 2 + 1] = p_bit;
+        }
+    }
+    let has_p_bits = mode.endpoint_p_bits || mode.shared_p_bits;
+
+    // Expands the endpoints to 8 bits by replicating their top bits
+    for (endpoint, p_bit) in endpoints.iter_mut().zip(p_bits.iter()).take(endpoint_count) {
+        for (channel, value) in endpoint.iter_mut().enumerate() {
+            let mut bits = if channel < 3 {
+                mode.colour_bits
+            } else {
+                mode.alpha_bits
+            };
+            if bits == 0 {
+                *value = 255;
+                continue;
+            }
+            if has_p_bits {
+                *value = (*value << 1) | p_bit;
+                bits += 1;
+            }
+            let expanded = *value << (8 - bits);
+            *value = expanded | (expanded >> bits);
+        }
+    }
+
+    let mut indices = [0u32; 16];
+    for (pixel, index) in indices.iter_mut().enumerate() {
+        let anchor = is_anchor(mode.subsets, partition, pixel);
+        *index = reader.read(mode.index_bits - anchor as u32);
+    }
+    let mut secondary_indices = [0u32; 16];
+    if mode.secondary_index_bits > 0 {
+        for (pixel, index) in secondary_indices.iter_mut().enumerate() {
+            *index = reader.read(mode.secondary_index_bits - (pixel == 0) as u32);
+        }
+    }
+
+    let mut texels = [[0u8; 4]; 16];
+    for (pixel, texel) in texels.iter_mut().enumerate() {
+        let subset = subset(mode.subsets, partition, pixel);
+        let (first, second) = (endpoints[subset * 2], endpoints[subset * 2 + 1]);
+
+        let (colour_index, colour_bits, alpha_index, alpha_bits) = if mode.secondary_index_bits == 0
+        {
+            (
+                indices[pixel],
+                mode.index_bits,
+                indices[pixel],
+                mode.index_bits,
+            )
+        } else if index_selection == 0 {
+            let secondary = (secondary_indices[pixel], mode.secondary_index_bits);
+            (indices[pixel], mode.index_bits, secondary.0, secondary.1)
+        } else {
+            let secondary = (secondary_indices[pixel], mode.secondary_index_bits);
+            (secondary.0, secondary.1, indices[pixel], mode.index_bits)
+        };
+
+        let colour_weight = weights(colour_bits)[colour_index as usize];
+        let alpha_weight = weights(alpha_bits)[alpha_index as usize];
+        for channel in 0..3 {
+            texel[channel] = interpolate(first[channel], second[channel], colour_weight) as u8;
+        }
+        texel[3] = interpolate(first[3], second[3], alpha_weight) as u8;
+
+        match rotation {
+            1 => texel.swap(0, 3),
+            2 => texel.swap(1, 3),
+            3 => texel.swap(2, 3),
+            _ => {}
+        }
+    }
+    texels
+}
+
+// Endpoint components of BC6H, w and x being the endpoints of the first region and y and z those
+// of the second, and the partition
+const RW: usize = 0;
+const GW: usize = 1;
+const BW: usize = 2;
+const RX: usize = 3;
+const GX: usize = 4;
+const BX: usize = 5;
+const RY: usize = 6;
+const GY: usize = 7;
+const BY: usize = 8;
+const RZ: usize = 9;
+const GZ: usize = 10;
+const BZ: usize = 11;
+const D: usize = 12;
+
+// Where the bits of a field are in the header of a mode, in the order they are stored. Each
+// entry reads the bits from `high` down to `low` of a field, lowest bit first.
+type Bc6hField = (usize, u32, u32);
+
+struct Bc6hMode {
+    layout: &'static [Bc6hField],
+    endpoint_bits: u32,
+    delta_bits: [u32; 3],
+    transformed: bool,
+    regions: usize,
+}
+
+const BC6H_MODE_1: [Bc6hField; 20] = [
+    (GY, 4, 4),
+    (BY, 4, 4),
+    (BZ, 4, 4),
+    (RW, 9, 0),
+    (GW, 9, 0),
+    (BW, 9, 0),
+    (RX, 4, 0),
+    (GZ, 4, 4),
+    (GY, 3, 0),
+    (GX, 4, 0),
+    (BZ, 0, 0),
+    (GZ, 3, 0),
+    (BX, 4, 0),
+    (BZ, 1, 1),
+    (BY, 3, 0),
+    (RY, 4, 0),
+    (BZ, 2, 2),
+    (RZ, 4, 0),
+    (BZ, 3, 3),
+    (D, 4, 0),
+];
+
+const BC6H_MODE_2: [Bc6hField; 24] = [
+    (GY, 5, 5),
+    (GZ, 4, 4),
+    (GZ, 5, 5),
+    (RW, 6, 0),
+    (BZ, 0, 0),
+    (BZ, 1, 1),
+    (BY, 4, 4),
+    (GW, 6, 0),
+    (BY, 5, 5),
+    (BZ, 2, 2),
+    (GY, 4, 4),
+    (BW, 6, 0),
+    (BZ, 3, 3),
+    (BZ, 5, 5),
+    (BZ, 4, 4),
+    (RX, 5, 0),
+    (GY, 3, 0),
+    (GX, 5, 0),
+    (GZ, 3, 0),
+    (BX, 5, 0),
+    (BY, 3, 0),
+    (RY, 5, 0),
+    (RZ, 5, 0),
+    (D, 4, 0),
+];
+
+const BC6H_MODE_3: [Bc6hField; 19] = [
+    (RW, 9, 0),
+    (GW, 9, 0),
+    (BW, 9, 0),
+    (RX, 4, 0),
+    (RW, 10, 10),
+    (GY, 3, 0),
+    (GX, 3, 0),
+    (GW, 10, 10),
+    (BZ, 0, 0),
+    (GZ, 3, 0),
+    (BX, 3, 0),
+    (BW, 10, 10),
+    (BZ, 1, 1),
+    (BY, 3, 0),
+    (RY, 4, 0),
+    (BZ, 2, 2),
+    (RZ, 4, 0),
+    (BZ, 3, 3),
+    (D, 4, 0),
+];
+
+const BC6H_MODE_4: [Bc6hField; 21] = [
+    (RW, 9, 0),
+    (GW, 9, 0),
+    (BW, 9, 0),
+    (RX, 3, 0),
+    (RW, 10, 10),
+    (GZ, 4, 4),
+    (GY, 3, 0),
+    (GX, 4, 0),
+    (GW, 10, 10),
+    (GZ, 3, 0),
+    (BX, 3, 0),
+    (BW, 10, 10),
+    (BZ, 1, 1),
+    (BY, 3, 0),
+    (RY, 3, 0),
+    (BZ, 0, 0),
+    (BZ, 2, 2),
+    (RZ, 3, 0),
+    (GY, 4, 4),
+    (BZ, 3, 3),
+    (D, 4, 0),
+];
+
+const BC6H_MODE_5: [Bc6hField; 21] = [
+    (RW, 9, 0),
+    (GW, 9, 0),
+    (BW, 9, 0),
+    (RX, 3, 0),
+    (RW, 10, 10),
+    (BY, 4, 4),
+    (GY, 3, 0),
+    (GX, 3, 0),
+    (GW, 10, 10),
+    (BZ, 0, 0),
+    (GZ, 3, 0),
+    (BX, 4, 0),
+    (BW, 10, 10),
+    (BY, 3, 0),
+    (RY, 3, 0),
+    (BZ, 1, 1),
+    (BZ, 2, 2),
+    (RZ, 3, 0),
+    (BZ, 4, 4),
+    (BZ, 3, 3),
+    (D, 4, 0),
+];
+
+const BC6H_MODE_6: [Bc6hField; 20] = [
+    (RW, 8, 0),
+    (BY, 4, 4),
+    (GW, 8, 0),
+    (GY, 4, 4),
+    (BW, 8, 0),
+    (BZ, 4, 4),
+    (RX, 4, 0),
+    (GZ, 4, 4),
+    (GY, 3, 0),
+    (GX, 4, 0),
+    (BZ, 0, 0),
+    (GZ, 3, 0),
+    (BX, 4, 0),
+    (BZ, 1, 1),
+    (BY, 3, 0),
+    (RY, 4, 0),
+    (BZ, 2, 2),
+    (RZ, 4, 0),
+    (BZ, 3, 3),
+    (D, 4, 0),
+];
+
+const BC6H_MODE_7: [Bc6hField; 20] = [
+    (RW, 7, 0),
+    (GZ, 4, 4),
+    (BY, 4, 4),
+    (GW, 7, 0),
+    (BZ, 2, 2),
+    (GY, 4, 4),
+    (BW, 7, 0),
+    (BZ, 3, 3),
+    (BZ, 4, 4),
+    (RX, 5, 0),
+    (GY, 3, 0),
+    (GX, 4, 0),
+    (BZ, 0, 0),
+    (GZ, 3, 0),
+    (BX, 4, 0),
+    (BZ, 1, 1),
+    (BY, 3, 0),
+    (RY, 5, 0),
+    (RZ, 5, 0),
+    (D, 4, 0),
+];
+
+const BC6H_MODE_8: [Bc6hField; 22] = [
+    (RW, 7, 0),
+    (BZ, 0, 0),
+    (BY, 4, 4),
+    (GW, 7, 0),
+    (GY, 5, 5),
+    (GY, 4, 4),
+    (BW, 7, 0),
+    (GZ, 5, 5),
+    (BZ, 4, 4),
+    (RX, 4, 0),
+    (GZ, 4, 4),
+    (GY, 3, 0),
+    (GX, 5, 0),
+    (GZ, 3, 0),
+    (BX, 4, 0),
+    (BZ, 1, 1),
+    (BY, 3, 0),
+    (RY, 4, 0),
+    (BZ, 2, 2),
+    (RZ, 4, 0),
+    (BZ, 3, 3),
+    (D, 4, 0),
+];
+
+const BC6H_MODE_9: [Bc6hField; 22] = [
+    (RW, 7, 0),
+    (BZ, 1, 1),
+    (BY, 4, 4),
+    (GW, 7, 0),
+    (BY, 5, 5),
+    (GY, 4, 4),
+    (BW, 7, 0),
+    (BZ, 5, 5),
+    (BZ, 4, 4),
+    (RX, 4, 0),
+    (GZ, 4, 4),
+    (GY, 3, 0),
+    (GX, 4, 0),
+    (BZ, 0, 0),
+    (GZ, 3, 0),
+    (BX, 5, 0),
+    (BY, 3, 0),
+    (RY, 4, 0),
+    (BZ, 2, 2),
+    (RZ, 4, 0),
+    (BZ, 3, 3),
+    (D, 4, 0),
+];
+
+const BC6H_MODE_10: [Bc6hField; 24] = [
+    (RW, 5, 0),
+    (GZ, 4, 4),
+    (BZ, 0, 0),
+    (BZ, 1, 1),
+    (BY, 4, 4),
+    (GW, 5, 0),
+    (GY, 5, 5),
+    (BY, 5, 5),
+    (BZ, 2, 2),
+    (GY, 4, 4),
+    (BW, 5, 0),
+    (GZ, 5, 5),
+    (BZ, 3, 3),
+    (BZ, 5, 5),
+    (BZ, 4, 4),
+    (RX, 5, 0),
+    (GY, 3, 0),
+    (GX, 5, 0),
+    (GZ, 3, 0),
+    (BX, 5, 0),
+    (BY, 3, 0),
+    (RY, 5, 0),
+    (RZ, 5, 0),
+    (D, 4, 0),
+];
+
+const BC6H_MODE_11: [Bc6hField; 6] = [
+    (RW, 9, 0),
+    (GW, 9, 0),
+    (BW, 9, 0),
+    (RX, 9, 0),
+    (GX, 9, 0),
+    (BX, 9, 0),
+];
+
+const BC6H_MODE_12: [Bc6hField; 9] = [
+    (RW, 9, 0),
+    (GW, 9, 0),
+    (BW, 9, 0),
+    (RX, 8, 0),
+    (RW, 10, 10),
+    (GX, 8, 0),
+    (GW, 10, 10),
+    (BX, 8, 0),
+    (BW, 10, 10),
+];
+
+// The high bits of the base endpoint are stored in reverse in the last two modes
+const BC6H_MODE_13: [Bc6hField; 12] = [
+    (RW, 9, 0),
+    (GW, 9, 0),
+    (BW, 9, 0),
+    (RX, 7, 0),
+    (RW, 11, 11),
+    (RW, 10, 10),
+    (GX, 7, 0),
+    (GW, 11, 11),
+    (GW, 10, 10),
+    (BX, 7, 0),
+    (BW, 11, 11),
+    (BW, 10, 10),
+];
+
+const BC6H_MODE_14: [Bc6hField; 24] = [
+    (RW, 9, 0),
+    (GW, 9, 0),
+    (BW, 9, 0),
+    (RX, 3, 0),
+    (RW, 15, 15),
+    (RW, 14, 14),
+    (RW, 13, 13),
+    (RW, 12, 12),
+    (RW, 11, 11),
+    (RW, 10, 10),
+    (GX, 3, 0),
+    (GW, 15, 15),
+    (GW, 14, 14),
+    (GW, 13, 13),
+    (GW, 12, 12),
+    (GW, 11, 11),
+    (GW, 10, 10),
+    (BX, 3, 0),
+    (BW, 15, 15),
+    (BW, 14, 14),
+    (BW, 13, 13),
+    (BW, 12, 12),
+    (BW, 11, 11),
+    (BW, 10, 10),
+];
+
+fn bc6h_mode(
+    layout: &'static [Bc6hField],
+    endpoint_bits: u32,
+    delta_bits: [u32; 3],
+    transformed: bool,
+    regions: usize,
+) -> Bc6hMode {
+    Bc6hMode {
+        layout,
+        endpoint_bits,
+        delta_bits,
+        transformed,
+        regions,
+    }
+}
+
+// Indexed by the mode bits, the two bit modes 0 and 1 and the five bit modes after them
+fn bc6h_mode_from_bits(bits: u32) -> Option<Bc6hMode> {
+    Some(match bits {
+        0x00 => bc6h_mode(&BC6H_MODE_1, 10, [5, 5, 5], true, 2),
+        0x01 => bc6h_mode(&BC6H_MODE_2, 7, [6, 6, 6], true, 2),
+        0x02 => bc6h_mode(&BC6H_MODE_3, 11, [5, 4, 4], true, 2),
+        0x06 => bc6h_mode(&BC6H_MODE_4, 11, [4, 5, 4], true, 2),
+        0x0A => bc6h_mode(&BC6H_MODE_5, 11, [4, 4, 5], true, 2),
+        0x0E => bc6h_mode(&BC6H_MODE_6, 9, [5, 5, 5], true, 2),
+        0x12 => bc6h_mode(&BC6H_MODE_7, 8, [6, 5, 5], true, 2),
+        0x16 => bc6h_mode(&BC6H_MODE_8, 8, [5, 6, 5], true, 2),
+        0x1A => bc6h_mode(&BC6H_MODE_9, 8, [5, 5, 6], true, 2),
+        0x1E => bc6h_mode(&BC6H_MODE_10, 6, [6, 6, 6], false, 2),
+        0x03 => bc6h_mode(&BC6H_MODE_11, 10, [10, 10, 10], false, 1),
+        0x07 => bc6h_mode(&BC6H_MODE_12, 11, [9, 9, 9], true, 1),
+        0x0B => bc6h_mode(&BC6H_MODE_13, 12, [8, 8, 8], true, 1),
+        0x0F => bc6h_mode(&BC6H_MODE_14, 16, [4, 4, 4], true, 1),
+        _ => return None,
+    })
+}
+
+fn sign_extend(value: i32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    (value << shift) >> shift
+}
+
+fn unquantize(value: i32, bits: u32, signed: bool) -> i32 {
+    if signed {
+        if bits >= 16 {
+            return value;
+        }
+        let (negative, magnitude) = (value < 0, value.abs());
+        let unquantized = if magnitude == 0 {
+            0
+        } else if magnitude >= (1 << (bits - 1)) - 1 {
+            0x7FFF
+        } else {
+            ((magnitude << 15) + 0x4000) >> (bits - 1)
+        };
+        if negative {
+            -unquantized
+        } else {
+            unquantized
+        }
+    } else if bits >= 15 {
+        value
+    } else if value == 0 {
+        0
+    } else if value == (1 << bits) - 1 {
+        0xFFFF
+    } else {
+        ((value << 16) + 0x8000) >> bits
+    }
+}
+
+// Scales an interpolated value into the bits of a half float
+fn finish_unquantize(value: i32, signed: bool) -> u16 {
+    if signed {
+        if value < 0 {
+            0x8000 | (((-value) * 31) >> 5) as u16
+        } else {
+            ((value * 31) >> 5) as u16
+        }
+    } else {
+        ((value * 31) >> 6) as u16
+    }
+}
+
+fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = i32::from((half >> 10) & 0x1F);
+    let mantissa = f32::from(half & 0x3FF);
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 => {
+            if mantissa == 0.0 {
+                f32::INFINITY
+            } else {
+                f32::NAN
+            }
+        }
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+fn decode_bc6h(block: &[u8], signed: bool) -> [[f32; 3]; 16] {
+    let mut reader = BitReader::new(block);
+    let mut mode_bits = reader.read(2);
+    if mode_bits > 1 {
+        mode_bits |= reader.read(3) << 2;
+    }
+    // Reserved modes decode to black
+    let mode = match bc6h_mode_from_bits(mode_bits) {
+        Some(mode) => mode,
+        None => return [[0.0; 3]; 16],
+    };
+
+    let mut fields = [0i32; 13];
+    for (field, high, low) in mode.layout.iter() {
+        let value = reader.read(high - low + 1) as i32;
+        fields[*field] |= value << low;
+    }
+    let partition = fields[D] as usize;
+
+    let endpoint_count = mode.regions * 2;
+    let mut endpoints = [[0i32; 3]; 4];
+    for (i, endpoint) in endpoints.iter_mut().enumerate().take(endpoint_count) {
+        endpoint.copy_from_slice(&fields[i * 3..i * 3 + 3]);
+    }
+
+    if signed {
+        for value in endpoints[0].iter_mut() {
+            *value = sign_extend(*value, mode.endpoint_bits);
+        }
+    }
+    let base = endpoints[0];
+    let mask = (1 << mode.endpoint_bits) - 1;
+    for endpoint in endpoints.iter_mut().take(endpoint_count).skip(1) {
+        for channel in 0..3 {
+            if mode.transformed || signed {
+                endpoint[channel] = sign_extend(endpoint[channel], mode.delta_bits[channel]);
+            }
+            // The other endpoints are stored as offsets from the first one
+            if mode.transformed {
+                endpoint[channel] = (base[channel] + endpoint[channel]) & mask;
+                if signed {
+                    endpoint[channel] = sign_extend(endpoint[channel], mode.endpoint_bits);
+                }
+            }
+        }
+    }
+
+    let index_bits = if mode.regions == 1 { 4 } else { 3 };
+    let mut texels = [[0.0; 3]; 16];
+    for (pixel, texel) in texels.iter_mut().enumerate() {
+        let anchor = is_anchor(mode.regions, partition, pixel);
+        let index = reader.read(index_bits - anchor as u32) as usize;
+        let region = subset(mode.regions, partition, pixel);
+        let weight = weights(index_bits)[index] as i32;
+        for channel in 0..3 {
+            let first = unquantize(endpoints[region * 2][channel], mode.endpoint_bits, signed);
+            let second = unquantize(
+                endpoints[region * 2 + 1][channel],
+                mode.endpoint_bits,
+                signed,
+            );
+            let value = (first * (64 - weight) + second * weight + 32) >> 6;
+            texel[channel] = half_to_f32(finish_unquantize(value, signed));
+        }
+    }
+    texels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Packs fields into a 16 byte block from the least significant bit of the first byte on,
+    // the order `BitReader` reads them in
+    struct BitWriter {
+        bits: u128,
+        position: u32,
+    }
+
+    impl BitWriter {
+        fn new() -> Self {
+            Self {
+                bits: 0,
+                position: 0,
+            }
+        }
+
+        fn write(&mut self, value: u32, count: u32) {
+            self.bits |= (u128::from(value) & ((1 << count) - 1)) << self.position;
+            self.position += count;
+        }
+
+        fn block(&self) -> [u8; 16] {
+            assert_eq!(self.position, 128, "block isn't 128 bits");
+            self.bits.to_le_bytes()
+        }
+    }
+
+    fn pixel(pixels: &[u8], i: usize) -> &[u8] {
+        &pixels[i * 4..i * 4 + 4]
+    }
+
+    fn pixel_f32(pixels: &[f32], i: usize) -> &[f32] {
+        &pixels[i * 4..i * 4 + 4]
+    }
+
+    // Red and blue endpoints, each row of pixels using the indices 0, 1, 2 and 3
+    const BC1_RED_BLUE: [u8; 8] = [0x00, 0xF8, 0x1F, 0x00, 0xE4, 0xE4, 0xE4, 0xE4];
+
+    #[test]
+    fn image_sizes_round_up_to_whole_blocks() {
+        assert_eq!(TextureFormat::Bc1.image_size(5, 5), Some(32));
+        assert_eq!(TextureFormat::Bc7.image_size(1, 1), Some(16));
+        assert_eq!(TextureFormat::Rgba8.image_size(3, 2), Some(24));
+    }
+
+    #[test]
+    fn image_sizes_which_overflow_are_none() {
+        assert_eq!(TextureFormat::Rgba8.image_size(u32::MAX, u32::MAX), None);
+        assert_eq!(TextureFormat::Bc7.image_size(u32::MAX, u32::MAX), None);
+    }
+
+    #[test]
+    fn level_counts_and_sizes() {
+        assert_eq!(max_level_count(1, 1), 1);
+        assert_eq!(max_level_count(8, 8), 4);
+        assert_eq!(max_level_count(5, 3), 3);
+        assert_eq!(level_size(8, 2), 2);
+        assert_eq!(level_size(8, 5), 1);
+    }
+
+    #[test]
+    fn bc1_four_colour_block() {
+        let pixels = decode_rgba8(TextureFormat::Bc1, &BC1_RED_BLUE, 4, 4);
+        assert_eq!(pixel(&pixels, 0), &[255, 0, 0, 255]);
+        assert_eq!(pixel(&pixels, 1), &[0, 0, 255, 255]);
+        assert_eq!(pixel(&pixels, 2), &[170, 0, 85, 255]);
+        assert_eq!(pixel(&pixels, 3), &[85, 0, 170, 255]);
+    }
+
+    #[test]
+    fn bc1_three_colour_block_has_transparent_black() {
+        // The first endpoint isn't greater than the second
+        let block = [0x1F, 0x00, 0x00, 0xF8, 0xE4, 0xE4, 0xE4, 0xE4];
+        let pixels = decode_rgba8(TextureFormat::Bc1, &block, 4, 4);
+        assert_eq!(pixel(&pixels, 2), &[127, 0, 127, 255]);
+        assert_eq!(pixel(&pixels, 3), &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn partial_blocks_are_cropped() {
+        let pixels = decode_rgba8(TextureFormat::Bc1, &BC1_RED_BLUE, 2, 2);
+        assert_eq!(
+            pixels,
+            vec![255, 0, 0, 255, 0, 0, 255, 255, 255, 0, 0, 255, 0, 0, 255, 255]
+        );
+    }
+
+    #[test]
+    fn missing_blocks_are_black() {
+        let pixels = decode_rgba8(TextureFormat::Bc1, &BC1_RED_BLUE, 8, 4);
+        assert_eq!(pixel(&pixels, 0), &[255, 0, 0, 255]);
+        assert_eq!(pixel(&pixels, 4), &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn bc2_has_explicit_alpha_and_always_four_colours() {
+        let mut block = [0u8; 16];
+        block[0] = 0xF0;
+        // Blue then red, which would be the three colour mode in BC1
+        block[8..16].copy_from_slice(&[0x1F, 0x00, 0x00, 0xF8, 0xE4, 0xE4, 0xE4, 0xE4]);
+        let pixels = decode_rgba8(TextureFormat::Bc2, &block, 4, 4);
+        assert_eq!(pixel(&pixels, 0), &[0, 0, 255, 0]);
+        assert_eq!(pixel(&pixels, 1), &[255, 0, 0, 255]);
+        assert_eq!(pixel(&pixels, 3), &[170, 0, 85, 0]);
+    }
+
+    #[test]
+    fn bc3_interpolates_alpha() {
+        let mut block = [0u8; 16];
+        // Indices 0, 1 and 2 for the first three pixels
+        block[0..8].copy_from_slice(&[255, 0, 0b1000_1000, 0, 0, 0, 0, 0]);
+        block[8..16].copy_from_slice(&BC1_RED_BLUE);
+        let pixels = decode_rgba8(TextureFormat::Bc3, &block, 4, 4);
+        assert_eq!(pixel(&pixels, 0), &[255, 0, 0, 255]);
+        assert_eq!(pixel(&pixels, 1), &[0, 0, 255, 0]);
+        assert_eq!(pixel(&pixels, 2), &[170, 0, 85, 218]);
+    }
+
+    #[test]
+    fn bc4_eight_value_block() {
+        // Index 2 for the first pixel, 1 for the rest
+        let mut bits: u64 = 200 | 100 << 8;
+        for i in 0..16 {
+            bits |= (if i == 0 { 2 } else { 1 }) << (16 + i * 3);
+        }
+        let pixels = decode_rgba8(TextureFormat::Bc4, &bits.to_le_bytes(), 4, 4);
+        assert_eq!(pixel(&pixels, 0), &[185, 0, 0, 255]);
+        assert_eq!(pixel(&pixels, 1), &[100, 0, 0, 255]);
+    }
+
+    #[test]
+    fn bc4_six_value_block_has_black_and_white() {
+        // Indices 2, 6 and 7 for the first three pixels
+        let bits: u64 = 50 | 150 << 8 | 2 << 16 | 6 << 19 | 7 << 22;
+        let pixels = decode_rgba8(TextureFormat::Bc4, &bits.to_le_bytes(), 4, 4);
+        assert_eq!(pixel(&pixels, 0), &[70, 0, 0, 255]);
+        assert_eq!(pixel(&pixels, 1), &[0, 0, 0, 255]);
+        assert_eq!(pixel(&pixels, 2), &[255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn bc5_decodes_red_and_green() {
+        let mut block = [0u8; 16];
+        block[0..2].copy_from_slice(&[200, 100]);
+        block[8..10].copy_from_slice(&[10, 20]);
+        let pixels = decode_rgba8(TextureFormat::Bc5, &block, 4, 4);
+        assert_eq!(pixel(&pixels, 0), &[200, 10, 0, 255]);
+    }
+
+    #[test]
+    fn unorm_blocks_decode_to_float() {
+        let pixels = decode_rgba_f32(TextureFormat::Bc1, &BC1_RED_BLUE, 4, 4);
+        assert_eq!(pixel_f32(&pixels, 0), &[1.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn bc7_mode_6_endpoints_with_p_bits() {
+        let mut writer = BitWriter::new();
+        writer.write(1 << 6, 7);
+        let (first, second) = ([10, 20, 30, 127], [100, 110, 120, 0]);
+        for channel in 0..4 {
+            writer.write(first[channel], 7);
+            writer.write(second[channel], 7);
+        }
+        writer.write(1, 1);
+        writer.write(0, 1);
+        // The anchor index drops its top bit
+        writer.write(0, 3);
+        for _ in 1..16 {
+            writer.write(15, 4);
+        }
+
+        let pixels = decode_rgba8(TextureFormat::Bc7, &writer.block(), 4, 4);
+        assert_eq!(pixel(&pixels, 0), &[21, 41, 61, 255]);
+        assert_eq!(pixel(&pixels, 1), &[200, 220, 240, 0]);
+    }
+
+    #[test]
+    fn bc7_mode_5_rotation_swaps_alpha_into_red() {
+        let mut writer = BitWriter::new();
+        writer.write(1 << 5, 6);
+        // Rotation 1 swaps red and alpha
+        writer.write(1, 2);
+        for value in [127, 0, 0].iter() {
+            writer.write(*value, 7);
+            writer.write(*value, 7);
+        }
+        writer.write(0, 8);
+        writer.write(0, 8);
+        writer.write(0, 31);
+        writer.write(0, 31);
+
+        let pixels = decode_rgba8(TextureFormat::Bc7, &writer.block(), 4, 4);
+        assert_eq!(pixel(&pixels, 0), &[0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn bc7_mode_1_partitions_pixels_into_subsets() {
+        let mut writer = BitWriter::new();
+        writer.write(0b10, 2);
+        // Partition 0 puts the two right columns into the second subset
+        writer.write(0, 6);
+        // Red for the first subset, green for the second
+        let endpoints = [[63, 63, 0, 0], [0, 0, 63, 63], [0, 0, 0, 0]];
+        for channel in endpoints.iter() {
+            for value in channel.iter() {
+                writer.write(*value, 6);
+            }
+        }
+        writer.write(1, 1);
+        writer.write(1, 1);
+        writer.write(0, 46);
+
+        let pixels = decode_rgba8(TextureFormat::Bc7, &writer.block(), 4, 4);
+        assert_eq!(pixel(&pixels, 0), &[255, 2, 2, 255]);
+        assert_eq!(pixel(&pixels, 2), &[2, 255, 2, 255]);
+        assert_eq!(pixel(&pixels, 15), &[2, 255, 2, 255]);
+    }
+
+    #[test]
+    fn bc7_block_without_a_mode_is_transparent_black() {
+        let pixels = decode_rgba8(TextureFormat::Bc7, &[0; 16], 4, 4);
+        assert!(pixels.iter().all(|value| *value == 0));
+    }
+
+    // 10 bit endpoints which unquantize to the halfs 0x3C00 and 0x3B65
+    const BC6H_ONE: u32 = 495;
+    const BC6H_BELOW_ONE: u32 = 490;
+    const BELOW_ONE: f32 = 0.924_316_4;
+
+    #[test]
+    fn bc6h_mode_11_interpolates_endpoints() {
+        let mut writer = BitWriter::new();
+        writer.write(0x03, 5);
+        for _ in 0..3 {
+            writer.write(0, 10);
+        }
+        for _ in 0..3 {
+            writer.write(BC6H_ONE, 10);
+        }
+        writer.write(0, 3);
+        for _ in 1..16 {
+            writer.write(15, 4);
+        }
+
+        let pixels = decode_rgba_f32(TextureFormat::Bc6hUnsigned, &writer.block(), 4, 4);
+        assert_eq!(pixel_f32(&pixels, 0), &[0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(pixel_f32(&pixels, 1), &[1.0, 1.0, 1.0, 1.0]);
+        let pixels = decode_rgba8(TextureFormat::Bc6hUnsigned, &writer.block(), 4, 4);
+        assert_eq!(pixel(&pixels, 1), &[255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn bc6h_maximum_endpoint_is_the_largest_half() {
+        let mut writer = BitWriter::new();
+        writer.write(0x03, 5);
+        for _ in 0..6 {
+            writer.write(1023, 10);
+        }
+        writer.write(0, 63);
+
+        let pixels = decode_rgba_f32(TextureFormat::Bc6hUnsigned, &writer.block(), 4, 4);
+        assert_eq!(pixel_f32(&pixels, 0), &[65504.0, 65504.0, 65504.0, 1.0]);
+    }
+
+    #[test]
+    fn bc6h_signed_endpoints_are_sign_extended() {
+        let mut writer = BitWriter::new();
+        writer.write(0x03, 5);
+        // -512, the smallest 10 bit value
+        for _ in 0..3 {
+            writer.write(0x200, 10);
+        }
+        for _ in 0..3 {
+            writer.write(0, 10);
+        }
+        writer.write(0, 3);
+        for _ in 1..16 {
+            writer.write(15, 4);
+        }
+
+        let pixels = decode_rgba_f32(TextureFormat::Bc6hSigned, &writer.block(), 4, 4);
+        assert_eq!(pixel_f32(&pixels, 0), &[-65504.0, -65504.0, -65504.0, 1.0]);
+        assert_eq!(pixel_f32(&pixels, 1), &[0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn bc6h_mode_1_endpoints_are_deltas_from_the_first() {
+        let mut writer = BitWriter::new();
+        writer.write(0, 2);
+        // gy4, by4, bz4
+        writer.write(0, 3);
+        for _ in 0..3 {
+            writer.write(BC6H_BELOW_ONE, 10);
+        }
+        // The second endpoint is 5 above the first, the other deltas are zero apart from ry
+        let rx = BC6H_ONE - BC6H_BELOW_ONE;
+        writer.write(rx, 5);
+        // gz4, gy, gx, bz0, gz
+        writer.write(0, 5);
+        writer.write(rx, 5);
+        writer.write(0, 5);
+        // bx, bz1, by
+        writer.write(rx, 5);
+        writer.write(0, 5);
+        // ry of -1, bz2, rz, bz3 and partition 0
+        writer.write(0x1F, 5);
+        writer.write(0, 1);
+        writer.write(0, 5);
+        writer.write(0, 1);
+        writer.write(0, 5);
+        // The highest index everywhere, the anchors of both subsets drop their top bit
+        for i in 0..16 {
+            if i == 0 || i == 15 {
+                writer.write(3, 2);
+            } else {
+                writer.write(7, 3);
+            }
+        }
+
+        let pixels = decode_rgba_f32(TextureFormat::Bc6hUnsigned, &writer.block(), 4, 4);
+        assert_eq!(pixel_f32(&pixels, 1), &[1.0, 1.0, 1.0, 1.0]);
+        // Partition 0 puts pixel 2 into the second subset, which ends on the unchanged rz
+        assert_eq!(
+            pixel_f32(&pixels, 2),
+            &[BELOW_ONE, BELOW_ONE, BELOW_ONE, 1.0]
+        );
+    }
+}
@@ -0,0 +1,380 @@
+//! Parsing of .dds files into plain texture data, uploaded by `import::upload_texture_file` and
+//! `import::upload_srgb_texture_file`.
+
+use crate::compressed::{self, TextureFile, TextureFormat, TextureLevel};
+use std::fmt;
+
+/// A .dds file which couldn't be parsed.
+#[derive(Debug)]
+pub struct ParseError {
+    pub reason: String,
+}
+
+impl ParseError {
+    fn new(reason: &str) -> Self {
+        Self {
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub type Result<T> = std::result::Result<T, ParseError>;
+
+const MAGIC: &[u8; 4] = b"DDS ";
+// The magic number and the 124 byte header
+const HEADER_SIZE: usize = 128;
+// The extra header of files whose pixel format is "DX10"
+const DX10_HEADER_SIZE: usize = 20;
+
+const PIXEL_FORMAT_ALPHA_PIXELS: u32 = 0x1;
+const PIXEL_FORMAT_FOUR_CC: u32 = 0x4;
+const PIXEL_FORMAT_RGB: u32 = 0x40;
+const CAPS2_CUBEMAP: u32 = 0x200;
+const CAPS2_VOLUME: u32 = 0x20_0000;
+const DX10_MISC_TEXTURE_CUBE: u32 = 0x4;
+const DX10_DIMENSION_TEXTURE_3D: u32 = 4;
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+// Rgba8 data in the file is either stored in order or with red and blue swapped
+#[derive(Clone, Copy, PartialEq)]
+enum ChannelOrder {
+    Rgba,
+    Bgra,
+}
+
+struct PixelFormat {
+    format: TextureFormat,
+    srgb: bool,
+    order: ChannelOrder,
+    // Uncompressed files without an alpha channel are made opaque
+    opaque: bool,
+}
+
+impl PixelFormat {
+    fn new(format: TextureFormat, srgb: bool) -> Self {
+        Self {
+            format,
+            srgb,
+            order: ChannelOrder::Rgba,
+            opaque: false,
+        }
+    }
+}
+
+fn legacy_pixel_format(bytes: &[u8]) -> Result<PixelFormat> {
+    let flags = read_u32(bytes, 80);
+    if flags & PIXEL_FORMAT_FOUR_CC != 0 {
+        let format = match &bytes[84..88] {
+            b"DXT1" => TextureFormat::Bc1,
+            b"DXT2" | b"DXT3" => TextureFormat::Bc2,
+            b"DXT4" | b"DXT5" => TextureFormat::Bc3,
+            b"ATI1" | b"BC4U" => TextureFormat::Bc4,
+            b"ATI2" | b"BC5U" => TextureFormat::Bc5,
+            _ => return Err(ParseError::new("unsupported four character code")),
+        };
+        return Ok(PixelFormat::new(format, false));
+    }
+
+    let bit_count = read_u32(bytes, 88);
+    let masks = (
+        read_u32(bytes, 92),
+        read_u32(bytes, 96),
+        read_u32(bytes, 100),
+    );
+    if flags & PIXEL_FORMAT_RGB == 0 || bit_count != 32 {
+        return Err(ParseError::new(
+            "only 32 bit rgb pixel formats are supported",
+        ));
+    }
+    let order = match masks {
+        (0xFF, 0xFF00, 0xFF_0000) => ChannelOrder::Rgba,
+        (0xFF_0000, 0xFF00, 0xFF) => ChannelOrder::Bgra,
+        _ => return Err(ParseError::new("unsupported channel masks")),
+    };
+    Ok(PixelFormat {
+        format: TextureFormat::Rgba8,
+        srgb: false,
+        order,
+        opaque: flags & PIXEL_FORMAT_ALPHA_PIXELS == 0 || read_u32(bytes, 104) == 0,
+    })
+}
+
+fn dxgi_pixel_format(dxgi_format: u32) -> Result<PixelFormat> {
+    let (format, srgb) = match dxgi_format {
+        27 | 28 => (TextureFormat::Rgba8, false),
+        29 => (TextureFormat::Rgba8, true),
+        87 | 90 => {
+            return Ok(PixelFormat {
+                order: ChannelOrder::Bgra,
+                ..PixelFormat::new(TextureFormat::Rgba8, false)
+            })
+        }
+        91 => {
+            return Ok(PixelFormat {
+                order: ChannelOrder::Bgra,
+                ..PixelFormat::new(TextureFormat::Rgba8, true)
+            })
+        }
+        70 | 71 => (TextureFormat::Bc1, false),
+        72 => (TextureFormat::Bc1, true),
+        73 | 74 => (TextureFormat::Bc2, false),
+        75 => (TextureFormat::Bc2, true),
+        76 | 77 => (TextureFormat::Bc3, false),
+        78 => (TextureFormat::Bc3, true),
+        79 | 80 => (TextureFormat::Bc4, false),
+        82 | 83 => (TextureFormat::Bc5, false),
+        94 | 95 => (TextureFormat::Bc6hUnsigned, false),
+        96 => (TextureFormat::Bc6hSigned, false),
+        97 | 98 => (TextureFormat::Bc7, false),
+        99 => (TextureFormat::Bc7, true),
+        81 | 84 => return Err(ParseError::new("signed BC4 and BC5 aren't supported")),
+        _ => return Err(ParseError::new("unsupported dxgi format")),
+    };
+    Ok(PixelFormat::new(format, srgb))
+}
+
+/// Parses the first texture of a .dds file, with all of its mip levels and cubemap faces.
+pub fn parse_dds(bytes: &[u8]) -> Result<TextureFile> {
+    if bytes.len() < HEADER_SIZE || &bytes[0..4] != MAGIC || read_u32(bytes, 4) != 124 {
+        return Err(ParseError::new("missing dds header"));
+    }
+
+    let height = read_u32(bytes, 12);
+    let width = read_u32(bytes, 16);
+    let caps2 = read_u32(bytes, 112);
+    if width == 0 || height == 0 {
+        return Err(ParseError::new("texture has no pixels"));
+    }
+    let level_count =
+        (read_u32(bytes, 28).max(1) as usize).min(compressed::max_level_count(width, height));
+    if caps2 & CAPS2_VOLUME != 0 {
+        return Err(ParseError::new("volume textures aren't supported"));
+    }
+
+    let (pixel_format, cubemap, data_start) = if &bytes[84..88] == b"DX10" {
+        if bytes.len() < HEADER_SIZE + DX10_HEADER_SIZE {
+            return Err(ParseError::new("missing dx10 header"));
+        }
+        if read_u32(bytes, 132) == DX10_DIMENSION_TEXTURE_3D {
+            return Err(ParseError::new("volume textures aren't supported"));
+        }
+        let cubemap = read_u32(bytes, 136) & DX10_MISC_TEXTURE_CUBE != 0;
+        let pixel_format = dxgi_pixel_format(read_u32(bytes, 128))?;
+        (pixel_format, cubemap, HEADER_SIZE + DX10_HEADER_SIZE)
+    } else {
+        let cubemap = caps2 & CAPS2_CUBEMAP != 0;
+        (legacy_pixel_format(bytes)?, cubemap, HEADER_SIZE)
+    };
+
+    if cubemap && width != height {
+        return Err(ParseError::new("cubemap faces aren't square"));
+    }
+
+    // Faces are stored one after the other, each with its whole mip chain
+    let face_count = if cubemap { 6 } else { 1 };
+    let mut levels: Vec<TextureLevel> = (0..level_count)
+        .map(|level| TextureLevel {
+            width: compressed::level_size(width, level),
+            height: compressed::level_size(height, level),
+            faces: Vec::with_capacity(face_count),
+        })
+        .collect();
+    let mut offset = data_start;
+    for _ in 0..face_count {
+        for level in levels.iter_mut() {
+            let size = pixel_format
+                .format
+                .image_size(level.width, level.height)
+                .ok_or_else(|| ParseError::new("texture is too large"))?;
+            let data = bytes
+                .get(offset..offset.saturating_add(size))
+                .ok_or_else(|| ParseError::new("file is shorter than its mip levels"))?;
+            level.faces.push(to_rgba(data, &pixel_format));
+            offset += size;
+        }
+    }
+
+    Ok(TextureFile {
+        format: pixel_format.format,
+        srgb: pixel_format.srgb,
+        levels,
+    })
+}
+
+fn to_rgba(data: &[u8], pixel_format: &PixelFormat) -> Vec<u8> {
+    let mut data = data.to_vec();
+    if pixel_format.format == TextureFormat::Rgba8 {
+        for pixel in data.chunks_exact_mut(4) {
+            if pixel_format.order == ChannelOrder::Bgra {
+                pixel.swap(0, 2);
+            }
+            if pixel_format.opaque {
+                pixel[3] = 255;
+            }
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn header(width: u32, height: u32, level_count: u32) -> Vec<u8> {
+        let mut bytes = vec![0; HEADER_SIZE];
+        bytes[0..4].copy_from_slice(MAGIC);
+        write_u32(&mut bytes, 4, 124);
+        write_u32(&mut bytes, 12, height);
+        write_u32(&mut bytes, 16, width);
+        write_u32(&mut bytes, 28, level_count);
+        bytes
+    }
+
+    fn four_cc_header(width: u32, height: u32, level_count: u32, four_cc: &[u8; 4]) -> Vec<u8> {
+        let mut bytes = header(width, height, level_count);
+        write_u32(&mut bytes, 80, PIXEL_FORMAT_FOUR_CC);
+        bytes[84..88].copy_from_slice(four_cc);
+        bytes
+    }
+
+    fn bgra_header(width: u32, height: u32, alpha: bool) -> Vec<u8> {
+        let mut bytes = header(width, height, 1);
+        let alpha_flag = if alpha { PIXEL_FORMAT_ALPHA_PIXELS } else { 0 };
+        write_u32(&mut bytes, 80, PIXEL_FORMAT_RGB | alpha_flag);
+        write_u32(&mut bytes, 88, 32);
+        write_u32(&mut bytes, 92, 0xFF_0000);
+        write_u32(&mut bytes, 96, 0xFF00);
+        write_u32(&mut bytes, 100, 0xFF);
+        write_u32(&mut bytes, 104, if alpha { 0xFF00_0000 } else { 0 });
+        bytes
+    }
+
+    fn dx10_header(width: u32, height: u32, dxgi_format: u32) -> Vec<u8> {
+        let mut bytes = four_cc_header(width, height, 1, b"DX10");
+        bytes.resize(HEADER_SIZE + DX10_HEADER_SIZE, 0);
+        write_u32(&mut bytes, 128, dxgi_format);
+        write_u32(&mut bytes, 132, 3);
+        bytes
+    }
+
+    #[test]
+    fn parses_every_mip_level() {
+        let mut bytes = four_cc_header(8, 8, 4, b"DXT1");
+        // 8x8, 4x4, 2x2 and 1x1, each level filled with its index
+        for (level, size) in [32, 8, 8, 8].iter().enumerate() {
+            bytes.resize(bytes.len() + size, level as u8);
+        }
+
+        let texture = parse_dds(&bytes).unwrap();
+        assert_eq!(texture.format, TextureFormat::Bc1);
+        assert!(!texture.srgb);
+        assert!(!texture.is_cubemap());
+        assert_eq!(texture.levels.len(), 4);
+        assert_eq!((texture.levels[1].width, texture.levels[1].height), (4, 4));
+        assert_eq!(texture.levels[0].faces[0], vec![0; 32]);
+        assert_eq!(texture.levels[3].faces[0], vec![3; 8]);
+    }
+
+    #[test]
+    fn cubemap_faces_are_stored_with_their_mip_chains() {
+        let mut bytes = four_cc_header(4, 4, 2, b"DXT1");
+        write_u32(&mut bytes, 112, CAPS2_CUBEMAP);
+        for face in 0..6 {
+            bytes.resize(bytes.len() + 8, face * 2);
+            bytes.resize(bytes.len() + 8, face * 2 + 1);
+        }
+
+        let texture = parse_dds(&bytes).unwrap();
+        assert!(texture.is_cubemap());
+        assert_eq!(texture.levels[0].faces[3], vec![6; 8]);
+        assert_eq!(texture.levels[1].faces[5], vec![11; 8]);
+    }
+
+    #[test]
+    fn level_count_is_limited_to_the_mip_chain() {
+        let mut bytes = four_cc_header(8, 8, 20, b"DXT1");
+        bytes.resize(HEADER_SIZE + 56, 0);
+        assert_eq!(parse_dds(&bytes).unwrap().levels.len(), 4);
+
+        let mut bytes = four_cc_header(1, 1, 0, b"DXT1");
+        bytes.resize(HEADER_SIZE + 8, 0);
+        assert_eq!(parse_dds(&bytes).unwrap().levels.len(), 1);
+    }
+
+    #[test]
+    fn truncated_mip_levels_are_an_error() {
+        let mut bytes = four_cc_header(8, 8, 4, b"DXT1");
+        bytes.resize(HEADER_SIZE + 55, 0);
+        assert!(parse_dds(&bytes).is_err());
+    }
+
+    #[test]
+    fn too_large_textures_are_an_error() {
+        let bytes = bgra_header(u32::MAX, u32::MAX, true);
+        assert!(parse_dds(&bytes).is_err());
+    }
+
+    #[test]
+    fn dx10_formats() {
+        let mut bytes = dx10_header(4, 4, 99);
+        bytes.resize(HEADER_SIZE + DX10_HEADER_SIZE + 16, 0);
+
+        let texture = parse_dds(&bytes).unwrap();
+        assert_eq!(texture.format, TextureFormat::Bc7);
+        assert!(texture.srgb);
+    }
+
+    #[test]
+    fn bgra_without_alpha_is_swizzled_and_opaque() {
+        let mut bytes = bgra_header(1, 1, false);
+        bytes.extend(&[1, 2, 3, 4]);
+
+        let texture = parse_dds(&bytes).unwrap();
+        assert_eq!(texture.format, TextureFormat::Rgba8);
+        assert_eq!(texture.levels[0].faces[0], vec![3, 2, 1, 255]);
+    }
+
+    #[test]
+    fn unsupported_files_are_an_error() {
+        assert!(parse_dds(b"DDS ").is_err());
+
+        let mut bytes = four_cc_header(4, 4, 1, b"DXT1");
+        bytes[0] = b'X';
+        bytes.resize(HEADER_SIZE + 8, 0);
+        assert!(parse_dds(&bytes).is_err());
+
+        let mut bytes = four_cc_header(8, 4, 1, b"DXT1");
+        write_u32(&mut bytes, 112, CAPS2_CUBEMAP);
+        bytes.resize(HEADER_SIZE + 6 * 16, 0);
+        assert!(parse_dds(&bytes).is_err());
+
+        let mut bytes = four_cc_header(4, 4, 1, b"DXT1");
+        write_u32(&mut bytes, 112, CAPS2_VOLUME);
+        bytes.resize(HEADER_SIZE + 8, 0);
+        assert!(parse_dds(&bytes).is_err());
+
+        let mut bytes = dx10_header(4, 4, 81);
+        bytes.resize(HEADER_SIZE + DX10_HEADER_SIZE + 8, 0);
+        assert!(parse_dds(&bytes).is_err());
+    }
+}
@@ -0,0 +1,785 @@
+use crate::import::{
+    NEUTRAL_DIFFUSE_COLOUR, NEUTRAL_EMISSIVE_COLOUR, NEUTRAL_METALLIC_ROUGHNESS_COLOUR,
+    NEUTRAL_NORMAL_COLOUR, NEUTRAL_OCCLUSION_COLOUR,
+};
+use crate::material::{AlphaMode, Material, TextureSampler};
+use crate::mesh::Mesh;
+use crate::model::{Model, ModelHandle};
+use crate::primitive::Primitive;
+use crate::renderer::RendererState;
+use crate::vertex::Vertex;
+use glium::index::PrimitiveType;
+use glium::texture::RawImage2d;
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerWrapFunction};
+use gltf::binary::{Glb, Header};
+use gltf::json;
+use gltf::json::accessor::{ComponentType, GenericComponentType, Type};
+use gltf::json::buffer::Target;
+use gltf::json::material::{
+    AlphaCutoff, EmissiveFactor, NormalTexture, OcclusionTexture, PbrBaseColorFactor,
+    PbrMetallicRoughness, StrengthFactor,
+};
+use gltf::json::mesh::{Mode, Semantic};
+use gltf::json::texture::{MagFilter, MinFilter, WrappingMode};
+use gltf::json::validation::Checked::Valid;
+use image::codecs::png::PngEncoder;
+use image::{ColorType, ImageError};
+use legion::*;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const EMISSIVE_STRENGTH_EXTENSION: &str = "KHR_materials_emissive_strength";
+
+/// Errors that can occur while exporting an asset.
+#[derive(Debug)]
+pub enum ExportError {
+    /// A file could not be written.
+    Io(PathBuf, std::io::Error),
+    /// The gltf document could not be serialised.
+    Json(json::Error),
+    /// The binary container of a .glb file could not be written.
+    Glb(gltf::Error),
+    /// A texture could not be encoded as a png.
+    Image(ImageError),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExportError::Io(path, error) => write!(f, "failed to write file {:?}: {}", path, error),
+            ExportError::Json(error) => write!(f, "failed to serialise gltf document: {}", error),
+            ExportError::Glb(error) => write!(f, "failed to write glb container: {}", error),
+            ExportError::Image(error) => write!(f, "failed to encode texture: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExportError::Io(_, error) => Some(error),
+            ExportError::Json(error) => Some(error),
+            ExportError::Glb(error) => Some(error),
+            ExportError::Image(error) => Some(error),
+        }
+    }
+}
+
+impl From<json::Error> for ExportError {
+    fn from(error: json::Error) -> Self {
+        ExportError::Json(error)
+    }
+}
+
+impl From<gltf::Error> for ExportError {
+    fn from(error: gltf::Error) -> Self {
+        ExportError::Glb(error)
+    }
+}
+
+impl From<ImageError> for ExportError {
+    fn from(error: ImageError) -> Self {
+        ExportError::Image(error)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ExportError>;
+
+// Collects the gltf document along with the single buffer holding every vertex, index and image
+struct GltfBuilder {
+    root: json::Root,
+    buffer: Vec<u8>,
+    // Emissive strengths other than 1.0 by material, which the json types have no field for
+    emissive_strengths: Vec<(usize, f32)>,
+}
+
+impl GltfBuilder {
+    fn new() -> Self {
+        let mut root = json::Root::default();
+        root.asset.generator = Some("learning_glium".to_string());
+        Self {
+            root,
+            buffer: Vec::new(),
+            emissive_strengths: Vec::new(),
+        }
+    }
+
+    fn push_view(
+        &mut self,
+        bytes: &[u8],
+        target: Option<Target>,
+    ) -> json::Index<json::buffer::View> {
+        // Accessors have to start at a multiple of their component size
+        let padding = (4 - self.buffer.len() % 4) % 4;
+        self.buffer.resize(self.buffer.len() + padding, 0);
+
+        self.root.buffer_views.push(json::buffer::View {
+            buffer: json::Index::new(0),
+            byte_length: bytes.len() as u32,
+            byte_offset: Some(self.buffer.len() as u32),
+            byte_stride: None,
+            name: None,
+            target: target.map(Valid),
+            extensions: None,
+            extras: Default::default(),
+        });
+        self.buffer.extend_from_slice(bytes);
+        json::Index::new(self.root.buffer_views.len() as u32 - 1)
+    }
+
+    // Positions require bounds, which are written for every float accessor for simplicity. Values
+    // mustn't be empty, as there would be no bounds.
+    fn push_float_accessor(
+        &mut self,
+        values: &[f32],
+        type_: Type,
+        components: usize,
+    ) -> Result<json::Index<json::Accessor>> {
+        let mut min = vec![f32::MAX; components];
+        let mut max = vec![f32::MIN; components];
+        for element in values.chunks_exact(components) {
+            for (i, value) in element.iter().enumerate() {
+                min[i] = min[i].min(*value);
+                max[i] = max[i].max(*value);
+            }
+        }
+
+        let bytes: Vec<u8> = values
+            .iter()
+            .flat_map(|value| value.to_le_bytes().to_vec())
+            .collect();
+        let view = self.push_view(&bytes, Some(Target::ArrayBuffer));
+        self.root.accessors.push(json::Accessor {
+            buffer_view: Some(view),
+            byte_offset: 0,
+            count: (values.len() / components) as u32,
+            component_type: Valid(GenericComponentType(ComponentType::F32)),
+            extensions: None,
+            extras: Default::default(),
+            type_: Valid(type_),
+            min: Some(json::serialize::to_value(min)?),
+            max: Some(json::serialize::to_value(max)?),
+            name: None,
+            normalized: false,
+            sparse: None,
+        });
+        Ok(json::Index::new(self.root.accessors.len() as u32 - 1))
+    }
+
+    fn push_index_accessor(&mut self, indices: &[u32]) -> json::Index<json::Accessor> {
+        let bytes: Vec<u8> = indices
+            .iter()
+            .flat_map(|index| index.to_le_bytes().to_vec())
+            .collect();
+        let view = self.push_view(&bytes, Some(Target::ElementArrayBuffer));
+        self.root.accessors.push(json::Accessor {
+            buffer_view: Some(view),
+            byte_offset: 0,
+            count: indices.len() as u32,
+            component_type: Valid(GenericComponentType(ComponentType::U32)),
+            extensions: None,
+            extras: Default::default(),
+            type_: Valid(Type::Scalar),
+            min: None,
+            max: None,
+            name: None,
+            normalized: false,
+            sparse: None,
+        });
+        json::Index::new(self.root.accessors.len() as u32 - 1)
+    }
+
+    // Only samplers which differ from the default are written
+    fn push_sampler(
+        &mut self,
+        sampler: &TextureSampler,
+    ) -> Option<json::Index<json::texture::Sampler>> {
+        if *sampler == TextureSampler::default() {
+            return None;
+        }
+
+        let wrapping_mode = |function| match function {
+            SamplerWrapFunction::Repeat => WrappingMode::Repeat,
+            SamplerWrapFunction::Mirror => WrappingMode::MirroredRepeat,
+            _ => WrappingMode::ClampToEdge,
+        };
+        let min_filter = match sampler.minify_filter {
+            MinifySamplerFilter::Nearest => MinFilter::Nearest,
+            MinifySamplerFilter::Linear => MinFilter::Linear,
+            MinifySamplerFilter::NearestMipmapNearest => MinFilter::NearestMipmapNearest,
+            MinifySamplerFilter::LinearMipmapNearest => MinFilter::LinearMipmapNearest,
+            MinifySamplerFilter::NearestMipmapLinear => MinFilter::NearestMipmapLinear,
+            MinifySamplerFilter::LinearMipmapLinear => MinFilter::LinearMipmapLinear,
+        };
+        let mag_filter = match sampler.magnify_filter {
+            MagnifySamplerFilter::Nearest => MagFilter::Nearest,
+            MagnifySamplerFilter::Linear => MagFilter::Linear,
+        };
+
+        self.root.samplers.push(json::texture::Sampler {
+            mag_filter: Some(Valid(mag_filter)),
+            min_filter: Some(Valid(min_filter)),
+            wrap_s: Valid(wrapping_mode(sampler.wrap_s)),
+            wrap_t: Valid(wrapping_mode(sampler.wrap_t)),
+            ..Default::default()
+        });
+        Some(json::Index::new(self.root.samplers.len() as u32 - 1))
+    }
+
+    // Textures read back from the gpu are embedded as pngs. The 1x1 neutral textures the importer
+    // fills empty material slots with are left out, so the slot stays empty.
+    fn push_texture(
+        &mut self,
+        image: RawImage2d<u8>,
+        neutral_colour: [u8; 4],
+        sampler: &TextureSampler,
+    ) -> Result<Option<json::Index<json::Texture>>> {
+        if image.width == 1 && image.height == 1 && image.data[..] == neutral_colour[..] {
+            return Ok(None);
+        }
+
+        let mut png: Vec<u8> = Vec::new();
+        PngEncoder::new(&mut png).encode(
+            &image.data,
+            image.width,
+            image.height,
+            ColorType::Rgba8,
+        )?;
+        let view = self.push_view(&png, None);
+
+        self.root.images.push(json::Image {
+            buffer_view: Some(view),
+            mime_type: Some(json::image::MimeType("image/png".to_string())),
+            name: None,
+            uri: None,
+            extensions: None,
+            extras: Default::default(),
+        });
+        let sampler = self.push_sampler(sampler);
+        self.root.textures.push(json::Texture {
+            name: None,
+            sampler,
+            source: json::Index::new(self.root.images.len() as u32 - 1),
+            extensions: None,
+            extras: Default::default(),
+        });
+        Ok(Some(json::Index::new(self.root.textures.len() as u32 - 1)))
+    }
+
+    fn push_material(&mut self, material: &Material) -> Result<json::Index<json::Material>> {
+        let info = |index, tex_coord| json::texture::Info {
+            index,
+            tex_coord,
+            extensions: None,
+            extras: Default::default(),
+        };
+
+        let diffuse_texture = self.push_texture(
+            material.diffuse_map.read(),
+            NEUTRAL_DIFFUSE_COLOUR,
+            &material.diffuse_sampler,
+        )?;
+        let metallic_roughness_texture = self.push_texture(
+            material.metallic_roughness_map.read(),
+            NEUTRAL_METALLIC_ROUGHNESS_COLOUR,
+            &material.metallic_roughness_sampler,
+        )?;
+        let occlusion_texture = if material.packed_occlusion {
+            metallic_roughness_texture
+        } else {
+            self.push_texture(
+                material.occlusion_map.read(),
+                NEUTRAL_OCCLUSION_COLOUR,
+                &material.occlusion_sampler,
+            )?
+        };
+        let normal_texture = self.push_texture(
+            material.normal_map.read(),
+            NEUTRAL_NORMAL_COLOUR,
+            &material.normal_sampler,
+        )?;
+        let emissive_texture = self.push_texture(
+            material.emissive_map.read(),
+            NEUTRAL_EMISSIVE_COLOUR,
+            &material.emissive_sampler,
+        )?;
+
+        let alpha_mode = match material.alpha_mode {
+            AlphaMode::Opaque => json::material::AlphaMode::Opaque,
+            AlphaMode::Mask => json::material::AlphaMode::Mask,
+            AlphaMode::Blend => json::material::AlphaMode::Blend,
+        };
+
+        let json_material = json::Material {
+            alpha_cutoff: AlphaCutoff(material.alpha_cutoff),
+            alpha_mode: Valid(alpha_mode),
+            double_sided: material.double_sided,
+            name: None,
+            pbr_metallic_roughness: PbrMetallicRoughness {
+                base_color_factor: PbrBaseColorFactor(material.base_colour_factor),
+                base_color_texture: diffuse_texture
+                    .map(|index| info(index, material.diffuse_uv_set)),
+                metallic_factor: StrengthFactor(material.metallic_factor),
+                roughness_factor: StrengthFactor(material.roughness_factor),
+                metallic_roughness_texture: metallic_roughness_texture
+                    .map(|index| info(index, material.metallic_roughness_uv_set)),
+                extensions: None,
+                extras: Default::default(),
+            },
+            normal_texture: normal_texture.map(|index| NormalTexture {
+                index,
+                scale: material.normal_scale,
+                tex_coord: material.normal_uv_set,
+                extensions: None,
+                extras: Default::default(),
+            }),
+            occlusion_texture: occlusion_texture.map(|index| OcclusionTexture {
+                index,
+                strength: StrengthFactor(material.occlusion_strength),
+                tex_coord: material.occlusion_uv_set,
+                extensions: None,
+                extras: Default::default(),
+            }),
+            emissive_texture: emissive_texture.map(|index| info(index, material.emissive_uv_set)),
+            emissive_factor: EmissiveFactor(material.emissive_factor),
+            extensions: None,
+            extras: Default::default(),
+        };
+        Ok(self.push_json_material(json_material, material.emissive_strength))
+    }
+
+    fn push_json_material(
+        &mut self,
+        material: json::Material,
+        emissive_strength: f32,
+    ) -> json::Index<json::Material> {
+        self.root.materials.push(material);
+        let material_index = self.root.materials.len() - 1;
+        if emissive_strength != 1.0 {
+            self.emissive_strengths
+                .push((material_index, emissive_strength));
+        }
+        json::Index::new(material_index as u32)
+    }
+
+    fn push_primitive(
+        &mut self,
+        primitive: &Primitive,
+        material_offset: u32,
+    ) -> Result<Option<json::mesh::Primitive>> {
+        self.push_vertices(
+            &primitive.vertices,
+            &primitive.indices,
+            primitive.primitive_type,
+            material_offset + primitive.material_index as u32,
+        )
+    }
+
+    // Primitives without any vertices are left out, their accessors would be invalid
+    fn push_vertices(
+        &mut self,
+        vertices: &[Vertex],
+        indices: &[u32],
+        primitive_type: PrimitiveType,
+        material: u32,
+    ) -> Result<Option<json::mesh::Primitive>> {
+        if vertices.is_empty() {
+            return Ok(None);
+        }
+
+        let positions: Vec<f32> = vertices
+            .iter()
+            .flat_map(|v| v.position().to_vec())
+            .collect();
+        let normals: Vec<f32> = vertices.iter().flat_map(|v| v.normal().to_vec()).collect();
+        let tangents: Vec<f32> = vertices.iter().flat_map(|v| v.tangent().to_vec()).collect();
+        let texture_coords: Vec<f32> = vertices
+            .iter()
+            .flat_map(|v| v.texture_coord().to_vec())
+            .collect();
+
+        let mut attributes = HashMap::new();
+        attributes.insert(
+            Valid(Semantic::Positions),
+            self.push_float_accessor(&positions, Type::Vec3, 3)?,
+        );
+        attributes.insert(
+            Valid(Semantic::Normals),
+            self.push_float_accessor(&normals, Type::Vec3, 3)?,
+        );
+        attributes.insert(
+            Valid(Semantic::Tangents),
+            self.push_float_accessor(&tangents, Type::Vec4, 4)?,
+        );
+        attributes.insert(
+            Valid(Semantic::TexCoords(0)),
+            self.push_float_accessor(&texture_coords, Type::Vec2, 2)?,
+        );
+
+        // The second uv set and colours are only written when they carry any information
+        if vertices
+            .iter()
+            .any(|v| v.texture_coord_1() != v.texture_coord())
+        {
+            let texture_coords_1: Vec<f32> = vertices
+                .iter()
+                .flat_map(|v| v.texture_coord_1().to_vec())
+                .collect();
+            attributes.insert(
+                Valid(Semantic::TexCoords(1)),
+                self.push_float_accessor(&texture_coords_1, Type::Vec2, 2)?,
+            );
+        }
+        if vertices.iter().any(|v| v.colour() != [1.0, 1.0, 1.0, 1.0]) {
+            let colours: Vec<f32> = vertices.iter().flat_map(|v| v.colour().to_vec()).collect();
+            attributes.insert(
+                Valid(Semantic::Colors(0)),
+                self.push_float_accessor(&colours, Type::Vec4, 4)?,
+            );
+        }
+
+        let mode = match primitive_type {
+            PrimitiveType::Points => Mode::Points,
+            PrimitiveType::LinesList => Mode::Lines,
+            PrimitiveType::LineStrip => Mode::LineStrip,
+            PrimitiveType::LineLoop => Mode::LineLoop,
+            PrimitiveType::TriangleStrip => Mode::TriangleStrip,
+            PrimitiveType::TriangleFan => Mode::TriangleFan,
+            _ => Mode::Triangles,
+        };
+
+        Ok(Some(json::mesh::Primitive {
+            attributes,
+            extensions: None,
+            extras: Default::default(),
+            indices: Some(self.push_index_accessor(indices)),
+            material: Some(json::Index::new(material)),
+            mode: Valid(mode),
+            targets: None,
+        }))
+    }
+
+    // Meshes are written where they currently are in the world, e.g. after being moved by
+    // physics, so the node hierarchy is flattened into one node per mesh. Nodes of meshes without
+    // any vertices are kept, but without a mesh.
+    fn push_mesh(
+        &mut self,
+        mesh: &Mesh,
+        name: Option<String>,
+        material_offset: u32,
+    ) -> Result<json::Index<json::Node>> {
+        let mut primitives: Vec<json::mesh::Primitive> = Vec::new();
+        for primitive in mesh.primitives.iter() {
+            primitives.extend(self.push_primitive(primitive, material_offset)?);
+        }
+
+        let json_mesh = if primitives.is_empty() {
+            None
+        } else {
+            self.root.meshes.push(json::Mesh {
+                extensions: None,
+                extras: Default::default(),
+                name: name.clone(),
+                primitives,
+                weights: None,
+            });
+            Some(json::Index::new(self.root.meshes.len() as u32 - 1))
+        };
+
+        let translation = mesh.updated_isometry.translation.vector;
+        let rotation = mesh.updated_isometry.rotation;
+        self.root.nodes.push(json::Node {
+            camera: None,
+            children: None,
+            extensions: None,
+            extras: Default::default(),
+            matrix: None,
+            mesh: json_mesh,
+            name,
+            rotation: Some(json::scene::UnitQuaternion([
+                rotation.i, rotation.j, rotation.k, rotation.w,
+            ])),
+            scale: Some(mesh.scaling.into()),
+            translation: Some(translation.into()),
+            skin: None,
+            weights: None,
+        });
+        Ok(json::Index::new(self.root.nodes.len() as u32 - 1))
+    }
+
+    fn push_model(&mut self, model: &Model) -> Result<Vec<json::Index<json::Node>>> {
+        let material_offset = self.root.materials.len() as u32;
+        for material in model.materials.iter() {
+            self.push_material(material)?;
+        }
+
+        model
+            .meshes
+            .iter()
+            .map(|mesh| {
+                let name = mesh.node.and_then(|node| model.nodes[node].name.clone());
+                self.push_mesh(mesh, name, material_offset)
+            })
+            .collect()
+    }
+
+    // Adds the scene and the buffer, which is the binary chunk of a .glb unless it has a uri, and
+    // serialises the document along with the extensions the json types have no fields for. Empty
+    // scenes are left out, the gltf crate can't read a scene without nodes back.
+    fn finish(
+        &mut self,
+        nodes: Vec<json::Index<json::Node>>,
+        buffer_uri: Option<String>,
+    ) -> Result<json::Value> {
+        if !nodes.is_empty() {
+            self.root.scenes.push(json::Scene {
+                extensions: None,
+                extras: Default::default(),
+                name: None,
+                nodes,
+            });
+            self.root.scene = Some(json::Index::new(0));
+        }
+
+        if !self.buffer.is_empty() {
+            self.root.buffers.push(json::Buffer {
+                byte_length: self.buffer.len() as u32,
+                name: None,
+                uri: buffer_uri,
+                extensions: None,
+                extras: Default::default(),
+            });
+        }
+        if !self.emissive_strengths.is_empty() {
+            self.root
+                .extensions_used
+                .push(EMISSIVE_STRENGTH_EXTENSION.to_string());
+        }
+
+        let mut document = json::serialize::to_value(&self.root)?;
+        for (material_index, strength) in self.emissive_strengths.iter() {
+            let mut extension = HashMap::new();
+            extension.insert("emissiveStrength", *strength);
+            let mut extensions = HashMap::new();
+            extensions.insert(EMISSIVE_STRENGTH_EXTENSION, extension);
+            document["materials"][*material_index]["extensions"] =
+                json::serialize::to_value(extensions)?;
+        }
+        Ok(document)
+    }
+
+    fn into_glb(mut self, nodes: Vec<json::Index<json::Node>>) -> Result<Vec<u8>> {
+        let document = self.finish(nodes, None)?;
+        let glb = Glb {
+            header: Header {
+                magic: *b"glTF",
+                version: 2,
+                length: 0,
+            },
+            json: Cow::Owned(json::serialize::to_vec(&document)?),
+            bin: if self.buffer.is_empty() {
+                None
+            } else {
+                Some(Cow::Owned(self.buffer))
+            },
+        };
+        Ok(glb.to_vec()?)
+    }
+
+    // Writes a .glb if the path ends in .glb, otherwise a .gltf with the buffer in a .bin file
+    // next to it
+    fn write<P>(mut self, nodes: Vec<json::Index<json::Node>>, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let write_file = |path: &Path, bytes: &[u8]| {
+            fs::write(path, bytes).map_err(|error| ExportError::Io(path.to_path_buf(), error))
+        };
+
+        let binary = matches!(
+            path.extension().and_then(|extension| extension.to_str()),
+            Some(extension) if extension.eq_ignore_ascii_case("glb")
+        );
+        if binary {
+            return write_file(path, &self.into_glb(nodes)?);
+        }
+
+        let bin_path = path.with_extension("bin");
+        let uri = bin_path
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().into_owned());
+        let document = self.finish(nodes, uri)?;
+        if !self.buffer.is_empty() {
+            write_file(&bin_path, &self.buffer)?;
+        }
+        write_file(path, &json::serialize::to_vec_pretty(&document)?)
+    }
+}
+
+/// Writes a model to a .gltf or .glb file, chosen by the extension of the path. Meshes are
+/// placed where they currently are and textures are embedded as pngs. Skins, animations and
+/// morph targets aren't written.
+pub fn export_model<P>(model: &Model, path: P) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let mut builder = GltfBuilder::new();
+    let nodes = builder.push_model(model)?;
+    builder.write(nodes, path)
+}
+
+/// Writes the model of every entity in the world into a single scene, e.g. to save a scene after
+/// physics settled it. Models shared by several entities are written once.
+pub fn export_scene<P>(world: &World, rs: &RendererState, path: P) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let mut query = <&ModelHandle>::query();
+    let mut model_indices: Vec<usize> = query
+        .iter(world)
+        .map(|model_handle| model_handle.index())
+        .collect();
+    model_indices.sort_unstable();
+    model_indices.dedup();
+
+    let mut builder = GltfBuilder::new();
+    let mut nodes: Vec<json::Index<json::Node>> = Vec::new();
+    for index in model_indices {
+        let model = rs.get_model(&ModelHandle::new(index));
+        nodes.extend(builder.push_model(model)?);
+    }
+    builder.write(nodes, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(position: [f32; 3]) -> Vertex {
+        Vertex::new(
+            position,
+            [position[0], position[1]],
+            [position[0], position[1]],
+            [0.0, 0.0, 1.0],
+            [1.0, 0.0, 0.0, 1.0],
+            [1.0, 1.0, 1.0, 1.0],
+        )
+    }
+
+    // A triangle with an emissive material, after an odd sized view such as an embedded png
+    fn triangle_glb() -> Vec<u8> {
+        let mut builder = GltfBuilder::new();
+        builder.push_view(&[1, 2, 3], None);
+
+        let material = builder.push_json_material(json::Material::default(), 4.0);
+        let vertices = [
+            vertex([-1.0, 0.0, 2.0]),
+            vertex([1.0, 0.5, 2.0]),
+            vertex([0.0, 3.0, -2.0]),
+        ];
+        let primitive = builder
+            .push_vertices(
+                &vertices,
+                &[0, 1, 2],
+                PrimitiveType::TrianglesList,
+                material.value() as u32,
+            )
+            .unwrap()
+            .unwrap();
+        builder.root.meshes.push(json::Mesh {
+            extensions: None,
+            extras: Default::default(),
+            name: None,
+            primitives: vec![primitive],
+            weights: None,
+        });
+        builder.root.nodes.push(json::Node {
+            camera: None,
+            children: None,
+            extensions: None,
+            extras: Default::default(),
+            matrix: None,
+            mesh: Some(json::Index::new(0)),
+            name: None,
+            rotation: None,
+            scale: None,
+            translation: None,
+            skin: None,
+            weights: None,
+        });
+
+        builder.into_glb(vec![json::Index::new(0)]).unwrap()
+    }
+
+    #[test]
+    fn round_trips_through_gltf() {
+        let gltf = gltf::Gltf::from_slice(&triangle_glb()).unwrap();
+        let primitive = gltf.meshes().next().unwrap().primitives().next().unwrap();
+
+        let positions = primitive.get(&gltf::Semantic::Positions).unwrap();
+        assert_eq!(positions.count(), 3);
+        assert_eq!(
+            positions.min(),
+            Some(json::serialize::to_value([-1.0, 0.0, -2.0]).unwrap())
+        );
+        assert_eq!(
+            positions.max(),
+            Some(json::serialize::to_value([1.0, 3.0, 2.0]).unwrap())
+        );
+        assert_eq!(primitive.indices().unwrap().count(), 3);
+        // The second uv set and colours carry no information
+        assert_eq!(primitive.attributes().count(), 4);
+        assert_eq!(primitive.material().index(), Some(0));
+    }
+
+    #[test]
+    fn buffer_views_are_aligned_to_four_bytes() {
+        let gltf = gltf::Gltf::from_slice(&triangle_glb()).unwrap();
+
+        assert!(gltf.views().count() > 1);
+        for view in gltf.views() {
+            assert_eq!(view.offset() % 4, 0);
+        }
+        let buffer = gltf.buffers().next().unwrap();
+        assert_eq!(Some(buffer.length()), gltf.blob.as_ref().map(Vec::len));
+    }
+
+    #[test]
+    fn emissive_strength_is_written_as_an_extension() {
+        let bytes = triangle_glb();
+        let glb = Glb::from_slice(&bytes).unwrap();
+        let document: json::Value = json::deserialize::from_slice(&glb.json).unwrap();
+
+        assert_eq!(
+            document["extensionsUsed"],
+            json::serialize::to_value([EMISSIVE_STRENGTH_EXTENSION]).unwrap()
+        );
+        assert_eq!(
+            document["materials"][0]["extensions"][EMISSIVE_STRENGTH_EXTENSION]["emissiveStrength"],
+            4.0
+        );
+    }
+
+    #[test]
+    fn empty_documents_can_be_read_back() {
+        let glb = GltfBuilder::new().into_glb(Vec::new()).unwrap();
+        let gltf = gltf::Gltf::from_slice(&glb).unwrap();
+        assert_eq!(gltf.scenes().count(), 0);
+    }
+
+    #[test]
+    fn primitives_without_vertices_are_left_out() {
+        let mut builder = GltfBuilder::new();
+        let primitive = builder
+            .push_vertices(&[], &[], PrimitiveType::TrianglesList, 0)
+            .unwrap();
+
+        assert!(primitive.is_none());
+        assert!(builder.root.accessors.is_empty());
+        assert!(builder.buffer.is_empty());
+    }
+}
@@ -1,59 +1,304 @@
+use crate::animation::{self, Animation, Channel};
+use crate::camera::{self, Camera, Projection};
+use crate::compressed::{self, TextureFile, TextureFormat, TextureLevel};
+use crate::dds;
+use crate::ktx2;
+use crate::light::{Light, LightKind};
 use crate::material::Material as InternalMaterial;
+use crate::material::{AlphaMode, MaterialSrgbTexture, MaterialTexture, TextureSampler};
 use crate::mesh::Mesh;
 use crate::model::{Model, ModelHandle};
-use crate::primitive::Primitive;
+use crate::node::{self, Node};
+use crate::obj::{self, MtlMaterial, ObjData, ObjPrimitive};
+use crate::ply;
+use crate::primitive::{self, MorphTarget, Primitive};
 use crate::renderer::RendererState;
+use crate::skeleton::{Skeleton, MAX_JOINT_COUNT};
 use crate::skybox::Skybox;
+use crate::stl;
+use crate::texture_cache::{self, TextureCache, TextureSource};
 use crate::vertex;
-use crate::vertex::Vertex;
+use crate::vertex::{RawVertex, SkinVertex, Vertex};
 use glium::backend::Facade;
+use glium::buffer::BufferCreationError;
 use glium::framebuffer::{RenderBuffer, SimpleFrameBuffer};
+use glium::index::PrimitiveType;
 use glium::texture::{
-    Cubemap, MipmapsOption, RawImage2d, SrgbTexture2d, Texture2d, UncompressedFloatFormat,
+    CompressedFormat, CompressedMipmapsOption, CompressedSrgbFormat, CompressedSrgbTexture2d,
+    CompressedTexture2d, Cubemap, MipmapsOption, RawImage2d, SrgbTexture2d, Texture2d,
+    TextureCreationError, UncompressedFloatFormat,
+};
+use glium::uniforms::{
+    MagnifySamplerFilter, MinifySamplerFilter, SamplerWrapFunction, UniformBuffer,
 };
 use glium::Program;
+use glium::Rect;
 use glium::Surface;
-use gltf::accessor::{Accessor, DataType};
+use gltf::accessor::sparse::IndexType;
+use gltf::accessor::{Accessor, DataType, Dimensions};
+use gltf::animation::{Interpolation as GltfInterpolation, Property as GltfProperty};
 use gltf::buffer::Source as BufferSource;
+use gltf::buffer::View;
+use gltf::camera::Projection as GltfProjection;
+use gltf::image::Image as GltfImage;
 use gltf::image::Source as ImageSource;
 use gltf::iter::Buffers;
+use gltf::json::Value as JsonValue;
+use gltf::khr_lights_punctual::Kind as GltfLightKind;
+use gltf::material::AlphaMode as GltfAlphaMode;
 use gltf::material::Material as GltfMaterial;
-use gltf::mesh::Semantic;
-use gltf::scene::Transform;
-use gltf::Gltf;
+use gltf::mesh::{Mode, Semantic};
+use gltf::texture::{MagFilter, MinFilter, Sampler as GltfSampler, WrappingMode};
+use gltf::Node as GltfNode;
+use gltf::Primitive as GltfPrimitive;
+use gltf::Texture as GltfTexture;
+use gltf::{Glb, Gltf};
 use image::codecs::hdr::HdrDecoder;
+use image::imageops::{self, FilterType};
 use image::io::Reader;
-use image::ImageFormat;
-use na::{Isometry3, Matrix3, Quaternion, Translation3, UnitQuaternion, Vector3};
+use image::{ImageError, ImageFormat, Rgba, RgbaImage};
+use na::{
+    Isometry3, IsometryMatrix3, Matrix4, Orthographic3, Perspective3, Point3, Quaternion,
+    UnitQuaternion, Vector3,
+};
 use num::NumCast;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::convert::TryInto;
+use std::fmt;
 use std::fs;
 use std::fs::File;
-use std::io::{BufReader, Error, ErrorKind, Result};
-use std::path::Path;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 use std::fmt::Debug;
 
+/// Loaded in place of material images which can't be read or decoded.
 pub const MISSING_TEXTURE_PATH: &str = "assets/missing_texture.png";
 
-// Used to load all gltf buffers into data
-pub fn load_buffers<P>(buffers: Buffers, buffer_location: P) -> Vec<Vec<u8>>
+// Colours of the 1x1 textures used when a material slot has no texture
+pub const NEUTRAL_DIFFUSE_COLOUR: [u8; 4] = [255, 255, 255, 255];
+pub const NEUTRAL_METALLIC_ROUGHNESS_COLOUR: [u8; 4] = [255, 255, 255, 255];
+pub const NEUTRAL_OCCLUSION_COLOUR: [u8; 4] = [255, 255, 255, 255];
+pub const NEUTRAL_NORMAL_COLOUR: [u8; 4] = [128, 128, 255, 255];
+pub const NEUTRAL_EMISSIVE_COLOUR: [u8; 4] = [255, 255, 255, 255];
+
+/// Errors that can occur while importing an asset.
+#[derive(Debug)]
+pub enum ImportError {
+    /// A file could not be opened or read.
+    MissingFile(PathBuf, std::io::Error),
+    /// The gltf document could not be parsed or failed validation.
+    Gltf(gltf::Error),
+    /// The gltf document does not contain any scene to load.
+    MissingScene,
+    /// A buffer is referenced which does not exist, such as the binary chunk of a .gltf file.
+    MissingBuffer(usize),
+    /// A material is requested which the gltf document does not contain.
+    MissingMaterial(usize),
+    /// A uri could not be resolved, for example a malformed base64 data uri.
+    InvalidUri(String),
+    /// The data of an accessor can't be read into the requested type.
+    UnsupportedAccessor { accessor: usize, reason: String },
+    /// A primitive lacks a vertex attribute which is required to draw it.
+    MissingAttribute {
+        mesh: usize,
+        primitive: usize,
+        attribute: String,
+    },
+    /// An image could not be decoded.
+    BadImage { image: String, error: ImageError },
+    /// A decoded image could not be uploaded as a texture.
+    Texture(TextureCreationError),
+    /// A skin has more joints than a skinned mesh can be drawn with.
+    TooManyJoints { skin: usize, joints: usize },
+    /// A buffer holding per mesh data, such as joint matrices, could not be created.
+    Buffer(BufferCreationError),
+    /// An .obj or .mtl file could not be parsed.
+    Obj {
+        file: PathBuf,
+        error: obj::ParseError,
+    },
+    /// A .ply file could not be parsed.
+    Ply {
+        file: PathBuf,
+        error: ply::ParseError,
+    },
+    /// An .stl file could not be parsed.
+    Stl {
+        file: PathBuf,
+        error: stl::ParseError,
+    },
+    /// A .dds file could not be parsed.
+    Dds {
+        file: PathBuf,
+        error: dds::ParseError,
+    },
+    /// A .ktx2 file could not be parsed.
+    Ktx2 {
+        file: PathBuf,
+        error: ktx2::ParseError,
+    },
+    /// A skybox was loaded from a texture file which isn't a cubemap.
+    NotACubemap,
+    /// Decoding a file panicked on a worker thread of an `AssetLoader`.
+    Panicked { file: PathBuf, message: String },
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::MissingFile(path, error) => {
+                write!(f, "failed to read file {:?}: {}", path, error)
+            }
+            ImportError::Gltf(error) => write!(f, "invalid gltf document: {}", error),
+            ImportError::MissingScene => write!(f, "gltf document contains no scene"),
+            ImportError::MissingBuffer(index) => write!(f, "buffer {} does not exist", index),
+            ImportError::MissingMaterial(index) => write!(f, "material {} does not exist", index),
+            ImportError::InvalidUri(uri) => write!(f, "invalid uri {:?}", uri),
+            ImportError::UnsupportedAccessor { accessor, reason } => {
+                write!(f, "unsupported accessor {}: {}", accessor, reason)
+            }
+            ImportError::MissingAttribute {
+                mesh,
+                primitive,
+                attribute,
+            } => write!(
+                f,
+                "primitive {} of mesh {} is missing the {} attribute",
+                primitive, mesh, attribute
+            ),
+            ImportError::BadImage { image, error } => {
+                write!(f, "failed to decode image {}: {}", image, error)
+            }
+            ImportError::Texture(error) => write!(f, "failed to create texture: {:?}", error),
+            ImportError::TooManyJoints { skin, joints } => write!(
+                f,
+                "skin {} has {} joints, at most {} are supported",
+                skin, joints, MAX_JOINT_COUNT
+            ),
+            ImportError::Buffer(error) => write!(f, "failed to create buffer: {:?}", error),
+            ImportError::Obj { file, error } => write!(f, "failed to parse {:?}: {}", file, error),
+            ImportError::Ply { file, error } => write!(f, "failed to parse {:?}: {}", file, error),
+            ImportError::Stl { file, error } => write!(f, "failed to parse {:?}: {}", file, error),
+            ImportError::Dds { file, error } => write!(f, "failed to parse {:?}: {}", file, error),
+            ImportError::Ktx2 { file, error } => write!(f, "failed to parse {:?}: {}", file, error),
+            ImportError::NotACubemap => write!(f, "texture file is not a cubemap"),
+            ImportError::Panicked { file, message } => {
+                write!(f, "panicked while decoding {:?}: {}", file, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ImportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImportError::MissingFile(_, error) => Some(error),
+            ImportError::Gltf(error) => Some(error),
+            ImportError::BadImage { error, .. } => Some(error),
+            ImportError::Obj { error, .. } => Some(error),
+            ImportError::Ply { error, .. } => Some(error),
+            ImportError::Stl { error, .. } => Some(error),
+            ImportError::Dds { error, .. } => Some(error),
+            ImportError::Ktx2 { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<gltf::Error> for ImportError {
+    fn from(error: gltf::Error) -> Self {
+        ImportError::Gltf(error)
+    }
+}
+
+impl From<TextureCreationError> for ImportError {
+    fn from(error: TextureCreationError) -> Self {
+        ImportError::Texture(error)
+    }
+}
+
+impl From<BufferCreationError> for ImportError {
+    fn from(error: BufferCreationError) -> Self {
+        ImportError::Buffer(error)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ImportError>;
+
+// Used to read a whole file, keeping track of which file failed
+fn read_file<P>(path: P) -> Result<Vec<u8>>
 where
-    P: AsRef<Path> + Debug,
+    P: AsRef<Path>,
+{
+    fs::read(&path).map_err(|error| ImportError::MissingFile(path.as_ref().to_path_buf(), error))
+}
+
+// Used to read the bytes behind a gltf uri, either a base64 data uri or a relative file path
+fn load_uri<P>(uri: &str, uri_location: P) -> Result<Vec<u8>>
+where
+    P: AsRef<Path>,
+{
+    if uri.starts_with("data:") {
+        let (_, data) = uri
+            .split_once(',')
+            .filter(|(header, _)| header.ends_with(";base64"))
+            .ok_or_else(|| ImportError::InvalidUri(uri.to_string()))?;
+        base64::decode(data).map_err(|_| ImportError::InvalidUri(uri.to_string()))
+    } else {
+        read_file(uri_location.as_ref().join(uri))
+    }
+}
+
+// Used to load all gltf buffers into data, blob is the binary chunk of a .glb file
+pub fn load_buffers<P>(
+    buffers: Buffers,
+    mut blob: Option<Vec<u8>>,
+    buffer_location: P,
+) -> Result<Vec<Vec<u8>>>
+where
+    P: AsRef<Path>,
 {
     let mut buffer_vec: Vec<Vec<u8>> = Vec::new();
     for buffer in buffers {
-        if let BufferSource::Uri(path) = buffer.source() {
-            println!("buffer path: {:#?}", buffer_location);
-            let path = buffer_location.as_ref().join(path);
-            let buffer_binary = fs::read(path).unwrap();
-            buffer_vec.push(buffer_binary);
+        let buffer_binary = match buffer.source() {
+            BufferSource::Uri(uri) => load_uri(uri, &buffer_location)?,
+            BufferSource::Bin => blob
+                .take()
+                .ok_or_else(|| ImportError::MissingBuffer(buffer.index()))?,
+        };
+        buffer_vec.push(buffer_binary);
+    }
+    Ok(buffer_vec)
+}
+
+// Used to get the encoded bytes of a gltf image, wherever they are stored
+fn load_image_bytes<P>(image: &GltfImage, image_location: P, buffers: &[&[u8]]) -> Result<Vec<u8>>
+where
+    P: AsRef<Path>,
+{
+    match image.source() {
+        ImageSource::Uri { uri, .. } => load_uri(uri, image_location),
+        ImageSource::View { view, .. } => {
+            let buffer_index = view.buffer().index();
+            let buffer = buffers
+                .get(buffer_index)
+                .ok_or(ImportError::MissingBuffer(buffer_index))?;
+            let start = view.offset();
+            let end = start + view.length();
+            buffer
+                .get(start..end)
+                .map(|bytes| bytes.to_vec())
+                .ok_or(ImportError::MissingBuffer(buffer_index))
         }
     }
-    buffer_vec
 }
 
-// Used to load indivudial buffer
+/// Reads `count` elements of `dimension` components each. Normalized integers are mapped into
+/// [0, 1] or [-1, 1] as described by the glTF spec, everything else is cast as is.
+#[allow(clippy::too_many_arguments)]
 fn load_buffer_data<T: NumCast>(
     buffer: &[u8],
     offset: usize,
@@ -61,8 +306,9 @@ fn load_buffer_data<T: NumCast>(
     data_type: DataType,
     dimension: usize,
     stride: usize,
-) -> Vec<T> {
-    let mut data: Vec<T> = Vec::with_capacity(count);
+    normalized: bool,
+) -> Option<Vec<T>> {
+    let mut data: Vec<T> = Vec::with_capacity(count * dimension);
 
     for i in 0..count {
         for dimension_offset in 0..dimension {
@@ -71,144 +317,281 @@ fn load_buffer_data<T: NumCast>(
             let bytes = &buffer[start..end];
             //TODO find crate to do this
             //let index: T = T::from_le_bytes(bytes.try_into().unwrap());
-            let value: T = match data_type {
-                DataType::I8 => {
-                    num::cast::<i8, T>(i8::from_le_bytes(bytes.try_into().unwrap())).unwrap()
-                }
-                DataType::U8 => {
-                    num::cast::<u8, T>(u8::from_le_bytes(bytes.try_into().unwrap())).unwrap()
-                }
-                DataType::I16 => {
-                    num::cast::<i16, T>(i16::from_le_bytes(bytes.try_into().unwrap())).unwrap()
-                }
-                DataType::U16 => {
-                    num::cast::<u16, T>(u16::from_le_bytes(bytes.try_into().unwrap())).unwrap()
-                }
-                DataType::U32 => {
-                    num::cast::<u32, T>(u32::from_le_bytes(bytes.try_into().unwrap())).unwrap()
-                }
-                DataType::F32 => {
-                    num::cast::<f32, T>(f32::from_le_bytes(bytes.try_into().unwrap())).unwrap()
+            let value: Option<T> = if normalized {
+                let value = match data_type {
+                    DataType::I8 => (i8::from_le_bytes(bytes.try_into().unwrap()) as f32
+                        / i8::MAX as f32)
+                        .max(-1.0),
+                    DataType::U8 => {
+                        u8::from_le_bytes(bytes.try_into().unwrap()) as f32 / u8::MAX as f32
+                    }
+                    DataType::I16 => (i16::from_le_bytes(bytes.try_into().unwrap()) as f32
+                        / i16::MAX as f32)
+                        .max(-1.0),
+                    DataType::U16 => {
+                        u16::from_le_bytes(bytes.try_into().unwrap()) as f32 / u16::MAX as f32
+                    }
+                    DataType::U32 => {
+                        (u32::from_le_bytes(bytes.try_into().unwrap()) as f64 / u32::MAX as f64)
+                            as f32
+                    }
+                    DataType::F32 => f32::from_le_bytes(bytes.try_into().unwrap()),
+                };
+                num::cast::<f32, T>(value)
+            } else {
+                match data_type {
+                    DataType::I8 => {
+                        num::cast::<i8, T>(i8::from_le_bytes(bytes.try_into().unwrap()))
+                    }
+                    DataType::U8 => {
+                        num::cast::<u8, T>(u8::from_le_bytes(bytes.try_into().unwrap()))
+                    }
+                    DataType::I16 => {
+                        num::cast::<i16, T>(i16::from_le_bytes(bytes.try_into().unwrap()))
+                    }
+                    DataType::U16 => {
+                        num::cast::<u16, T>(u16::from_le_bytes(bytes.try_into().unwrap()))
+                    }
+                    DataType::U32 => {
+                        num::cast::<u32, T>(u32::from_le_bytes(bytes.try_into().unwrap()))
+                    }
+                    DataType::F32 => {
+                        num::cast::<f32, T>(f32::from_le_bytes(bytes.try_into().unwrap()))
+                    }
                 }
             };
-            data.push(value);
+            data.push(value?);
         }
     }
 
-    data
+    Some(data)
 }
 
-fn accessor_as<T: NumCast>(accessor: Accessor, buffers: &[&[u8]]) -> Vec<T> {
-    let data_type = accessor.data_type();
-    let buffer_view = accessor.view().unwrap();
-    let buffer_stride = buffer_view.stride().unwrap_or(accessor.size());
-    let buffer = &buffers[buffer_view.buffer().index()];
+/// Reads tightly packed or strided elements out of a buffer view, checking that the last element
+/// is still inside of the buffer.
+#[allow(clippy::too_many_arguments)]
+fn load_buffer_view<T: NumCast>(
+    accessor_index: usize,
+    buffer_view: &View,
+    offset: usize,
+    count: usize,
+    data_type: DataType,
+    dimension: usize,
+    stride: Option<usize>,
+    normalized: bool,
+    buffers: &[&[u8]],
+) -> Result<Vec<T>> {
+    let unsupported = |reason: &str| ImportError::UnsupportedAccessor {
+        accessor: accessor_index,
+        reason: reason.to_string(),
+    };
+
+    let element_size = data_type.size() * dimension;
+    let stride = stride.unwrap_or(element_size);
+    let buffer_index = buffer_view.buffer().index();
+    let buffer = buffers
+        .get(buffer_index)
+        .ok_or(ImportError::MissingBuffer(buffer_index))?;
+    let offset = buffer_view.offset() + offset;
+
+    if count > 0 && offset + (count - 1) * stride + element_size > buffer.len() {
+        return Err(unsupported("accessor reads past the end of its buffer"));
+    }
 
     load_buffer_data::<T>(
-        buffer,
-        buffer_view.offset() + accessor.offset(),
-        accessor.count(),
-        data_type,
-        accessor.dimensions().multiplicity(),
-        buffer_stride,
+        buffer, offset, count, data_type, dimension, stride, normalized,
     )
+    .ok_or_else(|| unsupported("accessor values don't fit in the requested type"))
+}
+
+fn accessor_as<T: NumCast + Copy>(accessor: Accessor, buffers: &[&[u8]]) -> Result<Vec<T>> {
+    let unsupported = |reason: &str| ImportError::UnsupportedAccessor {
+        accessor: accessor.index(),
+        reason: reason.to_string(),
+    };
+
+    let data_type = accessor.data_type();
+    let normalized = accessor.normalized();
+    let count = accessor.count();
+    let dimension = accessor.dimensions().multiplicity();
+
+    // Without a buffer view every value starts out as zero, usually to be filled by sparse values
+    let mut data: Vec<T> = match accessor.view() {
+        Some(buffer_view) => load_buffer_view::<T>(
+            accessor.index(),
+            &buffer_view,
+            accessor.offset(),
+            count,
+            data_type,
+            dimension,
+            buffer_view.stride(),
+            normalized,
+            buffers,
+        )?,
+        None => {
+            let zero = num::cast::<u8, T>(0)
+                .ok_or_else(|| unsupported("accessor values don't fit in the requested type"))?;
+            vec![zero; count * dimension]
+        }
+    };
+
+    if let Some(sparse) = accessor.sparse() {
+        let sparse_count = sparse.count() as usize;
+        let sparse_indices = sparse.indices();
+        let index_type = match sparse_indices.index_type() {
+            IndexType::U8 => DataType::U8,
+            IndexType::U16 => DataType::U16,
+            IndexType::U32 => DataType::U32,
+        };
+        let indices = load_buffer_view::<u32>(
+            accessor.index(),
+            &sparse_indices.view(),
+            sparse_indices.offset() as usize,
+            sparse_count,
+            index_type,
+            1,
+            None,
+            false,
+            buffers,
+        )?;
+        let sparse_values = sparse.values();
+        let values = load_buffer_view::<T>(
+            accessor.index(),
+            &sparse_values.view(),
+            sparse_values.offset() as usize,
+            sparse_count,
+            data_type,
+            dimension,
+            None,
+            normalized,
+            buffers,
+        )?;
+
+        for (i, index) in indices.into_iter().enumerate() {
+            let index = index as usize;
+            if index >= count {
+                return Err(unsupported(
+                    "sparse index refers to an element which does not exist",
+                ));
+            }
+            data[index * dimension..(index + 1) * dimension]
+                .copy_from_slice(&values[i * dimension..(i + 1) * dimension]);
+        }
+    }
+
+    Ok(data)
 }
 
-pub fn load_indices<T: NumCast>(accessor: Accessor, buffers: &[&[u8]]) -> Vec<T> {
+pub fn load_indices<T: NumCast + Copy>(accessor: Accessor, buffers: &[&[u8]]) -> Result<Vec<T>> {
     accessor_as::<T>(accessor, buffers)
 }
 
-pub fn load_2d_array<T: NumCast + Copy>(accessor: Accessor, buffers: &[&[u8]]) -> Vec<[T; 2]> {
-    let data = accessor_as::<T>(accessor, buffers);
+pub fn load_2d_array<T: NumCast + Copy>(
+    accessor: Accessor,
+    buffers: &[&[u8]],
+) -> Result<Vec<[T; 2]>> {
+    let data = accessor_as::<T>(accessor, buffers)?;
     let mut values: Vec<[T; 2]> = Vec::with_capacity(data.len() / 2);
-    let chunks = data.chunks(2);
+    let chunks = data.chunks_exact(2);
 
     for chunk in chunks {
         values.push(chunk.try_into().unwrap());
     }
 
-    values
+    Ok(values)
 }
 
-pub fn load_3d_array<T: NumCast + Copy>(accessor: Accessor, buffers: &[&[u8]]) -> Vec<[T; 3]> {
-    let data = accessor_as::<T>(accessor, buffers);
+pub fn load_3d_array<T: NumCast + Copy>(
+    accessor: Accessor,
+    buffers: &[&[u8]],
+) -> Result<Vec<[T; 3]>> {
+    let data = accessor_as::<T>(accessor, buffers)?;
     let mut values: Vec<[T; 3]> = Vec::with_capacity(data.len() / 3);
-    let chunks = data.chunks(3);
+    let chunks = data.chunks_exact(3);
+
+    for chunk in chunks {
+        values.push(chunk.try_into().unwrap());
+    }
+
+    Ok(values)
+}
+
+pub fn load_4d_array<T: NumCast + Copy>(
+    accessor: Accessor,
+    buffers: &[&[u8]],
+) -> Result<Vec<[T; 4]>> {
+    let data = accessor_as::<T>(accessor, buffers)?;
+    let mut values: Vec<[T; 4]> = Vec::with_capacity(data.len() / 4);
+    let chunks = data.chunks_exact(4);
 
     for chunk in chunks {
         values.push(chunk.try_into().unwrap());
     }
 
-    values
+    Ok(values)
 }
 
-fn try_load_rawimage_hdr<P>(image_path: P) -> Result<RawImage2d<'static, f32>>
+/// Decodes an .hdr file without touching GL, so it can be done on a worker thread.
+pub fn try_load_rawimage_hdr<P>(image_path: P) -> Result<RawImage2d<'static, f32>>
 where
     P: AsRef<Path>,
 {
-    let file = File::open(&image_path)?;
+    let bad_image = |error| ImportError::BadImage {
+        image: image_path.as_ref().display().to_string(),
+        error,
+    };
+
+    let file = File::open(&image_path)
+        .map_err(|error| ImportError::MissingFile(image_path.as_ref().to_path_buf(), error))?;
     let reader = BufReader::new(file);
     //    let mut reader = Reader::open(&image_path)?;
-    let decoder = HdrDecoder::new(reader).or_else(|_| {
-        return Err(Error::new(
-            ErrorKind::NotFound,
-            "Failed to use hdr bufreader",
-        ));
-    })?;
+    let decoder = HdrDecoder::new(reader).map_err(bad_image)?;
 
     let meta_data = decoder.metadata();
     let width = meta_data.width;
     let height = meta_data.height;
     let dimensions = (width, height);
 
-    let pixels_rgb = decoder
-        .read_image_hdr()
-        .or_else(|_| return Err(Error::new(ErrorKind::NotFound, "Failed to read hdr image")))?;
+    let pixels_rgb = decoder.read_image_hdr().map_err(bad_image)?;
 
-    let mut pixels_data = Vec::with_capacity(pixels_rgb.len() * 3);
+    let pixels_data: Vec<f32> = pixels_rgb
+        .iter()
+        .flat_map(|pixel| pixel.0.iter().copied())
+        .collect();
 
-    println!("Starting long hdr load, copying pixels");
-    for i in 0..pixels_rgb.len() {
-        pixels_data.push(pixels_rgb[i][0]);
-        pixels_data.push(pixels_rgb[i][1]);
-        pixels_data.push(pixels_rgb[i][2]);
-    }
-    println!("Finished long hdr loading");
-
-    println!("{:?}", dimensions);
     Ok(RawImage2d::from_raw_rgb_reversed(&pixels_data, dimensions))
 }
 
-fn load_rawimage_hdr<P>(image_path: P) -> RawImage2d<'static, f32>
-where
-    P: AsRef<Path>,
-{
-    match try_load_rawimage_hdr(image_path) {
-        Ok(image) => image,
-        Err(_) => try_load_rawimage_hdr(MISSING_TEXTURE_PATH).unwrap(),
-    }
-}
-
-fn load_hdr_texture<F: ?Sized, P>(facade: &F, image_path: P) -> Texture2d
+fn load_hdr_texture<F: ?Sized>(facade: &F, image: RawImage2d<'static, f32>) -> Result<Texture2d>
 where
     F: Facade,
-    P: AsRef<Path>,
 {
-    let image = load_rawimage_hdr(image_path);
-    Texture2d::with_format(
+    let texture = Texture2d::with_format(
         facade,
         image,
         UncompressedFloatFormat::F32F32F32,
         MipmapsOption::NoMipmap,
-    )
-    .unwrap()
+    )?;
+    Ok(texture)
 }
 
-fn try_load_rawimage_rgba<P>(image_path: P) -> Result<RawImage2d<'static, u8>>
+fn try_load_image_rgba<P>(image_path: P) -> Result<RgbaImage>
 where
     P: AsRef<Path>,
 {
-    let mut reader = Reader::open(&image_path)?;
+    // Texture files are decoded on the CPU, losing their block compression and mip levels
+    if is_texture_file(&image_path) {
+        let file = decode_texture_file(&image_path)?;
+        let level = &file.levels[0];
+        let pixels =
+            compressed::decode_rgba8(file.format, &level.faces[0], level.width, level.height);
+        return Ok(RgbaImage::from_raw(level.width, level.height, pixels)
+            .expect("decoded levels are width * height * 4 bytes"));
+    }
+
+    let missing_file = |error| ImportError::MissingFile(image_path.as_ref().to_path_buf(), error);
+
+    let mut reader = Reader::open(&image_path).map_err(missing_file)?;
     if let Some(os_extension) = image_path.as_ref().extension() {
         if let Some(extension) = os_extension.to_str() {
             match extension {
@@ -228,321 +611,1726 @@ where
                     reader.set_format(ImageFormat::Bmp);
                 }
                 _ => {
-                    reader = reader.with_guessed_format()?;
+                    reader = reader.with_guessed_format().map_err(missing_file)?;
                 }
             }
         } else {
-            reader = reader.with_guessed_format()?
+            reader = reader.with_guessed_format().map_err(missing_file)?
         }
     } else {
-        reader = reader.with_guessed_format()?
+        reader = reader.with_guessed_format().map_err(missing_file)?
     };
 
     let image = reader
         .decode()
-        .or_else(|_| {
-            return Err(Error::new(
-                ErrorKind::NotFound,
-                "Failed to load raw rgba image",
-            ));
+        .map_err(|error| ImportError::BadImage {
+            image: image_path.as_ref().display().to_string(),
+            error,
         })?
         .into_rgba8();
-    let dimensions = image.dimensions();
-
-    println!("{:?}", dimensions);
-    Ok(RawImage2d::from_raw_rgba(image.into_raw(), dimensions))
+    Ok(image)
 }
 
-fn load_rawimage_rgba<P>(image_path: P) -> RawImage2d<'static, u8>
+// Decodes an image file, or the missing texture if it can't be read or decoded, so one bad image
+// doesn't fail a whole import
+fn load_image_rgba<P>(image_path: P) -> Result<RgbaImage>
 where
     P: AsRef<Path>,
 {
-    match try_load_rawimage_rgba(image_path) {
-        Ok(image) => image,
-        Err(_) => try_load_rawimage_rgba(MISSING_TEXTURE_PATH).unwrap(),
-    }
+    try_load_image_rgba(image_path).or_else(|error| {
+        eprintln!("{}, using the missing texture instead", error);
+        try_load_image_rgba(MISSING_TEXTURE_PATH)
+    })
 }
 
-fn load_srgb_texture<F: ?Sized, P>(facade: &F, image_path: P) -> SrgbTexture2d
+/// Decodes an image file into rgba without touching GL, so it can be done on a worker thread.
+pub fn try_load_rawimage_rgba<P>(image_path: P) -> Result<RawImage2d<'static, u8>>
 where
-    F: Facade,
     P: AsRef<Path>,
 {
-    let image = load_rawimage_rgba(image_path);
-    SrgbTexture2d::new(facade, image).unwrap()
+    let image = try_load_image_rgba(image_path)?;
+    let dimensions = image.dimensions();
+    Ok(RawImage2d::from_raw_rgba(image.into_raw(), dimensions))
+}
+
+fn try_load_rawimage_rgba_from_memory(
+    bytes: &[u8],
+    image_name: String,
+) -> Result<RawImage2d<'static, u8>> {
+    let image = image::load_from_memory(bytes)
+        .map_err(|error| ImportError::BadImage {
+            image: image_name,
+            error,
+        })?
+        .into_rgba8();
+    let dimensions = image.dimensions();
+
+    Ok(RawImage2d::from_raw_rgba(image.into_raw(), dimensions))
 }
 
-fn load_missing_srgb_texture<F: ?Sized>(facade: &F) -> SrgbTexture2d
+// Used to decode a gltf image from a file, data uri or buffer view
+fn load_gltf_rawimage_rgba<P>(
+    image: GltfImage,
+    image_location: P,
+    buffers: &[&[u8]],
+) -> Result<RawImage2d<'static, u8>>
 where
-    F: Facade,
+    P: AsRef<Path>,
 {
-    load_srgb_texture(facade, MISSING_TEXTURE_PATH)
+    let image_name = match (image.name(), image.source()) {
+        (Some(name), _) => name.to_string(),
+        (None, ImageSource::Uri { uri, .. }) if !uri.starts_with("data:") => uri.to_string(),
+        (None, _) => format!("#{}", image.index()),
+    };
+    let bytes = load_image_bytes(&image, image_location, buffers)?;
+    try_load_rawimage_rgba_from_memory(&bytes, image_name)
+}
+
+// Borrows a decoded image for uploading, so an image used by several materials is decoded once
+fn borrow_rawimage<'a>(image: &'a RawImage2d<'static, u8>) -> RawImage2d<'a, u8> {
+    RawImage2d {
+        data: Cow::Borrowed(image.data.as_ref()),
+        width: image.width,
+        height: image.height,
+        format: image.format,
+    }
 }
 
-fn load_rgba_texture<F: ?Sized, P>(facade: &F, image_path: P) -> Texture2d
+pub fn load_srgb_texture<F: ?Sized, P>(facade: &F, image_path: P) -> Result<SrgbTexture2d>
 where
     F: Facade,
     P: AsRef<Path>,
 {
-    let image = load_rawimage_rgba(image_path);
-    Texture2d::new(facade, image).unwrap()
+    let image = try_load_rawimage_rgba(image_path)?;
+    Ok(SrgbTexture2d::new(facade, image)?)
 }
 
-fn load_missing_rgba_texture<F: ?Sized>(facade: &F) -> Texture2d
+pub fn load_rgba_texture<F: ?Sized, P>(facade: &F, image_path: P) -> Result<Texture2d>
 where
     F: Facade,
+    P: AsRef<Path>,
 {
-    load_rgba_texture(facade, MISSING_TEXTURE_PATH)
+    let image = try_load_rawimage_rgba(image_path)?;
+    Ok(Texture2d::new(facade, image)?)
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .and_then(|path_extension| path_extension.to_str())
+        .is_some_and(|path_extension| path_extension.eq_ignore_ascii_case(extension))
 }
 
-fn load_missing_material<F: ?Sized>(facade: &F) -> InternalMaterial
+/// Whether a file is a .dds or .ktx2 texture, read with `decode_texture_file` rather than through
+/// `image`.
+pub fn is_texture_file<P>(path: P) -> bool
 where
-    F: Facade,
+    P: AsRef<Path>,
 {
-    let diffuse_map = load_missing_srgb_texture(facade);
-    let orm_map = load_missing_rgba_texture(facade);
-    let normal_map = load_missing_rgba_texture(facade);
-    InternalMaterial::new(diffuse_map, orm_map, normal_map)
+    has_extension(path.as_ref(), "dds") || has_extension(path.as_ref(), "ktx2")
 }
 
-pub fn load_material<F: ?Sized, P>(
-    facade: &F,
-    material: GltfMaterial,
-    material_location: P,
-) -> InternalMaterial
+/// Reads a .dds or .ktx2 file without touching GL, so it can be done on a worker thread.
+pub fn decode_texture_file<P>(path: P) -> Result<TextureFile>
 where
-    F: Facade,
     P: AsRef<Path>,
 {
-    let diffuse_map_source = match material.pbr_metallic_roughness().base_color_texture() {
-        Some(texture) => texture,
-        None => return load_missing_material(facade),
+    let bytes = read_file(&path)?;
+    let file = path.as_ref().to_path_buf();
+    if has_extension(path.as_ref(), "dds") {
+        dds::parse_dds(&bytes).map_err(|error| ImportError::Dds { file, error })
+    } else {
+        ktx2::parse_ktx2(&bytes).map_err(|error| ImportError::Ktx2 { file, error })
     }
-    .texture()
-    .source()
-    .source();
+}
 
-    let diffuse_map = if let ImageSource::Uri { uri: path, .. } = diffuse_map_source {
-        load_srgb_texture(facade, material_location.as_ref().join(path))
-    } else {
-        return load_missing_material(facade);
-    };
+fn compressed_format(format: TextureFormat) -> Option<CompressedFormat> {
+    Some(match format {
+        TextureFormat::Rgba8 => return None,
+        TextureFormat::Bc1 => CompressedFormat::S3tcDxt1Alpha,
+        TextureFormat::Bc2 => CompressedFormat::S3tcDxt3Alpha,
+        TextureFormat::Bc3 => CompressedFormat::S3tcDxt5Alpha,
+        TextureFormat::Bc4 => CompressedFormat::RgtcFormatU,
+        TextureFormat::Bc5 => CompressedFormat::RgtcFormatUU,
+        TextureFormat::Bc6hUnsigned => CompressedFormat::BptcUnsignedFloat3,
+        TextureFormat::Bc6hSigned => CompressedFormat::BptcSignedFloat3,
+        TextureFormat::Bc7 => CompressedFormat::BptcUnorm4,
+    })
+}
 
-    let orm_map_source = match material
-        .pbr_metallic_roughness()
-        .metallic_roughness_texture()
-    {
-        Some(texture) => texture,
-        None => return load_missing_material(facade),
+// Only the colour formats have srgb variants
+fn compressed_srgb_format(format: TextureFormat) -> Option<CompressedSrgbFormat> {
+    match format {
+        TextureFormat::Bc1 => Some(CompressedSrgbFormat::S3tcDxt1Alpha),
+        TextureFormat::Bc2 => Some(CompressedSrgbFormat::S3tcDxt3Alpha),
+        TextureFormat::Bc3 => Some(CompressedSrgbFormat::S3tcDxt5Alpha),
+        TextureFormat::Bc7 => Some(CompressedSrgbFormat::Bptc),
+        _ => None,
     }
-    .texture()
-    .source()
-    .source();
+}
 
-    let orm_map = if let ImageSource::Uri { uri: path, .. } = orm_map_source {
-        load_rgba_texture(facade, material_location.as_ref().join(path))
-    } else {
-        return load_missing_material(facade);
-    };
+fn level_rect(level: &TextureLevel) -> Rect {
+    Rect {
+        left: 0,
+        bottom: 0,
+        width: level.width,
+        height: level.height,
+    }
+}
 
-    let normal_map_source = match material.normal_texture() {
-        Some(texture) => texture,
-        None => return load_missing_material(facade),
+fn compressed_mipmaps(file: &TextureFile) -> CompressedMipmapsOption {
+    match file.levels.len() {
+        1 => CompressedMipmapsOption::NoMipmap,
+        count => CompressedMipmapsOption::EmptyMipmapsMax(count as u32 - 1),
     }
-    .texture()
-    .source()
-    .source();
+}
 
-    let normal_map = if let ImageSource::Uri { uri: path, .. } = normal_map_source {
-        load_rgba_texture(facade, material_location.as_ref().join(path))
-    } else {
-        return load_missing_material(facade);
-    };
+// Files without mip levels get them generated, the others keep their own
+fn uncompressed_mipmaps(file: &TextureFile) -> MipmapsOption {
+    match file.levels.len() {
+        1 => MipmapsOption::AutoGeneratedMipmaps,
+        count => MipmapsOption::EmptyMipmapsMax(count as u32 - 1),
+    }
+}
 
-    InternalMaterial::new(diffuse_map, orm_map, normal_map)
+// Decodes every level into 8 bit rgba, for formats the driver can't sample
+fn decode_levels(file: &TextureFile) -> Vec<RawImage2d<'static, u8>> {
+    file.levels
+        .iter()
+        .map(|level| {
+            let pixels =
+                compressed::decode_rgba8(file.format, &level.faces[0], level.width, level.height);
+            RawImage2d::from_raw_rgba(pixels, (level.width, level.height))
+        })
+        .collect()
 }
 
-pub fn model_from_gltf<F: ?Sized, P>(
+/// Uploads a texture file for an srgb material slot. Block compressed formats the driver supports
+/// stay compressed, the rest is decoded on the CPU. Compressed files without mip levels are
+/// sampled without them, as GL can't generate them for compressed textures.
+pub fn upload_srgb_texture_file<F: ?Sized>(
     facade: &F,
-    rs: &mut RendererState,
-    path: P,
-) -> Result<ModelHandle>
+    file: &TextureFile,
+) -> Result<MaterialSrgbTexture>
 where
     F: Facade,
-    P: AsRef<Path> + Debug,
 {
-    let gltf_path = path
-        .as_ref()
-        .join(path.as_ref().file_stem().unwrap().to_str().unwrap())
-        .with_extension("gltf");
+    if let Some(format) = compressed_srgb_format(file.format) {
+        if format.is_supported(&**facade.get_context()) {
+            let texture = CompressedSrgbTexture2d::with_compressed_data(
+                facade,
+                &file.levels[0].faces[0],
+                file.width(),
+                file.height(),
+                format,
+                compressed_mipmaps(file),
+            )?;
+            for (i, level) in file.levels.iter().enumerate().skip(1) {
+                texture
+                    .mipmap(i as u32)
+                    .unwrap()
+                    .write_compressed_data(
+                        level_rect(level),
+                        &level.faces[0],
+                        level.width,
+                        level.height,
+                        format,
+                    )
+                    .map_err(|_| TextureCreationError::DimensionsNotSupported)?;
+            }
+            return Ok(MaterialSrgbTexture::Compressed(texture));
+        }
+    }
+
+    let mut images = decode_levels(file);
+    let mipmaps = uncompressed_mipmaps(file);
+    if images.len() == 1 {
+        let texture = SrgbTexture2d::with_mipmaps(facade, images.remove(0), mipmaps)?;
+        return Ok(MaterialSrgbTexture::Uncompressed(texture));
+    }
+    let texture = SrgbTexture2d::empty_with_mipmaps(facade, mipmaps, file.width(), file.height())?;
+    for ((i, image), level) in images.into_iter().enumerate().zip(file.levels.iter()) {
+        texture
+            .mipmap(i as u32)
+            .unwrap()
+            .write(level_rect(level), image);
+    }
+    Ok(MaterialSrgbTexture::Uncompressed(texture))
+}
 
-    println!("{:?}", gltf_path);
+/// Uploads a texture file for a linear material slot, see `upload_srgb_texture_file`.
+pub fn upload_texture_file<F: ?Sized>(facade: &F, file: &TextureFile) -> Result<MaterialTexture>
+where
+    F: Facade,
+{
+    if let Some(format) = compressed_format(file.format) {
+        if format.is_supported(&**facade.get_context()) {
+            let texture = CompressedTexture2d::with_compressed_data(
+                facade,
+                &file.levels[0].faces[0],
+                file.width(),
+                file.height(),
+                format,
+                compressed_mipmaps(file),
+            )?;
+            for (i, level) in file.levels.iter().enumerate().skip(1) {
+                texture
+                    .mipmap(i as u32)
+                    .unwrap()
+                    .write_compressed_data(
+                        level_rect(level),
+                        &level.faces[0],
+                        level.width,
+                        level.height,
+                        format,
+                    )
+                    .map_err(|_| TextureCreationError::DimensionsNotSupported)?;
+            }
+            return Ok(MaterialTexture::Compressed(texture));
+        }
+    }
 
-    let gltf = Gltf::open(gltf_path).expect("Failed to load AKM gltf file.");
+    let mut images = decode_levels(file);
+    let mipmaps = uncompressed_mipmaps(file);
+    if images.len() == 1 {
+        let texture = Texture2d::with_mipmaps(facade, images.remove(0), mipmaps)?;
+        return Ok(MaterialTexture::Uncompressed(texture));
+    }
+    let texture = Texture2d::empty_with_mipmaps(facade, mipmaps, file.width(), file.height())?;
+    for ((i, image), level) in images.into_iter().enumerate().zip(file.levels.iter()) {
+        texture
+            .mipmap(i as u32)
+            .unwrap()
+            .write(level_rect(level), image);
+    }
+    Ok(MaterialTexture::Uncompressed(texture))
+}
 
-    let buffers: Vec<Vec<u8>> = load_buffers(gltf.buffers(), &path);
-    let buffers: Vec<&[u8]> = buffers.iter().map(|buffer| buffer.as_slice()).collect();
-    let buffer_slices = buffers.as_slice();
+// The material gltf specifies for primitives without one, white and fully rough metal. Material
+// slots without a texture use 1x1 textures of the neutral colours, so the factors are used as is.
+fn load_default_material<F: ?Sized>(
+    facade: &F,
+    textures: &mut TextureCache,
+) -> Result<InternalMaterial>
+where
+    F: Facade,
+{
+    let diffuse_map = textures.solid_srgb(facade, NEUTRAL_DIFFUSE_COLOUR)?;
+    let metallic_roughness_map = textures.solid(facade, NEUTRAL_METALLIC_ROUGHNESS_COLOUR)?;
+    let occlusion_map = textures.solid(facade, NEUTRAL_OCCLUSION_COLOUR)?;
+    let normal_map = textures.solid(facade, NEUTRAL_NORMAL_COLOUR)?;
+    let emissive_map = textures.solid_srgb(facade, NEUTRAL_EMISSIVE_COLOUR)?;
+    Ok(InternalMaterial::new(
+        diffuse_map,
+        metallic_roughness_map,
+        occlusion_map,
+        normal_map,
+        emissive_map,
+    ))
+}
 
-    let mut meshes: Vec<Mesh> = Vec::new();
-    let mut materials: Vec<InternalMaterial> = Vec::new();
+// Uploads an image of a gltf document the first time a material uses it
+fn load_gltf_srgb_texture<F: ?Sized>(
+    facade: &F,
+    textures: &mut TextureCache,
+    data: &GltfData,
+    texture: GltfTexture,
+) -> Result<Rc<MaterialSrgbTexture>>
+where
+    F: Facade,
+{
+    let image = texture.source().index();
+    let source = data.image_sources[image].clone();
+    textures.srgb_texture(facade, source, || Ok(borrow_rawimage(&data.images[image])))
+}
+
+fn load_gltf_rgba_texture<F: ?Sized>(
+    facade: &F,
+    textures: &mut TextureCache,
+    data: &GltfData,
+    texture: GltfTexture,
+) -> Result<Rc<MaterialTexture>>
+where
+    F: Facade,
+{
+    let image = texture.source().index();
+    let source = data.image_sources[image].clone();
+    textures.texture(facade, source, || Ok(borrow_rawimage(&data.images[image])))
+}
+
+// Filters the sampler leaves undefined are left at the trilinear default
+fn load_sampler(sampler: GltfSampler) -> TextureSampler {
+    let wrap_function = |mode| match mode {
+        WrappingMode::ClampToEdge => SamplerWrapFunction::Clamp,
+        WrappingMode::MirroredRepeat => SamplerWrapFunction::Mirror,
+        WrappingMode::Repeat => SamplerWrapFunction::Repeat,
+    };
 
-    for material in gltf.materials() {
-        materials.push(load_material(facade, material, &path));
+    let default = TextureSampler::default();
+    TextureSampler {
+        wrap_s: wrap_function(sampler.wrap_s()),
+        wrap_t: wrap_function(sampler.wrap_t()),
+        minify_filter: sampler
+            .min_filter()
+            .map(|filter| match filter {
+                MinFilter::Nearest => MinifySamplerFilter::Nearest,
+                MinFilter::Linear => MinifySamplerFilter::Linear,
+                MinFilter::NearestMipmapNearest => MinifySamplerFilter::NearestMipmapNearest,
+                MinFilter::LinearMipmapNearest => MinifySamplerFilter::LinearMipmapNearest,
+                MinFilter::NearestMipmapLinear => MinifySamplerFilter::NearestMipmapLinear,
+                MinFilter::LinearMipmapLinear => MinifySamplerFilter::LinearMipmapLinear,
+            })
+            .unwrap_or(default.minify_filter),
+        magnify_filter: sampler
+            .mag_filter()
+            .map(|filter| match filter {
+                MagFilter::Nearest => MagnifySamplerFilter::Nearest,
+                MagFilter::Linear => MagnifySamplerFilter::Linear,
+            })
+            .unwrap_or(default.magnify_filter),
     }
+}
 
-    let scene = gltf.default_scene().unwrap();
-    //        let scene = gltf.default_scene().unwrap_or_else(|| {
-    //            Err(Error::new(ErrorKind::NotFound, "Failed to unwrap scene from gltf."))
-    //        });
+/// Uploads a material of a decoded gltf file, images shared with materials loaded before through
+/// the same cache are reused.
+pub fn load_material<F: ?Sized>(
+    facade: &F,
+    textures: &mut TextureCache,
+    material: GltfMaterial,
+    data: &GltfData,
+) -> Result<InternalMaterial>
+where
+    F: Facade,
+{
+    let pbr_metallic_roughness = material.pbr_metallic_roughness();
 
-    for node in scene.nodes() {
-        let mesh = node.mesh().expect("Failed to unwrap mesh from node.");
-        let transformation_matrix = node.transform();
-        let (mesh_isometry, mesh_scaling) = match transformation_matrix {
-            Transform::Matrix { matrix } => {
-                let position = Translation3::new(matrix[3][0], matrix[3][1], matrix[3][2]);
-                //TODO check that the rotation matrix created is done properly
-                let rotation = UnitQuaternion::from_matrix(&Matrix3::new(
-                    matrix[0][0],
-                    matrix[0][1],
-                    matrix[0][2],
-                    matrix[1][0],
-                    matrix[1][1],
-                    matrix[1][2],
-                    matrix[2][0],
-                    matrix[2][1],
-                    matrix[2][2],
-                ));
+    let diffuse_map = match pbr_metallic_roughness.base_color_texture() {
+        Some(texture) => load_gltf_srgb_texture(facade, textures, data, texture.texture())?,
+        None => textures.solid_srgb(facade, NEUTRAL_DIFFUSE_COLOUR)?,
+    };
 
-                let isometry = Isometry3::from_parts(position, rotation);
-                let scaling = Vector3::from_element(1.0);
-                (isometry, scaling)
-            }
-            Transform::Decomposed {
-                translation,
-                rotation,
-                scale,
-            } => {
-                let translation_vec = Vector3::new(translation[0], translation[1], translation[2]);
-                let rotation_vec = Vector3::new(rotation[0], rotation[1], rotation[2]);
-                let scale_vec = Vector3::new(scale[0], scale[1], scale[2]);
-
-                let quaternion = UnitQuaternion::from_quaternion(Quaternion::from_parts(
-                    rotation[3],
-                    rotation_vec,
-                ));
+    let metallic_roughness_texture = pbr_metallic_roughness.metallic_roughness_texture();
+    let metallic_roughness_map = match &metallic_roughness_texture {
+        Some(texture) => load_gltf_rgba_texture(facade, textures, data, texture.texture())?,
+        None => textures.solid(facade, NEUTRAL_METALLIC_ROUGHNESS_COLOUR)?,
+    };
 
-                let axis_angle = match quaternion.axis() {
-                    Some(axis_angle) => axis_angle.into_inner(),
-                    None => Vector3::new(0.0, 0.0, 0.0),
-                };
+    // Exporters commonly pack occlusion into the red channel of the metallic roughness image,
+    // in which case the image is sampled once instead of being loaded twice
+    let metallic_roughness_image = metallic_roughness_texture
+        .as_ref()
+        .map(|texture| texture.texture().source().index());
+    let occlusion_image = material
+        .occlusion_texture()
+        .map(|texture| texture.texture().source().index());
+    let packed_occlusion = occlusion_image.is_some() && occlusion_image == metallic_roughness_image;
+
+    let occlusion_map = match material.occlusion_texture() {
+        Some(texture) if !packed_occlusion => {
+            load_gltf_rgba_texture(facade, textures, data, texture.texture())?
+        }
+        _ => textures.solid(facade, NEUTRAL_OCCLUSION_COLOUR)?,
+    };
 
-                let isometry = Isometry3::new(translation_vec, axis_angle);
-                let scaling = scale_vec;
+    let normal_map = match material.normal_texture() {
+        Some(texture) => load_gltf_rgba_texture(facade, textures, data, texture.texture())?,
+        None => textures.solid(facade, NEUTRAL_NORMAL_COLOUR)?,
+    };
 
-                (isometry, scaling)
-            }
-        };
-        let mut primitives: Vec<Primitive> = Vec::new();
+    let emissive_map = match material.emissive_texture() {
+        Some(texture) => load_gltf_srgb_texture(facade, textures, data, texture.texture())?,
+        None => textures.solid_srgb(facade, NEUTRAL_EMISSIVE_COLOUR)?,
+    };
+
+    let mut internal_material = InternalMaterial::new(
+        diffuse_map,
+        metallic_roughness_map,
+        occlusion_map,
+        normal_map,
+        emissive_map,
+    );
+    internal_material.packed_occlusion = packed_occlusion;
+    internal_material.base_colour_factor = pbr_metallic_roughness.base_color_factor();
+    internal_material.metallic_factor = pbr_metallic_roughness.metallic_factor();
+    internal_material.roughness_factor = pbr_metallic_roughness.roughness_factor();
+    if let Some(normal_texture) = material.normal_texture() {
+        internal_material.normal_scale = normal_texture.scale();
+    }
+    if let Some(occlusion_texture) = material.occlusion_texture() {
+        internal_material.occlusion_strength = occlusion_texture.strength();
+    }
+    internal_material.emissive_factor = material.emissive_factor();
+    internal_material.alpha_mode = match material.alpha_mode() {
+        GltfAlphaMode::Opaque => AlphaMode::Opaque,
+        GltfAlphaMode::Mask => AlphaMode::Mask,
+        GltfAlphaMode::Blend => AlphaMode::Blend,
+    };
+    internal_material.alpha_cutoff = material.alpha_cutoff();
+    internal_material.double_sided = material.double_sided();
+
+    // Only two uv sets are imported, textures using any other set fall back to the first
+    let uv_set = |tex_coord: u32| if tex_coord == 1 { 1 } else { 0 };
+    if let Some(texture) = pbr_metallic_roughness.base_color_texture() {
+        internal_material.diffuse_uv_set = uv_set(texture.tex_coord());
+        internal_material.diffuse_sampler = load_sampler(texture.texture().sampler());
+    }
+    if let Some(texture) = &metallic_roughness_texture {
+        internal_material.metallic_roughness_uv_set = uv_set(texture.tex_coord());
+        internal_material.metallic_roughness_sampler = load_sampler(texture.texture().sampler());
+    }
+    if let Some(texture) = material.occlusion_texture() {
+        internal_material.occlusion_uv_set = uv_set(texture.tex_coord());
+        internal_material.occlusion_sampler = load_sampler(texture.texture().sampler());
+    }
+    if let Some(texture) = material.normal_texture() {
+        internal_material.normal_uv_set = uv_set(texture.tex_coord());
+        internal_material.normal_sampler = load_sampler(texture.texture().sampler());
+    }
+    if let Some(texture) = material.emissive_texture() {
+        internal_material.emissive_uv_set = uv_set(texture.tex_coord());
+        internal_material.emissive_sampler = load_sampler(texture.texture().sampler());
+    }
 
-        for primitive in mesh.primitives() {
-            let indices_accessor = primitive
-                .indices()
-                .expect("Failed to unwrap indices from primitive.");
+    Ok(internal_material)
+}
 
-            let indices: Vec<u32> = load_indices::<u32>(indices_accessor, buffer_slices);
+// Parses the json of a .gltf or .glb file without the gltf crate, which drops the extensions it
+// doesn't know about
+fn load_raw_json(gltf_bytes: &[u8]) -> Result<JsonValue> {
+    let json_bytes = if gltf_bytes.starts_with(b"glTF") {
+        Glb::from_slice(gltf_bytes)?.json.into_owned()
+    } else {
+        gltf_bytes.to_vec()
+    };
+    gltf::json::deserialize::from_slice(&json_bytes)
+        .map_err(|error| ImportError::Gltf(gltf::Error::Deserialize(error)))
+}
 
-            let mut vertices: Vec<Vertex> = Vec::new();
+// Reads KHR_materials_emissive_strength, which multiplies the emissive factor
+fn load_emissive_strength(raw_json: &JsonValue, material_index: usize) -> f32 {
+    raw_json["materials"][material_index]["extensions"]["KHR_materials_emissive_strength"]
+        ["emissiveStrength"]
+        .as_f64()
+        .unwrap_or(1.0) as f32
+}
 
-            let mut vertex_positions: Vec<[f32; 3]> = Vec::with_capacity(indices.len());
-            let mut vertex_texture_coords: Vec<[f32; 2]> = Vec::with_capacity(indices.len());
-            let mut vertex_normals: Vec<[f32; 3]> = Vec::with_capacity(indices.len());
-            let mut vertex_tangents: Vec<[f32; 3]> = Vec::with_capacity(indices.len());
+/// A gltf file with its buffers read and its images decoded, waiting to be uploaded by
+/// `model_from_gltf_data`. Nothing here touches GL, so it can be built on a worker thread.
+pub struct GltfData {
+    /// The canonical path of the .gltf or .glb file.
+    pub path: PathBuf,
+    pub gltf: Gltf,
+    /// The json as written, for the extensions the gltf crate drops.
+    pub raw_json: JsonValue,
+    pub buffers: Vec<Vec<u8>>,
+    /// Indexed like the images of the document.
+    pub images: Vec<RawImage2d<'static, u8>>,
+    /// Identifies each image in a `TextureCache`. Images referred to by uri are keyed on the file,
+    /// so they are shared with other documents and .obj materials using the same file.
+    pub image_sources: Vec<TextureSource>,
+}
 
-            for attribute in primitive.attributes() {
-                let vertex_attribute = attribute.0;
-                let accessor = attribute.1;
+impl GltfData {
+    fn buffer_slices(&self) -> Vec<&[u8]> {
+        self.buffers
+            .iter()
+            .map(|buffer| buffer.as_slice())
+            .collect()
+    }
+}
 
-                match vertex_attribute {
-                    Semantic::Positions => {
-                        vertex_positions = load_3d_array::<f32>(accessor, &buffer_slices);
-                    }
-                    Semantic::Normals => {
-                        vertex_normals = load_3d_array::<f32>(accessor, &buffer_slices);
-                    }
-                    Semantic::TexCoords(0) => {
-                        vertex_texture_coords = load_2d_array::<f32>(accessor, &buffer_slices);
-                        vertex_texture_coords = vertex_texture_coords
-                            .into_iter()
-                            .map(|mut texture_coord| {
-                                let u = texture_coord[0];
-                                let v = texture_coord[1];
-
-                                if u > 1f32 {
-                                    texture_coord[0] = u - u.floor();
-                                } else if u < 0f32 {
-                                    texture_coord[0] = 1f32 - (u.abs() - u.abs().floor());
-                                }
-
-                                if v > 1f32 {
-                                    texture_coord[1] = v - v.floor();
-                                } else if v < 0f32 {
-                                    texture_coord[1] = 1f32 - (v.abs() - v.abs().floor());
-                                }
-                                texture_coord
-                            })
-                            .collect();
-                    }
-                    _ => (),
-                }
+/// Reads a .gltf or .glb file along with its buffers and decodes all of its images. Images which
+/// can't be read or decoded are replaced by the missing texture.
+pub fn decode_gltf<P>(path: P) -> Result<GltfData>
+where
+    P: AsRef<Path>,
+{
+    let (gltf_path, base_path) = resolve_gltf_path(&path);
+
+    let gltf_bytes = read_file(&gltf_path)?;
+    let mut gltf = Gltf::from_slice(&gltf_bytes)?;
+    let raw_json = load_raw_json(&gltf_bytes)?;
+    let blob = gltf.blob.take();
+
+    let buffers: Vec<Vec<u8>> = load_buffers(gltf.buffers(), blob, &base_path)?;
+    let buffer_slices: Vec<&[u8]> = buffers.iter().map(|buffer| buffer.as_slice()).collect();
+    let document = texture_cache::canonical_path(&gltf_path);
+
+    let mut images = Vec::new();
+    let mut image_sources = Vec::new();
+    for image in gltf.images() {
+        let source = match image.source() {
+            ImageSource::Uri { uri, .. } if !uri.starts_with("data:") => {
+                TextureSource::file(base_path.join(uri))
             }
-
-            // Calculating tangents
-            println!("{:?}", indices.len());
-            for i in 0..indices.len() / 3 {
-                let index1 = indices[i * 3] as usize;
-                let index2 = indices[i * 3 + 1] as usize;
-                let index3 = indices[i * 3 + 2] as usize;
-
-                let position1 = vertex_positions[index1];
-                let position2 = vertex_positions[index2];
-                let position3 = vertex_positions[index3];
-
-                let texture_coord1 = vertex_texture_coords[index1];
-                let texture_coord2 = vertex_texture_coords[index2];
-                let texture_coord3 = vertex_texture_coords[index3];
-
-                let tangent = vertex::calculate_tangent(
-                    position1,
-                    position2,
-                    position3,
-                    texture_coord1,
-                    texture_coord2,
-                    texture_coord3,
-                );
-                vertex_tangents.push(tangent);
+            _ => TextureSource::GltfImage {
+                document: document.clone(),
+                image: image.index(),
+            },
+        };
+        match load_gltf_rawimage_rgba(image, &base_path, &buffer_slices) {
+            Ok(decoded) => {
+                images.push(decoded);
+                image_sources.push(source);
             }
-
-            for i in 0..vertex_positions.len() {
-                vertices.push(Vertex::new(
-                    vertex_positions[i],
-                    vertex_texture_coords[i],
-                    vertex_normals[i],
-                    vertex_tangents[i / 3],
-                ));
+            Err(error) => {
+                eprintln!("{}, using the missing texture instead", error);
+                images.push(try_load_rawimage_rgba(MISSING_TEXTURE_PATH)?);
+                image_sources.push(TextureSource::file(MISSING_TEXTURE_PATH));
             }
-
-            let material_index = primitive.material().index().unwrap();
-            let primitive = Primitive::new(facade, vertices, indices, material_index);
-            primitives.push(primitive);
         }
-        meshes.push(Mesh::new(primitives, mesh_isometry, mesh_scaling));
     }
-    let model = Model::new(meshes, materials);
-    let model_handle = rs.push_model(model);
-    Ok(model_handle)
-    //    Err(Error::new(ErrorKind::NotFound, "Failed to load gltf file."))
+
+    Ok(GltfData {
+        path: document,
+        gltf,
+        raw_json,
+        buffers,
+        images,
+        image_sources,
+    })
+}
+
+/// Uploads one material of a decoded gltf file.
+pub fn load_gltf_material<F: ?Sized>(
+    facade: &F,
+    textures: &mut TextureCache,
+    data: &GltfData,
+    material_index: usize,
+) -> Result<InternalMaterial>
+where
+    F: Facade,
+{
+    let material = data
+        .gltf
+        .materials()
+        .nth(material_index)
+        .ok_or(ImportError::MissingMaterial(material_index))?;
+    let mut internal_material = load_material(facade, textures, material, data)?;
+    internal_material.emissive_strength = load_emissive_strength(&data.raw_json, material_index);
+    Ok(internal_material)
+}
+
+// Accepts a .gltf or .glb file, or a directory containing a .gltf/.glb named after the directory.
+// Returns the path of the gltf file and the directory relative uris are resolved against.
+fn resolve_gltf_path<P>(path: P) -> (PathBuf, PathBuf)
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    match path.file_name() {
+        Some(file_stem) if path.is_dir() => {
+            let gltf_path = path.join(file_stem).with_extension("gltf");
+            let glb_path = gltf_path.with_extension("glb");
+            let file_path = if !gltf_path.is_file() && glb_path.is_file() {
+                glb_path
+            } else {
+                gltf_path
+            };
+            (file_path, path.to_path_buf())
+        }
+        _ => {
+            let base_path = path
+                .parent()
+                .map(|parent| parent.to_path_buf())
+                .unwrap_or_default();
+            (path.to_path_buf(), base_path)
+        }
+    }
+}
+
+fn load_primitive<F: ?Sized>(
+    facade: &F,
+    mesh_index: usize,
+    primitive: GltfPrimitive,
+    default_material_index: usize,
+    buffers: &[&[u8]],
+) -> Result<Primitive>
+where
+    F: Facade,
+{
+    let missing_attribute = |attribute: &str| ImportError::MissingAttribute {
+        mesh: mesh_index,
+        primitive: primitive.index(),
+        attribute: attribute.to_string(),
+    };
+
+    let mut vertices: Vec<Vertex> = Vec::new();
+
+    let mut vertex_positions: Vec<[f32; 3]> = Vec::new();
+    let mut vertex_texture_coords: Vec<[f32; 2]> = Vec::new();
+    let mut vertex_texture_coords_1: Vec<[f32; 2]> = Vec::new();
+    let mut vertex_normals: Vec<[f32; 3]> = Vec::new();
+    let mut vertex_tangents: Vec<[f32; 4]> = Vec::new();
+    let mut vertex_colours: Vec<[f32; 4]> = Vec::new();
+    let mut vertex_joints: Vec<[u32; 4]> = Vec::new();
+    let mut vertex_weights: Vec<[f32; 4]> = Vec::new();
+
+    for attribute in primitive.attributes() {
+        let vertex_attribute = attribute.0;
+        let accessor = attribute.1;
+
+        match vertex_attribute {
+            Semantic::Positions => {
+                vertex_positions = load_3d_array::<f32>(accessor, buffers)?;
+            }
+            Semantic::Normals => {
+                vertex_normals = load_3d_array::<f32>(accessor, buffers)?;
+            }
+            Semantic::Tangents => {
+                vertex_tangents = load_4d_array::<f32>(accessor, buffers)?;
+            }
+            // Uvs outside of [0, 1] are left alone, the samplers repeat
+            Semantic::TexCoords(0) => {
+                vertex_texture_coords = load_2d_array::<f32>(accessor, buffers)?;
+            }
+            Semantic::TexCoords(1) => {
+                vertex_texture_coords_1 = load_2d_array::<f32>(accessor, buffers)?;
+            }
+            // Colours are either rgb or rgba, rgb is opaque
+            Semantic::Colors(0) => {
+                vertex_colours = match accessor.dimensions() {
+                    Dimensions::Vec3 => load_3d_array::<f32>(accessor, buffers)?
+                        .into_iter()
+                        .map(|colour| [colour[0], colour[1], colour[2], 1.0])
+                        .collect(),
+                    _ => load_4d_array::<f32>(accessor, buffers)?,
+                };
+            }
+            Semantic::Joints(0) => {
+                let accessor_index = accessor.index();
+                vertex_joints = load_4d_array::<u32>(accessor, buffers)?;
+                if vertex_joints
+                    .iter()
+                    .flatten()
+                    .any(|joint| *joint as usize >= MAX_JOINT_COUNT)
+                {
+                    return Err(ImportError::UnsupportedAccessor {
+                        accessor: accessor_index,
+                        reason: "joint index is past the supported joint count".to_string(),
+                    });
+                }
+            }
+            Semantic::Weights(0) => {
+                vertex_weights = load_4d_array::<f32>(accessor, buffers)?;
+            }
+            _ => (),
+        }
+    }
+
+    if vertex_positions.is_empty() {
+        return Err(missing_attribute("POSITION"));
+    }
+
+    // Non indexed primitives draw every vertex in order
+    let mut indices: Vec<u32> = match primitive.indices() {
+        Some(indices_accessor) => {
+            let indices_accessor_index = indices_accessor.index();
+            let indices = load_indices::<u32>(indices_accessor, buffers)?;
+            if indices
+                .iter()
+                .any(|index| *index as usize >= vertex_positions.len())
+            {
+                return Err(ImportError::UnsupportedAccessor {
+                    accessor: indices_accessor_index,
+                    reason: "index refers to a vertex which does not exist".to_string(),
+                });
+            }
+            indices
+        }
+        None => (0..vertex_positions.len() as u32).collect(),
+    };
+
+    // Strips and fans are converted to lists, so everything with a surface can be treated the same
+    let primitive_type = match primitive.mode() {
+        Mode::Points => PrimitiveType::Points,
+        Mode::Lines => PrimitiveType::LinesList,
+        Mode::LineLoop => PrimitiveType::LineLoop,
+        Mode::LineStrip => PrimitiveType::LineStrip,
+        Mode::Triangles => PrimitiveType::TrianglesList,
+        Mode::TriangleStrip => {
+            indices = primitive::triangle_strip_to_list(&indices);
+            PrimitiveType::TrianglesList
+        }
+        Mode::TriangleFan => {
+            indices = primitive::triangle_fan_to_list(&indices);
+            PrimitiveType::TrianglesList
+        }
+    };
+    let triangle_indices: &[u32] = if primitive_type == PrimitiveType::TrianglesList {
+        &indices
+    } else {
+        &[]
+    };
+
+    if vertex_normals.len() != vertex_positions.len() {
+        vertex_normals = vertex::calculate_normals(&vertex_positions, triangle_indices);
+    }
+    // Without texture coordinates every vertex samples the same texel
+    if vertex_texture_coords.len() != vertex_positions.len() {
+        vertex_texture_coords = vec![[0.0, 0.0]; vertex_positions.len()];
+    }
+    if vertex_texture_coords_1.len() != vertex_positions.len() {
+        vertex_texture_coords_1 = vertex_texture_coords.clone();
+    }
+    // White vertex colours leave the base colour unchanged
+    if vertex_colours.len() != vertex_positions.len() {
+        vertex_colours = vec![[1.0, 1.0, 1.0, 1.0]; vertex_positions.len()];
+    }
+
+    // The tangent space has to follow the uvs the normal map is sampled with
+    let normal_uv_set = primitive
+        .material()
+        .normal_texture()
+        .map(|texture| texture.tex_coord())
+        .unwrap_or(0);
+    let tangent_texture_coords = if normal_uv_set == 1 {
+        &vertex_texture_coords_1
+    } else {
+        &vertex_texture_coords
+    };
+
+    // Tangents are only generated when the asset doesn't provide them
+    if vertex_tangents.len() != vertex_positions.len() {
+        vertex_tangents = vertex::calculate_tangents(
+            &vertex_positions,
+            tangent_texture_coords,
+            &vertex_normals,
+            triangle_indices,
+        );
+    }
+
+    for i in 0..vertex_positions.len() {
+        vertices.push(Vertex::new(
+            vertex_positions[i],
+            vertex_texture_coords[i],
+            vertex_texture_coords_1[i],
+            vertex_normals[i],
+            vertex_tangents[i],
+            vertex_colours[i],
+        ));
+    }
+
+    let material_index = primitive
+        .material()
+        .index()
+        .unwrap_or(default_material_index);
+    let mut internal_primitive =
+        Primitive::new(facade, vertices, indices, primitive_type, material_index);
+
+    // Morph targets only store offsets for the attributes they move
+    for gltf_morph_target in primitive.morph_targets() {
+        let load_deltas = |accessor: Option<Accessor>| -> Result<Vec<[f32; 3]>> {
+            let accessor = match accessor {
+                Some(accessor) => accessor,
+                None => return Ok(Vec::new()),
+            };
+            let accessor_index = accessor.index();
+            let deltas = load_3d_array::<f32>(accessor, buffers)?;
+            if deltas.len() != vertex_positions.len() {
+                return Err(ImportError::UnsupportedAccessor {
+                    accessor: accessor_index,
+                    reason: "morph target doesn't match the vertex count".to_string(),
+                });
+            }
+            Ok(deltas)
+        };
+        let positions = load_deltas(gltf_morph_target.positions())?;
+        let normals = load_deltas(gltf_morph_target.normals())?;
+        let tangents = load_deltas(gltf_morph_target.tangents())?;
+        internal_primitive
+            .morph_targets
+            .push(MorphTarget::new(positions, normals, tangents));
+    }
+
+    // Only the first set of joints and weights is used, limiting each vertex to four joints
+    if vertex_joints.len() == vertex_positions.len()
+        && vertex_weights.len() == vertex_positions.len()
+    {
+        let skin_vertices = vertex_joints
+            .into_iter()
+            .zip(vertex_weights.into_iter())
+            .map(|(joints, weights)| {
+                // Quantized weights don't always add up to exactly one
+                let total: f32 = weights.iter().sum();
+                let weights = if total > 0.0 {
+                    [
+                        weights[0] / total,
+                        weights[1] / total,
+                        weights[2] / total,
+                        weights[3] / total,
+                    ]
+                } else {
+                    [1.0, 0.0, 0.0, 0.0]
+                };
+                SkinVertex::new(joints, weights)
+            })
+            .collect();
+        internal_primitive.set_skin_vertices(facade, skin_vertices);
+    }
+
+    Ok(internal_primitive)
+}
+
+// Creates an internal node for every gltf node, linking parents and children by index
+fn load_nodes(gltf: &Gltf) -> Vec<Node> {
+    let mut nodes: Vec<Node> = gltf
+        .nodes()
+        .map(|node| {
+            let (translation, rotation, scale) = node.transform().decomposed();
+            let mut internal_node = Node::new(
+                node.name().map(|name| name.to_string()),
+                Vector3::from(translation),
+                UnitQuaternion::from_quaternion(Quaternion::new(
+                    rotation[3],
+                    rotation[0],
+                    rotation[1],
+                    rotation[2],
+                )),
+                Vector3::from(scale),
+            );
+            // Weights of the node take precedence over the default weights of its mesh
+            if let Some(weights) = node
+                .weights()
+                .or_else(|| node.mesh().and_then(|mesh| mesh.weights()))
+            {
+                internal_node.weights = weights.to_vec();
+            }
+            internal_node
+        })
+        .collect();
+
+    for node in gltf.nodes() {
+        for child in node.children() {
+            nodes[child.index()].parent = Some(node.index());
+            nodes[node.index()].children.push(child.index());
+        }
+    }
+
+    nodes
+}
+
+// Creates a skeleton for every gltf skin. Joints without an inverse bind matrix are bound with the
+// identity
+fn load_skeletons(gltf: &Gltf, buffers: &[&[u8]]) -> Result<Vec<Skeleton>> {
+    gltf.skins()
+        .map(|skin| {
+            let joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+            if joints.len() > MAX_JOINT_COUNT {
+                return Err(ImportError::TooManyJoints {
+                    skin: skin.index(),
+                    joints: joints.len(),
+                });
+            }
+
+            let mut inverse_bind_matrices: Vec<Matrix4<f32>> = match skin.inverse_bind_matrices() {
+                Some(accessor) => accessor_as::<f32>(accessor, buffers)?
+                    .chunks_exact(16)
+                    .map(Matrix4::from_column_slice)
+                    .collect(),
+                None => Vec::new(),
+            };
+            inverse_bind_matrices.resize(joints.len(), Matrix4::identity());
+
+            Ok(Skeleton::new(
+                skin.name().map(|name| name.to_string()),
+                joints,
+                inverse_bind_matrices,
+                skin.skeleton().map(|root| root.index()),
+            ))
+        })
+        .collect()
+}
+
+// Creates an animation for every gltf animation, each sampler is copied into the channel using it
+fn load_animations(gltf: &Gltf, buffers: &[&[u8]]) -> Result<Vec<Animation>> {
+    gltf.animations()
+        .map(|gltf_animation| {
+            let mut channels: Vec<Channel> = Vec::new();
+            for gltf_channel in gltf_animation.channels() {
+                let sampler = gltf_channel.sampler();
+                let interpolation = match sampler.interpolation() {
+                    GltfInterpolation::Linear => animation::Interpolation::Linear,
+                    GltfInterpolation::Step => animation::Interpolation::Step,
+                    GltfInterpolation::CubicSpline => animation::Interpolation::CubicSpline,
+                };
+                let property = match gltf_channel.target().property() {
+                    GltfProperty::Translation => animation::Property::Translation,
+                    GltfProperty::Rotation => animation::Property::Rotation,
+                    GltfProperty::Scale => animation::Property::Scale,
+                    GltfProperty::MorphTargetWeights => animation::Property::MorphWeights,
+                };
+
+                let output_index = sampler.output().index();
+                let times = accessor_as::<f32>(sampler.input(), buffers)?;
+                let values = accessor_as::<f32>(sampler.output(), buffers)?;
+
+                // Morph weights have one value per target, which is only known from the counts
+                let values_per_keyframe = match interpolation {
+                    animation::Interpolation::CubicSpline => times.len() * 3,
+                    _ => times.len(),
+                };
+                let components = match property {
+                    animation::Property::Translation | animation::Property::Scale => 3,
+                    animation::Property::Rotation => 4,
+                    animation::Property::MorphWeights if values_per_keyframe > 0 => {
+                        values.len() / values_per_keyframe
+                    }
+                    animation::Property::MorphWeights => 0,
+                };
+                if values.len() != values_per_keyframe * components {
+                    return Err(ImportError::UnsupportedAccessor {
+                        accessor: output_index,
+                        reason: "sampler output doesn't match its keyframes".to_string(),
+                    });
+                }
+
+                channels.push(Channel::new(
+                    gltf_channel.target().node().index(),
+                    property,
+                    interpolation,
+                    times,
+                    values,
+                    components,
+                ));
+            }
+
+            Ok(Animation::new(
+                gltf_animation.name().map(|name| name.to_string()),
+                channels,
+            ))
+        })
+        .collect()
+}
+
+// Every node of the scene, including all children
+fn scene_nodes<'a>(scene: &gltf::Scene<'a>) -> Vec<GltfNode<'a>> {
+    let mut scene_nodes: Vec<GltfNode> = Vec::new();
+    let mut stack: Vec<GltfNode> = scene.nodes().collect();
+    while let Some(node) = stack.pop() {
+        stack.extend(node.children());
+        scene_nodes.push(node);
+    }
+    scene_nodes
+}
+
+// Creates a light for every node of the scene with a KHR_lights_punctual light, placed at the
+// node's transformation. Lights shine down the node's -z axis.
+fn load_lights(scene: &gltf::Scene, nodes: &[Node]) -> Vec<Light> {
+    let transformations = node::global_transformations(nodes);
+    let mut lights: Vec<Light> = Vec::new();
+
+    for node in scene_nodes(scene) {
+        let gltf_light = match node.light() {
+            Some(gltf_light) => gltf_light,
+            None => continue,
+        };
+        let transformation = &transformations[node.index()];
+        let position = transformation.transform_point(&Point3::origin());
+        let direction = transformation
+            .transform_vector(&-Vector3::z())
+            .try_normalize(std::f32::EPSILON)
+            .unwrap_or_else(|| -Vector3::z());
+
+        // Cone angles only matter to spot lights, the defaults are kept for everything else
+        let default_light = Light::default();
+        let (kind, inner_cone_angle, outer_cone_angle) = match gltf_light.kind() {
+            GltfLightKind::Directional => (
+                LightKind::Directional,
+                default_light.inner_cone_angle,
+                default_light.outer_cone_angle,
+            ),
+            GltfLightKind::Point => (
+                LightKind::Point,
+                default_light.inner_cone_angle,
+                default_light.outer_cone_angle,
+            ),
+            GltfLightKind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => (LightKind::Spot, inner_cone_angle, outer_cone_angle),
+        };
+
+        let light = Light {
+            position: [position.x, position.y, position.z],
+            colour: gltf_light.color(),
+            kind,
+            direction: [direction.x, direction.y, direction.z],
+            intensity: gltf_light.intensity(),
+            range: gltf_light.range(),
+            inner_cone_angle,
+            outer_cone_angle,
+        };
+        lights.push(light);
+    }
+
+    lights
+}
+
+// Cameras look down their node's -z axis, scaling of the node is ignored
+fn load_cameras(scene: &gltf::Scene, nodes: &[Node]) -> Vec<(Option<String>, Camera)> {
+    let transformations = node::global_transformations(nodes);
+    let mut cameras: Vec<(Option<String>, Camera)> = Vec::new();
+
+    for node in scene_nodes(scene) {
+        let gltf_camera = match node.camera() {
+            Some(gltf_camera) => gltf_camera,
+            None => continue,
+        };
+        let (isometry, _) = node::decompose(&transformations[node.index()]);
+        let view_matrix = IsometryMatrix3::from_parts(
+            isometry.translation,
+            isometry.rotation.to_rotation_matrix(),
+        )
+        .inverse();
+
+        let projection = match gltf_camera.projection() {
+            GltfProjection::Orthographic(orthographic) => {
+                Projection::Orthographic(Orthographic3::new(
+                    -orthographic.xmag(),
+                    orthographic.xmag(),
+                    -orthographic.ymag(),
+                    orthographic.ymag(),
+                    orthographic.znear(),
+                    orthographic.zfar(),
+                ))
+            }
+            GltfProjection::Perspective(perspective) => {
+                let aspect = perspective
+                    .aspect_ratio()
+                    .unwrap_or(camera::DEFAULT_ASPECT_RATIO);
+                match perspective.zfar() {
+                    Some(zfar) => Projection::Perspective(Perspective3::new(
+                        aspect,
+                        perspective.yfov(),
+                        perspective.znear(),
+                        zfar,
+                    )),
+                    None => Projection::InfinitePerspective {
+                        aspect,
+                        fovy: perspective.yfov(),
+                        znear: perspective.znear(),
+                    },
+                }
+            }
+        };
+
+        let name = gltf_camera
+            .name()
+            .or_else(|| node.name())
+            .map(|name| name.to_string());
+        cameras.push((name, Camera::from_projection(view_matrix, projection)));
+    }
+
+    cameras
+}
+
+// Recursively loads the meshes of a node and all of its children. Nodes without a mesh (empty
+// transforms, cameras, lights) only pass their transformation on to their children.
+fn load_node_meshes<F: ?Sized>(
+    facade: &F,
+    node: GltfNode,
+    parent_transformation: &Matrix4<f32>,
+    default_material_index: usize,
+    buffers: &[&[u8]],
+    nodes: &mut [Node],
+    meshes: &mut Vec<Mesh>,
+) -> Result<()>
+where
+    F: Facade,
+{
+    let transformation = parent_transformation * nodes[node.index()].local_transformation();
+
+    if let Some(gltf_mesh) = node.mesh() {
+        let mut primitives: Vec<Primitive> = Vec::new();
+        for primitive in gltf_mesh.primitives() {
+            primitives.push(load_primitive(
+                facade,
+                gltf_mesh.index(),
+                primitive,
+                default_material_index,
+                buffers,
+            )?);
+        }
+
+        let (mesh_isometry, mesh_scaling) = node::decompose(&transformation);
+        let mut mesh = Mesh::new(primitives, mesh_isometry, mesh_scaling);
+        mesh.node = Some(node.index());
+
+        nodes[node.index()].mesh = Some(meshes.len());
+        meshes.push(mesh);
+    }
+
+    for child in node.children() {
+        load_node_meshes(
+            facade,
+            child,
+            &transformation,
+            default_material_index,
+            buffers,
+            nodes,
+            meshes,
+        )?;
+    }
+
+    Ok(())
+}
+
+pub fn model_from_gltf<F: ?Sized, P>(
+    facade: &F,
+    rs: &mut RendererState,
+    path: P,
+) -> Result<ModelHandle>
+where
+    F: Facade,
+    P: AsRef<Path> + Debug,
+{
+    let data = decode_gltf(path)?;
+    let materials = (0..data.gltf.materials().len())
+        .map(|material_index| load_gltf_material(facade, &mut rs.textures, &data, material_index))
+        .collect::<Result<Vec<_>>>()?;
+    model_from_gltf_data(facade, rs, &data, materials)
+}
+
+/// Uploads the meshes of a decoded gltf file, `materials` are its materials as loaded by
+/// `load_gltf_material`, in order.
+pub fn model_from_gltf_data<F: ?Sized>(
+    facade: &F,
+    rs: &mut RendererState,
+    data: &GltfData,
+    mut materials: Vec<InternalMaterial>,
+) -> Result<ModelHandle>
+where
+    F: Facade,
+{
+    let gltf = &data.gltf;
+    let buffers = data.buffer_slices();
+    let buffer_slices = buffers.as_slice();
+
+    let mut meshes: Vec<Mesh> = Vec::new();
+
+    // Primitives without a material use the default material, placed after all gltf materials
+    let default_material_index = materials.len();
+
+    let scene = gltf
+        .default_scene()
+        .or_else(|| gltf.scenes().next())
+        .ok_or(ImportError::MissingScene)?;
+
+    let mut nodes = load_nodes(gltf);
+
+    for node in scene.nodes() {
+        load_node_meshes(
+            facade,
+            node,
+            &Matrix4::identity(),
+            default_material_index,
+            buffer_slices,
+            &mut nodes,
+            &mut meshes,
+        )?;
+    }
+
+    let uses_default_material = meshes
+        .iter()
+        .flat_map(|mesh| mesh.primitives.iter())
+        .any(|primitive| primitive.material_index == default_material_index);
+    if uses_default_material {
+        materials.push(load_default_material(facade, &mut rs.textures)?);
+    }
+
+    let skeletons = load_skeletons(gltf, buffer_slices)?;
+    let animations = load_animations(gltf, buffer_slices)?;
+
+    // Skins are attached to nodes, the mesh of such a node is deformed by the skin
+    for node in gltf.nodes() {
+        if let (Some(skin), Some(mesh_index)) = (node.skin(), nodes[node.index()].mesh) {
+            let identity: [[f32; 4]; 4] = Matrix4::<f32>::identity().into();
+            let mesh = &mut meshes[mesh_index];
+            mesh.skeleton = Some(skin.index());
+            mesh.joint_buffer = Some(UniformBuffer::new(facade, [identity; MAX_JOINT_COUNT])?);
+        }
+    }
+
+    rs.lights.extend(load_lights(&scene, &nodes));
+    let cameras = load_cameras(&scene, &nodes);
+
+    let mut model = Model::new(meshes, materials, nodes, skeletons, animations, cameras);
+    // Poses every skeleton in its rest pose
+    model.update_mesh_transformations();
+    let model_handle = rs.push_model(model);
+    Ok(model_handle)
+}
+
+// OBJ stores roughness and metalness as separate greyscale images, they are packed into the green
+// and blue channels the entity shader samples. The metallic image is resized to match the
+// roughness image if they differ.
+fn pack_metallic_roughness_image(
+    roughness_path: Option<&PathBuf>,
+    metallic_path: Option<&PathBuf>,
+) -> Result<RawImage2d<'static, u8>> {
+    let roughness = roughness_path.map(load_image_rgba).transpose()?;
+    let metallic = metallic_path.map(load_image_rgba).transpose()?;
+    let (width, height) = roughness
+        .as_ref()
+        .or_else(|| metallic.as_ref())
+        .map(|image| image.dimensions())
+        .unwrap_or((1, 1));
+    let resized = |image: RgbaImage| {
+        if image.dimensions() == (width, height) {
+            image
+        } else {
+            imageops::resize(&image, width, height, FilterType::Triangle)
+        }
+    };
+    let roughness = roughness.map(resized);
+    let metallic = metallic.map(resized);
+
+    let mut packed = RgbaImage::from_pixel(width, height, Rgba(NEUTRAL_METALLIC_ROUGHNESS_COLOUR));
+    for (x, y, pixel) in packed.enumerate_pixels_mut() {
+        if let Some(roughness) = &roughness {
+            pixel[1] = roughness.get_pixel(x, y)[0];
+        }
+        if let Some(metallic) = &metallic {
+            pixel[2] = metallic.get_pixel(x, y)[0];
+        }
+    }
+
+    Ok(RawImage2d::from_raw_rgba(
+        packed.into_raw(),
+        (width, height),
+    ))
+}
+
+/// Maps an .mtl material onto the metallic roughness model. Texture paths are relative to the
+/// .mtl file. Without `Pr` the roughness is estimated from the specular exponent `Ns`.
+pub fn load_obj_material<F: ?Sized, P>(
+    facade: &F,
+    textures: &mut TextureCache,
+    material: &MtlMaterial,
+    material_location: P,
+) -> Result<InternalMaterial>
+where
+    F: Facade,
+    P: AsRef<Path>,
+{
+    let texture_path = |file_name: &String| material_location.as_ref().join(file_name);
+
+    let diffuse_map = match &material.diffuse_map {
+        Some(file_name) => textures.srgb_file(facade, texture_path(file_name))?,
+        None => textures.solid_srgb(facade, NEUTRAL_DIFFUSE_COLOUR)?,
+    };
+
+    let metallic_roughness_map = match (&material.roughness_map, &material.metallic_map) {
+        (None, None) => textures.solid(facade, NEUTRAL_METALLIC_ROUGHNESS_COLOUR)?,
+        (roughness_map, metallic_map) => {
+            let roughness_path = roughness_map.as_ref().map(texture_path);
+            let metallic_path = metallic_map.as_ref().map(texture_path);
+            let source = TextureSource::MetallicRoughness {
+                roughness: roughness_path.as_ref().map(texture_cache::canonical_path),
+                metallic: metallic_path.as_ref().map(texture_cache::canonical_path),
+            };
+            textures.texture(facade, source, || {
+                pack_metallic_roughness_image(roughness_path.as_ref(), metallic_path.as_ref())
+            })?
+        }
+    };
+
+    let occlusion_map = textures.solid(facade, NEUTRAL_OCCLUSION_COLOUR)?;
+
+    let normal_map = match &material.normal_map {
+        Some(file_name) => textures.file(facade, texture_path(file_name))?,
+        None => textures.solid(facade, NEUTRAL_NORMAL_COLOUR)?,
+    };
+
+    let emissive_map = match &material.emissive_map {
+        Some(file_name) => textures.srgb_file(facade, texture_path(file_name))?,
+        None => textures.solid_srgb(facade, NEUTRAL_EMISSIVE_COLOUR)?,
+    };
+
+    let mut internal_material = InternalMaterial::new(
+        diffuse_map,
+        metallic_roughness_map,
+        occlusion_map,
+        normal_map,
+        emissive_map,
+    );
+    let [red, green, blue] = material.diffuse;
+    internal_material.base_colour_factor = [red, green, blue, material.dissolve];
+
+    // Factors multiply the maps, so a map without a factor is used as is
+    internal_material.roughness_factor = match (material.roughness, &material.roughness_map) {
+        (Some(roughness), _) => roughness,
+        (None, Some(_)) => 1.0,
+        (None, None) => match material.specular_exponent {
+            Some(exponent) => (2.0 / (exponent.max(0.0) + 2.0)).sqrt(),
+            None => 1.0,
+        },
+    };
+    internal_material.metallic_factor = match (material.metallic, &material.metallic_map) {
+        (Some(metallic), _) => metallic,
+        (None, Some(_)) => 1.0,
+        (None, None) => 0.0,
+    };
+    internal_material.emissive_factor = match (material.emissive, &material.emissive_map) {
+        (Some(emissive), _) => emissive,
+        (None, Some(_)) => [1.0, 1.0, 1.0],
+        (None, None) => [0.0, 0.0, 0.0],
+    };
+    internal_material.normal_scale = material.normal_scale;
+    if material.dissolve < 1.0 {
+        internal_material.alpha_mode = AlphaMode::Blend;
+    }
+
+    Ok(internal_material)
+}
+
+// Builds a primitive from vertices without tangents. Tangents are always generated, normals only
+// if the vertices lack them. Normals and tangents are only generated from triangle lists, the
+// vertices of anything else point up.
+fn primitive_from_raw_vertices<F: ?Sized>(
+    facade: &F,
+    raw_vertices: &[RawVertex],
+    indices: Vec<u32>,
+    primitive_type: PrimitiveType,
+    has_normals: bool,
+    material_index: usize,
+) -> Primitive
+where
+    F: Facade,
+{
+    let triangle_indices: &[u32] = if primitive_type == PrimitiveType::TrianglesList {
+        &indices
+    } else {
+        &[]
+    };
+
+    let positions: Vec<[f32; 3]> = raw_vertices.iter().map(|raw| raw.position).collect();
+    let texture_coords: Vec<[f32; 2]> = raw_vertices.iter().map(|raw| raw.texture_coord).collect();
+    let normals = if has_normals {
+        raw_vertices.iter().map(|raw| raw.normal).collect()
+    } else {
+        vertex::calculate_normals(&positions, triangle_indices)
+    };
+    let tangents =
+        vertex::calculate_tangents(&positions, &texture_coords, &normals, triangle_indices);
+
+    let vertices: Vec<Vertex> = raw_vertices
+        .iter()
+        .zip(normals.iter().zip(tangents.iter()))
+        .map(|(raw, (normal, tangent))| {
+            Vertex::new(
+                raw.position,
+                raw.texture_coord,
+                raw.texture_coord,
+                *normal,
+                *tangent,
+                raw.colour,
+            )
+        })
+        .collect();
+
+    Primitive::new(facade, vertices, indices, primitive_type, material_index)
+}
+
+// Builds a triangle list primitive from the faces of an OBJ group, merging identical corners.
+// Normals are generated if any corner lacks one.
+fn load_obj_primitive<F: ?Sized>(
+    facade: &F,
+    data: &ObjData,
+    primitive: &ObjPrimitive,
+    material_index: usize,
+) -> Primitive
+where
+    F: Facade,
+{
+    let has_normals = primitive
+        .corners
+        .iter()
+        .all(|corner| corner.normal.is_some());
+
+    let corners: Vec<RawVertex> = primitive
+        .corners
+        .iter()
+        .map(|corner| RawVertex {
+            position: data.positions[corner.position],
+            texture_coord: corner
+                .texture_coord
+                .map(|index| data.texture_coords[index])
+                .unwrap_or([0.0, 0.0]),
+            normal: match corner.normal {
+                Some(index) if has_normals => data.normals[index],
+                _ => [0.0, 0.0, 0.0],
+            },
+            colour: match &data.colours {
+                Some(colours) => {
+                    let [red, green, blue] = colours[corner.position];
+                    [red, green, blue, 1.0]
+                }
+                None => [1.0, 1.0, 1.0, 1.0],
+            },
+        })
+        .collect();
+
+    let (indices, raw_vertices) = vertex::load_raw_vertex_data(&corners);
+    primitive_from_raw_vertices(
+        facade,
+        &raw_vertices,
+        indices,
+        PrimitiveType::TrianglesList,
+        has_normals,
+        material_index,
+    )
+}
+
+/// Imports a Wavefront .obj file along with the .mtl files it references. Every object or group
+/// becomes a mesh attached to its own node, split into a primitive per material.
+pub fn model_from_obj<F: ?Sized, P>(
+    facade: &F,
+    rs: &mut RendererState,
+    path: P,
+) -> Result<ModelHandle>
+where
+    F: Facade,
+    P: AsRef<Path> + Debug,
+{
+    let obj_path = path.as_ref().to_path_buf();
+    let base_path = obj_path
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .to_path_buf();
+
+    let obj_source = String::from_utf8_lossy(&read_file(&obj_path)?).into_owned();
+    let data = obj::parse_obj(&obj_source).map_err(|error| ImportError::Obj {
+        file: obj_path.clone(),
+        error,
+    })?;
+
+    let mut materials: Vec<InternalMaterial> = Vec::new();
+    let mut material_indices: HashMap<String, usize> = HashMap::new();
+
+    for library in data.material_libraries.iter() {
+        let mtl_path = base_path.join(library);
+        let mtl_source = String::from_utf8_lossy(&read_file(&mtl_path)?).into_owned();
+        let mtl_materials = obj::parse_mtl(&mtl_source).map_err(|error| ImportError::Obj {
+            file: mtl_path.clone(),
+            error,
+        })?;
+
+        let material_location = mtl_path.parent().unwrap_or_else(|| Path::new(""));
+        for mtl_material in mtl_materials.iter() {
+            material_indices.insert(mtl_material.name.clone(), materials.len());
+            materials.push(load_obj_material(
+                facade,
+                &mut rs.textures,
+                mtl_material,
+                material_location,
+            )?);
+        }
+    }
+
+    // Groups without a material, or with one no library defines, use the default material
+    let default_material_index = materials.len();
+
+    let mut meshes: Vec<Mesh> = Vec::new();
+    let mut nodes: Vec<Node> = Vec::new();
+
+    for (index, obj_mesh) in data.meshes.iter().enumerate() {
+        let primitives: Vec<Primitive> = obj_mesh
+            .primitives
+            .iter()
+            .map(|primitive| {
+                let material_index = primitive
+                    .material
+                    .as_ref()
+                    .and_then(|material| material_indices.get(material))
+                    .copied()
+                    .unwrap_or(default_material_index);
+                load_obj_primitive(facade, &data, primitive, material_index)
+            })
+            .collect();
+
+        let mut mesh = Mesh::new(
+            primitives,
+            Isometry3::identity(),
+            Vector3::new(1.0, 1.0, 1.0),
+        );
+        mesh.node = Some(index);
+        meshes.push(mesh);
+
+        let mut node = Node::new(
+            obj_mesh.name.clone(),
+            Vector3::zeros(),
+            UnitQuaternion::identity(),
+            Vector3::new(1.0, 1.0, 1.0),
+        );
+        node.mesh = Some(index);
+        nodes.push(node);
+    }
+
+    let uses_default_material = meshes
+        .iter()
+        .flat_map(|mesh| mesh.primitives.iter())
+        .any(|primitive| primitive.material_index == default_material_index);
+    if uses_default_material {
+        materials.push(load_default_material(facade, &mut rs.textures)?);
+    }
+
+    let mut model = Model::new(meshes, materials, nodes, Vec::new(), Vec::new(), Vec::new());
+    model.update_mesh_transformations();
+    let model_handle = rs.push_model(model);
+    Ok(model_handle)
+}
+
+// Wraps a single primitive into a model with one node, drawn with the default material
+fn model_from_primitive<F: ?Sized>(
+    facade: &F,
+    rs: &mut RendererState,
+    name: Option<String>,
+    primitive: Primitive,
+) -> Result<ModelHandle>
+where
+    F: Facade,
+{
+    let materials = vec![load_default_material(facade, &mut rs.textures)?];
+
+    let mut mesh = Mesh::new(
+        vec![primitive],
+        Isometry3::identity(),
+        Vector3::new(1.0, 1.0, 1.0),
+    );
+    mesh.node = Some(0);
+
+    let mut node = Node::new(
+        name,
+        Vector3::zeros(),
+        UnitQuaternion::identity(),
+        Vector3::new(1.0, 1.0, 1.0),
+    );
+    node.mesh = Some(0);
+
+    let mut model = Model::new(
+        vec![mesh],
+        materials,
+        vec![node],
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+    );
+    model.update_mesh_transformations();
+    let model_handle = rs.push_model(model);
+    Ok(model_handle)
+}
+
+fn file_stem(path: &Path) -> Option<String> {
+    path.file_stem()
+        .map(|file_stem| file_stem.to_string_lossy().into_owned())
+}
+
+/// Imports an ascii or binary Stanford .ply file as a single mesh with the default material.
+/// Vertex normals, colours and uvs are used when present. Files without faces are imported as
+/// point clouds.
+pub fn model_from_ply<F: ?Sized, P>(
+    facade: &F,
+    rs: &mut RendererState,
+    path: P,
+) -> Result<ModelHandle>
+where
+    F: Facade,
+    P: AsRef<Path> + Debug,
+{
+    let ply_path = path.as_ref().to_path_buf();
+
+    let data = ply::parse_ply(&read_file(&ply_path)?).map_err(|error| ImportError::Ply {
+        file: ply_path.clone(),
+        error,
+    })?;
+
+    let raw_vertices: Vec<RawVertex> = (0..data.positions.len())
+        .map(|i| RawVertex {
+            position: data.positions[i],
+            texture_coord: data
+                .texture_coords
+                .as_ref()
+                .map_or([0.0, 0.0], |texture_coords| texture_coords[i]),
+            normal: data
+                .normals
+                .as_ref()
+                .map_or([0.0, 0.0, 0.0], |normals| normals[i]),
+            colour: data
+                .colours
+                .as_ref()
+                .map_or([1.0, 1.0, 1.0, 1.0], |colours| colours[i]),
+        })
+        .collect();
+
+    let has_normals = data.normals.is_some();
+    let (indices, primitive_type) = if data.indices.is_empty() {
+        let indices = (0..raw_vertices.len() as u32).collect();
+        (indices, PrimitiveType::Points)
+    } else {
+        (data.indices, PrimitiveType::TrianglesList)
+    };
+
+    let primitive = primitive_from_raw_vertices(
+        facade,
+        &raw_vertices,
+        indices,
+        primitive_type,
+        has_normals,
+        0,
+    );
+    model_from_primitive(facade, rs, file_stem(&ply_path), primitive)
+}
+
+/// Imports an ascii or binary .stl file as a single mesh with the default material. Every facet
+/// is flat shaded to keep the hard edges of CAD parts.
+pub fn model_from_stl<F: ?Sized, P>(
+    facade: &F,
+    rs: &mut RendererState,
+    path: P,
+) -> Result<ModelHandle>
+where
+    F: Facade,
+    P: AsRef<Path> + Debug,
+{
+    let stl_path = path.as_ref().to_path_buf();
+
+    let facets = stl::parse_stl(&read_file(&stl_path)?).map_err(|error| ImportError::Stl {
+        file: stl_path.clone(),
+        error,
+    })?;
+
+    let corners: Vec<RawVertex> = facets
+        .iter()
+        .flat_map(|facet| {
+            // Many exporters write zero normals, the winding of the facet is used instead
+            let edge1 = Vector3::from(facet.positions[1]) - Vector3::from(facet.positions[0]);
+            let edge2 = Vector3::from(facet.positions[2]) - Vector3::from(facet.positions[0]);
+            let normal = Vector3::from(facet.normal)
+                .try_normalize(std::f32::EPSILON)
+                .or_else(|| edge1.cross(&edge2).try_normalize(std::f32::EPSILON))
+                .unwrap_or_else(Vector3::y);
+            let normal = [normal.x, normal.y, normal.z];
+
+            facet.positions.iter().map(move |position| RawVertex {
+                position: *position,
+                texture_coord: [0.0, 0.0],
+                normal,
+                colour: [1.0, 1.0, 1.0, 1.0],
+            })
+        })
+        .collect();
+
+    let (indices, raw_vertices) = vertex::load_raw_vertex_data(&corners);
+    let primitive = primitive_from_raw_vertices(
+        facade,
+        &raw_vertices,
+        indices,
+        PrimitiveType::TrianglesList,
+        true,
+        0,
+    );
+    model_from_primitive(facade, rs, file_stem(&stl_path), primitive)
 }
 
 //TODO Refactor this to work with non IBL textures somehow. Maybe make another skybox struct without
@@ -583,12 +2371,35 @@ pub fn load_skybox_from_hdr<F: ?Sized, P>(
     irradiance_program: Program,
     prefiltered_program: Program,
     brdf_integration_program: Program,
-) -> Skybox
+) -> Result<Skybox>
 where
     F: Facade,
     P: AsRef<Path>,
 {
-    let hdr_texture = load_hdr_texture(facade, path.as_ref());
+    let image = try_load_rawimage_hdr(path)?;
+    skybox_from_hdr_image(
+        facade,
+        image,
+        hdr_program,
+        irradiance_program,
+        prefiltered_program,
+        brdf_integration_program,
+    )
+}
+
+/// Renders an already decoded equirectangular .hdr image into a skybox.
+pub fn skybox_from_hdr_image<F: ?Sized>(
+    facade: &F,
+    image: RawImage2d<'static, f32>,
+    hdr_program: Program,
+    irradiance_program: Program,
+    prefiltered_program: Program,
+    brdf_integration_program: Program,
+) -> Result<Skybox>
+where
+    F: Facade,
+{
+    let hdr_texture = load_hdr_texture(facade, image)?;
 
     let width = 2048;
     let height = 2048;
@@ -603,7 +2414,7 @@ where
     // Mipmaps on may or may not cause undefined behaviour, need to check
     let float_format = glium::texture::UncompressedFloatFormat::F32F32F32;
     let mipmaps_option = glium::texture::MipmapsOption::EmptyMipmaps;
-    let cubemap = Cubemap::empty_with_format(facade, float_format, mipmaps_option, width).unwrap();
+    let cubemap = Cubemap::empty_with_format(facade, float_format, mipmaps_option, width)?;
 
     let vbo = Skybox::make_vbo(facade);
     let ibo = Skybox::make_ibo(facade);
@@ -650,7 +2461,7 @@ where
         cubemap.generate_mipmaps();
     }
 
-    Skybox::from_cubemap(
+    Ok(Skybox::from_cubemap(
         facade,
         cubemap,
         irradiance_program,
@@ -658,9 +2469,106 @@ where
         7,
         brdf_integration_program,
         (512, 512),
+    ))
+}
+
+/// Loads a cubemap from a .dds or .ktx2 file as a skybox, see `skybox_from_texture_file`.
+pub fn load_skybox_from_cubemap_file<F: ?Sized, P>(
+    facade: &F,
+    path: P,
+    irradiance_program: Program,
+    prefiltered_program: Program,
+    brdf_integration_program: Program,
+) -> Result<Skybox>
+where
+    F: Facade,
+    P: AsRef<Path>,
+{
+    let file = decode_texture_file(path)?;
+    skybox_from_texture_file(
+        facade,
+        &file,
+        irradiance_program,
+        prefiltered_program,
+        brdf_integration_program,
     )
 }
 
+// Converts decoded srgb colours to linear, alpha is left as is
+fn srgb_to_linear(pixels: &mut [f32]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        for value in pixel.iter_mut().take(3) {
+            *value = if *value <= 0.04045 {
+                *value / 12.92
+            } else {
+                ((*value + 0.055) / 1.055).powf(2.4)
+            };
+        }
+    }
+}
+
+/// Turns an already decoded cubemap file into a skybox. The faces are decoded into floats on the
+/// CPU, as glium can't upload block compressed cubemaps. Mip levels stored in the file are kept,
+/// files without them get them generated.
+pub fn skybox_from_texture_file<F: ?Sized>(
+    facade: &F,
+    file: &TextureFile,
+    irradiance_program: Program,
+    prefiltered_program: Program,
+    brdf_integration_program: Program,
+) -> Result<Skybox>
+where
+    F: Facade,
+{
+    if !file.is_cubemap() {
+        return Err(ImportError::NotACubemap);
+    }
+
+    let float_format = UncompressedFloatFormat::F32F32F32;
+    let mipmaps_option = match file.levels.len() {
+        1 => MipmapsOption::EmptyMipmaps,
+        count => MipmapsOption::EmptyMipmapsMax(count as u32 - 1),
+    };
+    let cubemap = Cubemap::empty_with_format(facade, float_format, mipmaps_option, file.width())?;
+
+    // Faces can't be written to directly, so each is uploaded as a 2d texture and copied over
+    for (i, level) in file.levels.iter().enumerate() {
+        let cubemap_framebuffers = Skybox::cubemap_mip_framebuffers(facade, &cubemap, i as u32);
+        for (face, cubemap_framebuffer) in level.faces.iter().zip(cubemap_framebuffers.iter()) {
+            let mut pixels =
+                compressed::decode_rgba_f32(file.format, face, level.width, level.height);
+            if file.srgb {
+                srgb_to_linear(&mut pixels);
+            }
+            let face_texture = Texture2d::with_format(
+                facade,
+                RawImage2d::from_raw_rgba(pixels, (level.width, level.height)),
+                UncompressedFloatFormat::F32F32F32F32,
+                MipmapsOption::NoMipmap,
+            )?;
+            face_texture
+                .as_surface()
+                .fill(cubemap_framebuffer, MagnifySamplerFilter::Nearest);
+        }
+    }
+
+    if file.levels.len() == 1 {
+        unsafe {
+            cubemap.generate_mipmaps();
+        }
+    }
+
+    Ok(Skybox::from_cubemap(
+        facade,
+        cubemap,
+        irradiance_program,
+        prefiltered_program,
+        7,
+        brdf_integration_program,
+        (512, 512),
+    ))
+}
+
 pub fn load_program<F>(facade: &F, vertex_src: &str, fragment_src: &str) -> Program
 where
     F: Facade,
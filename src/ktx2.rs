@@ -0,0 +1,296 @@
+//! Parsing of .ktx2 files into plain texture data, uploaded by `import::upload_texture_file` and
+//! `import::upload_srgb_texture_file`.
+
+use crate::compressed::{self, TextureFile, TextureFormat, TextureLevel};
+use std::fmt;
+
+/// A .ktx2 file which couldn't be parsed.
+#[derive(Debug)]
+pub struct ParseError {
+    pub reason: String,
+}
+
+impl ParseError {
+    fn new(reason: &str) -> Self {
+        Self {
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub type Result<T> = std::result::Result<T, ParseError>;
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+// The identifier, the header and the offsets of the data format descriptor, key/value data and
+// supercompression data, followed by the level index
+const LEVEL_INDEX_START: usize = 80;
+// Offset, length and uncompressed length of each level
+const LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from(read_u32(bytes, offset)) | u64::from(read_u32(bytes, offset + 4)) << 32
+}
+
+// The format, whether it's srgb and whether red and blue are swapped
+fn vk_format(vk_format: u32) -> Result<(TextureFormat, bool, bool)> {
+    Ok(match vk_format {
+        37 => (TextureFormat::Rgba8, false, false),
+        43 => (TextureFormat::Rgba8, true, false),
+        44 => (TextureFormat::Rgba8, false, true),
+        50 => (TextureFormat::Rgba8, true, true),
+        131 | 133 => (TextureFormat::Bc1, false, false),
+        132 | 134 => (TextureFormat::Bc1, true, false),
+        135 => (TextureFormat::Bc2, false, false),
+        136 => (TextureFormat::Bc2, true, false),
+        137 => (TextureFormat::Bc3, false, false),
+        138 => (TextureFormat::Bc3, true, false),
+        139 => (TextureFormat::Bc4, false, false),
+        141 => (TextureFormat::Bc5, false, false),
+        143 => (TextureFormat::Bc6hUnsigned, false, false),
+        144 => (TextureFormat::Bc6hSigned, false, false),
+        145 => (TextureFormat::Bc7, false, false),
+        146 => (TextureFormat::Bc7, true, false),
+        0 => return Err(ParseError::new("basis universal textures aren't supported")),
+        140 | 142 => return Err(ParseError::new("signed BC4 and BC5 aren't supported")),
+        _ => return Err(ParseError::new("unsupported vulkan format")),
+    })
+}
+
+/// Parses the first layer of a .ktx2 file, with all of its mip levels and cubemap faces.
+pub fn parse_ktx2(bytes: &[u8]) -> Result<TextureFile> {
+    if bytes.len() < LEVEL_INDEX_START || bytes[0..12] != IDENTIFIER {
+        return Err(ParseError::new("missing ktx2 identifier"));
+    }
+
+    let (format, srgb, swap_red_blue) = vk_format(read_u32(bytes, 12))?;
+    let width = read_u32(bytes, 20);
+    let height = read_u32(bytes, 24);
+    let depth = read_u32(bytes, 28);
+    let layer_count = read_u32(bytes, 32).max(1) as usize;
+    let face_count = read_u32(bytes, 36) as usize;
+    let supercompression = read_u32(bytes, 44);
+
+    if width == 0 || height == 0 {
+        return Err(ParseError::new("texture has no pixels"));
+    }
+    // Zero levels asks for the mip chain to be generated at load
+    let level_count =
+        (read_u32(bytes, 40).max(1) as usize).min(compressed::max_level_count(width, height));
+    if depth > 1 {
+        return Err(ParseError::new("volume textures aren't supported"));
+    }
+    if face_count != 1 && face_count != 6 {
+        return Err(ParseError::new("texture must have one face or six"));
+    }
+    if face_count == 6 && width != height {
+        return Err(ParseError::new("cubemap faces aren't square"));
+    }
+    if supercompression != 0 {
+        return Err(ParseError::new("supercompressed textures aren't supported"));
+    }
+    if bytes.len() < LEVEL_INDEX_START + level_count * LEVEL_INDEX_ENTRY_SIZE {
+        return Err(ParseError::new("file is shorter than its level index"));
+    }
+
+    let mut levels = Vec::with_capacity(level_count);
+    for level in 0..level_count {
+        let entry = LEVEL_INDEX_START + level * LEVEL_INDEX_ENTRY_SIZE;
+        let offset = read_u64(bytes, entry) as usize;
+        let length = read_u64(bytes, entry + 8) as usize;
+        let data = bytes
+            .get(offset..offset.saturating_add(length))
+            .ok_or_else(|| ParseError::new("file is shorter than its mip levels"))?;
+
+        let (level_width, level_height) = (
+            compressed::level_size(width, level),
+            compressed::level_size(height, level),
+        );
+        // Each level holds every face of every layer, only the first layer is kept
+        let size = format
+            .image_size(level_width, level_height)
+            .ok_or_else(|| ParseError::new("texture is too large"))?;
+        let images_size = size
+            .checked_mul(face_count)
+            .and_then(|images_size| images_size.checked_mul(layer_count))
+            .ok_or_else(|| ParseError::new("texture is too large"))?;
+        if data.len() < images_size {
+            return Err(ParseError::new("mip level is shorter than its images"));
+        }
+        let faces = data
+            .chunks_exact(size)
+            .take(face_count)
+            .map(|face| {
+                let mut face = face.to_vec();
+                if swap_red_blue {
+                    face.chunks_exact_mut(4).for_each(|pixel| pixel.swap(0, 2));
+                }
+                face
+            })
+            .collect();
+
+        levels.push(TextureLevel {
+            width: level_width,
+            height: level_height,
+            faces,
+        });
+    }
+
+    Ok(TextureFile {
+        format,
+        srgb,
+        levels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    // A header followed by a level index with `level_count` entries, the level data is left to
+    // the caller
+    fn header(
+        vk_format: u32,
+        width: u32,
+        height: u32,
+        face_count: u32,
+        level_count: u32,
+    ) -> Vec<u8> {
+        let entries = level_count.max(1) as usize;
+        let mut bytes = vec![0; LEVEL_INDEX_START + entries * LEVEL_INDEX_ENTRY_SIZE];
+        bytes[0..12].copy_from_slice(&IDENTIFIER);
+        write_u32(&mut bytes, 12, vk_format);
+        write_u32(&mut bytes, 20, width);
+        write_u32(&mut bytes, 24, height);
+        write_u32(&mut bytes, 36, face_count);
+        write_u32(&mut bytes, 40, level_count);
+        bytes
+    }
+
+    // Appends the data of a level and points its entry of the level index at it
+    fn push_level(bytes: &mut Vec<u8>, level: usize, data: &[u8]) {
+        let entry = LEVEL_INDEX_START + level * LEVEL_INDEX_ENTRY_SIZE;
+        let offset = bytes.len() as u32;
+        write_u32(bytes, entry, offset);
+        write_u32(bytes, entry + 8, data.len() as u32);
+        bytes.extend(data);
+    }
+
+    #[test]
+    fn parses_levels_stored_smallest_first() {
+        let mut bytes = header(137, 8, 4, 1, 2);
+        push_level(&mut bytes, 1, &[1; 16]);
+        push_level(&mut bytes, 0, &[0; 32]);
+
+        let texture = parse_ktx2(&bytes).unwrap();
+        assert_eq!(texture.format, TextureFormat::Bc3);
+        assert_eq!(texture.levels.len(), 2);
+        assert_eq!((texture.levels[1].width, texture.levels[1].height), (4, 2));
+        assert_eq!(texture.levels[0].faces, vec![vec![0; 32]]);
+        assert_eq!(texture.levels[1].faces, vec![vec![1; 16]]);
+    }
+
+    #[test]
+    fn only_the_first_layer_of_cubemaps_is_kept() {
+        let mut bytes = header(145, 4, 4, 6, 1);
+        write_u32(&mut bytes, 32, 2);
+        let data: Vec<u8> = (0..12).flat_map(|image| vec![image; 16]).collect();
+        push_level(&mut bytes, 0, &data);
+
+        let texture = parse_ktx2(&bytes).unwrap();
+        assert!(texture.is_cubemap());
+        assert_eq!(texture.levels[0].faces.len(), 6);
+        assert_eq!(texture.levels[0].faces[5], vec![5; 16]);
+    }
+
+    #[test]
+    fn level_count_is_limited_to_the_mip_chain() {
+        let mut bytes = header(145, 4, 4, 1, 40);
+        for level in 0..3 {
+            push_level(&mut bytes, level, &[0; 16]);
+        }
+        assert_eq!(parse_ktx2(&bytes).unwrap().levels.len(), 3);
+
+        let mut bytes = header(145, 4, 4, 1, 0);
+        push_level(&mut bytes, 0, &[0; 16]);
+        assert_eq!(parse_ktx2(&bytes).unwrap().levels.len(), 1);
+    }
+
+    #[test]
+    fn bgra_is_swizzled() {
+        let mut bytes = header(50, 1, 1, 1, 1);
+        push_level(&mut bytes, 0, &[1, 2, 3, 4]);
+
+        let texture = parse_ktx2(&bytes).unwrap();
+        assert!(texture.srgb);
+        assert_eq!(texture.levels[0].faces[0], vec![3, 2, 1, 4]);
+    }
+
+    #[test]
+    fn short_levels_are_an_error() {
+        let mut bytes = header(145, 4, 4, 1, 1);
+        push_level(&mut bytes, 0, &[0; 15]);
+        assert!(parse_ktx2(&bytes).is_err());
+
+        let mut bytes = header(145, 4, 4, 1, 1);
+        push_level(&mut bytes, 0, &[0; 16]);
+        bytes.pop();
+        assert!(parse_ktx2(&bytes).is_err());
+    }
+
+    #[test]
+    fn too_large_textures_are_an_error() {
+        let bytes = header(37, u32::MAX, u32::MAX, 1, 1);
+        assert!(parse_ktx2(&bytes).is_err());
+
+        // A single image fits, but not all of the layers
+        let mut bytes = header(37, 1 << 30, 1 << 30, 1, 1);
+        write_u32(&mut bytes, 32, 8);
+        assert!(parse_ktx2(&bytes).is_err());
+    }
+
+    #[test]
+    fn unsupported_files_are_an_error() {
+        assert!(parse_ktx2(&IDENTIFIER).is_err());
+
+        let mut bytes = header(145, 4, 4, 1, 1);
+        write_u32(&mut bytes, 44, 2);
+        push_level(&mut bytes, 0, &[0; 16]);
+        assert!(parse_ktx2(&bytes).is_err());
+
+        let mut bytes = header(0, 4, 4, 1, 1);
+        push_level(&mut bytes, 0, &[0; 16]);
+        assert!(parse_ktx2(&bytes).is_err());
+
+        let mut bytes = header(145, 4, 4, 2, 1);
+        push_level(&mut bytes, 0, &[0; 32]);
+        assert!(parse_ktx2(&bytes).is_err());
+
+        let mut bytes = header(145, 4, 4, 1, 1);
+        write_u32(&mut bytes, 28, 4);
+        push_level(&mut bytes, 0, &[0; 64]);
+        assert!(parse_ktx2(&bytes).is_err());
+    }
+}
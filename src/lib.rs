@@ -4,15 +4,29 @@ extern crate glium;
 extern crate nalgebra as na;
 extern crate nalgebra_glm as glm;
 
+pub mod animation;
+pub mod blend;
 pub mod camera;
+pub mod compressed;
+pub mod dds;
+pub mod export;
 pub mod import;
+pub mod ktx2;
 pub mod light;
+pub mod loader;
 pub mod map;
 pub mod material;
 pub mod mesh;
 pub mod model;
+pub mod node;
+pub mod obj;
 pub mod physics;
+pub mod ply;
+pub mod pose;
 pub mod primitive;
 pub mod renderer;
+pub mod skeleton;
 pub mod skybox;
+pub mod stl;
+pub mod texture_cache;
 pub mod vertex;
@@ -1,9 +1,31 @@
 use glm::Vec3;
 
-#[derive(Copy, Clone)]
+/// The shape of the light. The discriminants match the light kinds of the entity shader.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    /// Shines in every direction from its position.
+    Point = 0,
+    /// Infinitely far away, only its direction matters.
+    Directional = 1,
+    /// A point light limited to a cone around its direction.
+    Spot = 2,
+}
+
+#[derive(Copy, Clone, PartialEq)]
 pub struct Light {
     pub position: [f32; 3],
     pub colour: [f32; 3],
+    pub kind: LightKind,
+    /// Direction the light shines in, unused by point lights.
+    pub direction: [f32; 3],
+    /// Multiplied with the colour. Candela for point and spot lights, lux for directional lights.
+    pub intensity: f32,
+    /// Distance at which the light has faded out completely, unlimited if `None`.
+    pub range: Option<f32>,
+    /// Angle from the direction in radians at which spot lights start to fade out.
+    pub inner_cone_angle: f32,
+    /// Angle from the direction in radians at which spot lights have faded out completely.
+    pub outer_cone_angle: f32,
 }
 
 impl Light {
@@ -14,7 +36,16 @@ impl Light {
         } else {
             [1.0, 1.0, 1.0]
         };
-        Self { position, colour }
+        Self {
+            position,
+            colour,
+            kind: LightKind::Point,
+            direction: [0.0, 0.0, -1.0],
+            intensity: 1.0,
+            range: None,
+            inner_cone_angle: 0.0,
+            outer_cone_angle: std::f32::consts::FRAC_PI_4,
+        }
     }
 }
 
@@ -23,6 +54,12 @@ impl Default for Light {
         Self{
             position: [std::f32::MAX, std::f32::MAX, std::f32::MAX],
             colour: [std::f32::MAX, std::f32::MAX, std::f32::MAX],
+            kind: LightKind::Point,
+            direction: [0.0, 0.0, -1.0],
+            intensity: 1.0,
+            range: None,
+            inner_cone_angle: 0.0,
+            outer_cone_angle: std::f32::consts::FRAC_PI_4,
         }
     }
 }
@@ -0,0 +1,520 @@
+//! Loads assets in the background. Files are read and decoded on worker threads, the decoded data
+//! is uploaded to GL on the main thread a piece at a time within a budget, so frames (such as a
+//! loading screen) keep being drawn while assets load.
+
+use crate::compressed::TextureFile;
+use crate::import::{self, GltfData, ImportError, Result};
+use crate::material::Material;
+use crate::model::ModelHandle;
+use crate::renderer::{DisplayState, RendererState};
+use crate::skybox::Skybox;
+use crossbeam::channel::{self, Receiver, Sender};
+use glium::backend::Facade;
+use glium::texture::{RawImage2d, SrgbTexture2d, Texture2d};
+use glium::Program;
+use legion::*;
+use std::mem;
+use std::panic::{self, UnwindSafe};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long `update_asset_loader` spends uploading assets each frame.
+pub const FRAME_UPLOAD_BUDGET: Duration = Duration::from_millis(4);
+
+/// Refers to an asset requested from an `AssetLoader`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AssetHandle {
+    index: usize,
+}
+
+impl AssetHandle {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+/// A loaded asset, taken out of the loader with `AssetLoader::take`.
+pub enum Asset {
+    /// Models are pushed into the `RendererState` passed to `AssetLoader::update`.
+    Model(ModelHandle),
+    Texture(Texture2d),
+    SrgbTexture(SrgbTexture2d),
+    Skybox(Skybox),
+}
+
+/// How far along an asset is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadState {
+    /// Waiting for a worker thread or being decoded by one.
+    Decoding,
+    /// Decoded and being uploaded to GL.
+    Uploading,
+    Ready,
+    Failed,
+    /// The asset or its error has been taken out of the loader.
+    Taken,
+}
+
+// What a worker thread is asked to decode
+#[derive(Clone, Copy)]
+enum DecodeKind {
+    Gltf,
+    Image,
+    Hdr,
+    TextureFile,
+}
+
+struct DecodeJob {
+    index: usize,
+    path: PathBuf,
+    kind: DecodeKind,
+}
+
+// Sent back from a worker thread once a file is decoded
+enum Decoded {
+    Gltf(GltfData),
+    Image(RawImage2d<'static, u8>),
+    Hdr(RawImage2d<'static, f32>),
+    TextureFile(TextureFile),
+}
+
+// Turns a panic into an error, so an asset whose decoder panics fails instead of never loading
+fn catch_panic<T, D>(path: &Path, decode: D) -> Result<T>
+where
+    D: FnOnce() -> Result<T> + UnwindSafe,
+{
+    panic::catch_unwind(decode).unwrap_or_else(|payload| {
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "unknown panic".to_string()
+        };
+        Err(ImportError::Panicked {
+            file: path.to_path_buf(),
+            message,
+        })
+    })
+}
+
+fn decode(job: &DecodeJob) -> Result<Decoded> {
+    match job.kind {
+        DecodeKind::Gltf => import::decode_gltf(&job.path).map(Decoded::Gltf),
+        DecodeKind::Image => import::try_load_rawimage_rgba(&job.path).map(Decoded::Image),
+        DecodeKind::Hdr => import::try_load_rawimage_hdr(&job.path).map(Decoded::Hdr),
+        DecodeKind::TextureFile => import::decode_texture_file(&job.path).map(Decoded::TextureFile),
+    }
+}
+
+struct SkyboxPrograms {
+    hdr: Program,
+    irradiance: Program,
+    prefiltered: Program,
+    brdf_integration: Program,
+}
+
+// What decoded data is turned into on the main thread. Programs can't be sent to workers, so they
+// wait here.
+enum Target {
+    Model,
+    Texture,
+    SrgbTexture,
+    Skybox(SkyboxPrograms),
+}
+
+// Decoded data waiting to be uploaded
+enum Upload {
+    // Materials are uploaded one per step, then the meshes in a final step
+    Gltf {
+        data: GltfData,
+        materials: Vec<Material>,
+    },
+    Texture(RawImage2d<'static, u8>),
+    SrgbTexture(RawImage2d<'static, u8>),
+    Skybox {
+        image: RawImage2d<'static, f32>,
+        programs: SkyboxPrograms,
+    },
+    CubemapSkybox {
+        file: TextureFile,
+        programs: SkyboxPrograms,
+    },
+}
+
+enum Step {
+    Pending(Upload),
+    Done(Asset),
+}
+
+impl Upload {
+    fn new(target: Target, decoded: Decoded) -> Self {
+        match (target, decoded) {
+            (Target::Model, Decoded::Gltf(data)) => Upload::Gltf {
+                data,
+                materials: Vec::new(),
+            },
+            (Target::Texture, Decoded::Image(image)) => Upload::Texture(image),
+            (Target::SrgbTexture, Decoded::Image(image)) => Upload::SrgbTexture(image),
+            (Target::Skybox(programs), Decoded::Hdr(image)) => Upload::Skybox { image, programs },
+            (Target::Skybox(programs), Decoded::TextureFile(file)) => {
+                Upload::CubemapSkybox { file, programs }
+            }
+            _ => unreachable!("workers decode the kind of data their target needs"),
+        }
+    }
+
+    // Does one piece of GL work
+    fn step<F: ?Sized>(self, facade: &F, rs: &mut RendererState) -> Result<Step>
+    where
+        F: Facade,
+    {
+        match self {
+            Upload::Gltf {
+                data,
+                mut materials,
+            } => {
+                if materials.len() < data.gltf.materials().len() {
+                    materials.push(import::load_gltf_material(
+                        facade,
+                        &mut rs.textures,
+                        &data,
+                        materials.len(),
+                    )?);
+                    Ok(Step::Pending(Upload::Gltf { data, materials }))
+                } else {
+                    let model_handle = import::model_from_gltf_data(facade, rs, &data, materials)?;
+                    Ok(Step::Done(Asset::Model(model_handle)))
+                }
+            }
+            Upload::Texture(image) => {
+                let texture = Texture2d::new(facade, image)?;
+                Ok(Step::Done(Asset::Texture(texture)))
+            }
+            Upload::SrgbTexture(image) => {
+                let texture = SrgbTexture2d::new(facade, image)?;
+                Ok(Step::Done(Asset::SrgbTexture(texture)))
+            }
+            Upload::Skybox { image, programs } => {
+                let skybox = import::skybox_from_hdr_image(
+                    facade,
+                    image,
+                    programs.hdr,
+                    programs.irradiance,
+                    programs.prefiltered,
+                    programs.brdf_integration,
+                )?;
+                Ok(Step::Done(Asset::Skybox(skybox)))
+            }
+            Upload::CubemapSkybox { file, programs } => {
+                let skybox = import::skybox_from_texture_file(
+                    facade,
+                    &file,
+                    programs.irradiance,
+                    programs.prefiltered,
+                    programs.brdf_integration,
+                )?;
+                Ok(Step::Done(Asset::Skybox(skybox)))
+            }
+        }
+    }
+
+    // The fraction of steps already done
+    fn progress(&self) -> f32 {
+        match self {
+            Upload::Gltf { data, materials } => {
+                materials.len() as f32 / (data.gltf.materials().len() + 1) as f32
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+enum Stage {
+    Decoding(Target),
+    Uploading(Upload),
+    Ready(Asset),
+    Failed(ImportError),
+    Taken,
+}
+
+/// Decodes files on worker threads and uploads them with `update`, which has to be called
+/// regularly from the thread owning the GL context.
+pub struct AssetLoader {
+    jobs: Sender<DecodeJob>,
+    results: Receiver<(usize, Result<Decoded>)>,
+    stages: Vec<Stage>,
+}
+
+impl AssetLoader {
+    /// Starts `worker_count` decoding threads, at least one. They stop once the loader is dropped.
+    /// A file whose decoder panics fails to load with `ImportError::Panicked`.
+    pub fn new(worker_count: usize) -> Self {
+        let (jobs, job_receiver) = channel::unbounded::<DecodeJob>();
+        let (result_sender, results) = channel::unbounded();
+
+        for _ in 0..worker_count.max(1) {
+            let job_receiver = job_receiver.clone();
+            let result_sender = result_sender.clone();
+            thread::spawn(move || {
+                for job in job_receiver.iter() {
+                    let result = catch_panic(&job.path, || decode(&job));
+                    if result_sender.send((job.index, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        Self {
+            jobs,
+            results,
+            stages: Vec::new(),
+        }
+    }
+
+    fn request<P>(&mut self, path: P, kind: DecodeKind, target: Target) -> AssetHandle
+    where
+        P: AsRef<Path>,
+    {
+        let index = self.stages.len();
+        self.stages.push(Stage::Decoding(target));
+        // The workers only stop when the sender is dropped, so this can't fail
+        self.jobs
+            .send(DecodeJob {
+                index,
+                path: path.as_ref().to_path_buf(),
+                kind,
+            })
+            .unwrap();
+        AssetHandle { index }
+    }
+
+    /// Loads a .gltf or .glb file, or a directory as accepted by `import::model_from_gltf`.
+    pub fn load_model<P>(&mut self, path: P) -> AssetHandle
+    where
+        P: AsRef<Path>,
+    {
+        self.request(path, DecodeKind::Gltf, Target::Model)
+    }
+
+    pub fn load_texture<P>(&mut self, path: P) -> AssetHandle
+    where
+        P: AsRef<Path>,
+    {
+        self.request(path, DecodeKind::Image, Target::Texture)
+    }
+
+    pub fn load_srgb_texture<P>(&mut self, path: P) -> AssetHandle
+    where
+        P: AsRef<Path>,
+    {
+        self.request(path, DecodeKind::Image, Target::SrgbTexture)
+    }
+
+    /// Loads an .hdr file as a skybox, see `import::load_skybox_from_hdr`. Cubemaps in .dds and
+    /// .ktx2 files are loaded by `import::load_skybox_from_cubemap_file` instead, which doesn't use
+    /// `hdr_program`.
+    pub fn load_skybox<P>(
+        &mut self,
+        path: P,
+        hdr_program: Program,
+        irradiance_program: Program,
+        prefiltered_program: Program,
+        brdf_integration_program: Program,
+    ) -> AssetHandle
+    where
+        P: AsRef<Path>,
+    {
+        let programs = SkyboxPrograms {
+            hdr: hdr_program,
+            irradiance: irradiance_program,
+            prefiltered: prefiltered_program,
+            brdf_integration: brdf_integration_program,
+        };
+        let kind = if import::is_texture_file(&path) {
+            DecodeKind::TextureFile
+        } else {
+            DecodeKind::Hdr
+        };
+        self.request(path, kind, Target::Skybox(programs))
+    }
+
+    /// Collects decoded files and uploads them until `budget` runs out. Assets are uploaded in the
+    /// order they were requested, and at least one piece is uploaded per call so loading always
+    /// moves forward. A single piece, such as a material or a skybox, can take longer than the
+    /// budget.
+    pub fn update<F: ?Sized>(&mut self, facade: &F, rs: &mut RendererState, budget: Duration)
+    where
+        F: Facade,
+    {
+        self.collect_decoded();
+
+        let start = Instant::now();
+        let mut uploaded = false;
+        for stage in self.stages.iter_mut() {
+            while matches!(stage, Stage::Uploading(_)) {
+                if uploaded && start.elapsed() >= budget {
+                    return;
+                }
+                if let Stage::Uploading(upload) = mem::replace(stage, Stage::Taken) {
+                    *stage = match upload.step(facade, rs) {
+                        Ok(Step::Pending(upload)) => Stage::Uploading(upload),
+                        Ok(Step::Done(asset)) => Stage::Ready(asset),
+                        Err(error) => Stage::Failed(error),
+                    };
+                }
+                uploaded = true;
+            }
+        }
+    }
+
+    // Moves the files the workers have finished decoding on to uploading
+    fn collect_decoded(&mut self) {
+        for (index, result) in self.results.try_iter() {
+            let stage = &mut self.stages[index];
+            *stage = match (mem::replace(stage, Stage::Taken), result) {
+                (Stage::Decoding(target), Ok(decoded)) => {
+                    Stage::Uploading(Upload::new(target, decoded))
+                }
+                (_, Err(error)) => Stage::Failed(error),
+                (unchanged, Ok(_)) => unchanged,
+            };
+        }
+    }
+
+    pub fn state(&self, handle: AssetHandle) -> LoadState {
+        match &self.stages[handle.index] {
+            Stage::Decoding(_) => LoadState::Decoding,
+            Stage::Uploading(_) => LoadState::Uploading,
+            Stage::Ready(_) => LoadState::Ready,
+            Stage::Failed(_) => LoadState::Failed,
+            Stage::Taken => LoadState::Taken,
+        }
+    }
+
+    pub fn is_ready(&self, handle: AssetHandle) -> bool {
+        self.state(handle) == LoadState::Ready
+    }
+
+    /// From 0.0 to 1.0, decoding is the first half and uploading the second. Failed assets count
+    /// as finished.
+    pub fn progress(&self, handle: AssetHandle) -> f32 {
+        match &self.stages[handle.index] {
+            Stage::Decoding(_) => 0.0,
+            Stage::Uploading(upload) => 0.5 + 0.5 * upload.progress(),
+            Stage::Ready(_) | Stage::Failed(_) | Stage::Taken => 1.0,
+        }
+    }
+
+    /// The average progress of every asset requested so far, for a loading screen.
+    pub fn total_progress(&self) -> f32 {
+        if self.stages.is_empty() {
+            return 1.0;
+        }
+        let total: f32 = (0..self.stages.len())
+            .map(|index| self.progress(AssetHandle { index }))
+            .sum();
+        total / self.stages.len() as f32
+    }
+
+    /// Whether every requested asset has loaded or failed.
+    pub fn is_finished(&self) -> bool {
+        self.stages
+            .iter()
+            .all(|stage| !matches!(stage, Stage::Decoding(_) | Stage::Uploading(_)))
+    }
+
+    /// Takes a loaded asset, or the error it failed with, out of the loader. Returns None while
+    /// it is still loading or once it has been taken.
+    pub fn take(&mut self, handle: AssetHandle) -> Option<Result<Asset>> {
+        let stage = &mut self.stages[handle.index];
+        if !matches!(stage, Stage::Ready(_) | Stage::Failed(_)) {
+            return None;
+        }
+        match mem::replace(stage, Stage::Taken) {
+            Stage::Ready(asset) => Some(Ok(asset)),
+            Stage::Failed(error) => Some(Err(error)),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Uploads some of what the asset loader has decoded, once a frame.
+#[system]
+pub fn update_asset_loader(
+    #[resource] loader: &mut AssetLoader,
+    #[resource] rs: &mut RendererState,
+    #[resource] ds: &DisplayState,
+) {
+    loader.update(&ds.display, rs, FRAME_UPLOAD_BUDGET);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Collects decoded files until the asset is no longer decoding
+    fn wait_for_decoding(loader: &mut AssetLoader, handle: AssetHandle) {
+        let start = Instant::now();
+        while loader.state(handle) == LoadState::Decoding {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "decoding timed out"
+            );
+            thread::sleep(Duration::from_millis(1));
+            loader.collect_decoded();
+        }
+    }
+
+    #[test]
+    fn without_requests_loading_is_finished() {
+        let loader = AssetLoader::new(1);
+        assert!(loader.is_finished());
+        assert_eq!(loader.total_progress(), 1.0);
+    }
+
+    #[test]
+    fn missing_files_fail() {
+        let mut loader = AssetLoader::new(2);
+        let missing = loader.load_texture("does/not/exist.png");
+        let model = loader.load_model("does/not/exist.gltf");
+        assert_eq!(loader.state(missing), LoadState::Decoding);
+        assert!(!loader.is_finished());
+        assert_eq!(loader.progress(missing), 0.0);
+        assert_eq!(loader.total_progress(), 0.0);
+
+        wait_for_decoding(&mut loader, missing);
+        wait_for_decoding(&mut loader, model);
+        assert_eq!(loader.state(missing), LoadState::Failed);
+        assert_eq!(loader.state(model), LoadState::Failed);
+        assert!(!loader.is_ready(missing));
+        assert!(loader.is_finished());
+        assert_eq!(loader.total_progress(), 1.0);
+
+        assert!(matches!(
+            loader.take(missing),
+            Some(Err(ImportError::MissingFile(..)))
+        ));
+        assert_eq!(loader.state(missing), LoadState::Taken);
+        assert!(loader.take(missing).is_none());
+        assert_eq!(loader.progress(missing), 1.0);
+    }
+
+    #[test]
+    fn panics_while_decoding_become_errors() {
+        let result: Result<()> =
+            catch_panic(Path::new("broken.gltf"), || panic!("index out of bounds"));
+        match result {
+            Err(ImportError::Panicked { file, message }) => {
+                assert_eq!(file, PathBuf::from("broken.gltf"));
+                assert_eq!(message, "index out of bounds");
+            }
+            _ => panic!("the panic wasn't caught"),
+        }
+
+        let result: Result<u32> = catch_panic(Path::new("fine.gltf"), || Ok(1));
+        assert!(matches!(result, Ok(1)));
+    }
+}
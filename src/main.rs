@@ -3,8 +3,11 @@ extern crate glium;
 extern crate nalgebra_glm as glm;
 
 use glium::glutin;
+use learning_glium::animation::*;
+use learning_glium::blend::*;
 use learning_glium::camera::Camera;
 use learning_glium::import;
+use learning_glium::light::Light;
 use learning_glium::map::Map;
 use learning_glium::physics::*;
 use learning_glium::renderer::*;
@@ -20,14 +23,22 @@ fn main() {
     let mut schedule = Schedule::builder()
         .add_system(update_physics_system())
         .flush()
+        .add_thread_local(update_animations_system())
+        .flush()
+        .add_thread_local(update_state_machines_system())
+        .flush()
         .add_thread_local(update_model_transform_system())
         .flush()
+        .add_thread_local(begin_frame_system())
+        .flush()
         .add_thread_local(render_models_system())
         .flush()
         .add_thread_local(render_map_system())
         .flush()
         .add_thread_local(render_skybox_system())
         .flush()
+        .add_thread_local(render_blended_system())
+        .flush()
         .add_thread_local(update_target_system())
         .flush()
         .build();
@@ -41,6 +52,11 @@ fn main() {
     let fragment_shader_src = include_str!("shaders/entity.fs");
     let model_program = import::load_program(display, vertex_shader_src, fragment_shader_src);
 
+    // Same as the model program, with the vertices deformed by a skeleton
+    let skinned_vertex_shader_src = include_str!("shaders/skinned_entity.vs");
+    let skinned_model_program =
+        import::load_program(display, skinned_vertex_shader_src, fragment_shader_src);
+
     // Used to draw entire skybox
     let cubemap_vertex_src = include_str!("shaders/skybox.vs");
     let cubemap_fragment_src = include_str!("shaders/skybox.fs");
@@ -83,9 +99,19 @@ fn main() {
         irradiance_program,
         prefiltered_program,
         brdf_integration_program,
+    )
+    .unwrap();
+
+    let mut renderer = RendererState::new(
+        Camera::default(),
+        model_program,
+        skinned_model_program,
+        skybox_program,
     );
 
-    let mut renderer = RendererState::new(Camera::default(), model_program, skybox_program);
+    renderer
+        .lights
+        .push(Light::new(glm::vec3(-0.5, 0.1, -0.5), None));
 
     let akm_gltf_path = "assets/AKM_glTF";
     let akm_model_handle = import::model_from_gltf(display, &mut renderer, &akm_gltf_path).unwrap();
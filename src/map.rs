@@ -1,6 +1,7 @@
 use crate::model::ModelHandle;
 use crate::physics::PhysicsState;
 use crate::renderer::RendererState;
+use glium::index::PrimitiveType;
 use rapier3d::dynamics::{RigidBodyBuilder, RigidBodyHandle};
 use rapier3d::geometry::ColliderBuilder;
 
@@ -18,7 +19,12 @@ impl Map {
 
         for mesh in rs.get_model(&model).meshes.iter() {
             let mut offset = 0;
-            for primitive in mesh.primitives.iter() {
+            // Points and lines don't have a surface to collide with
+            for primitive in mesh
+                .primitives
+                .iter()
+                .filter(|primitive| primitive.primitive_type == PrimitiveType::TrianglesList)
+            {
                 let new_vertices = primitive.vbo.read().unwrap();
                 let new_indices = primitive
                     .ibo
@@ -1,41 +1,223 @@
-use glium::texture::{SrgbTexture2d, Texture2d};
-use glium::uniforms::Sampler;
+use glium::texture::{
+    CompressedSrgbTexture2d, CompressedTexture2d, RawImage2d, SrgbTexture2d, Texture2d,
+};
+use glium::uniforms::{
+    AsUniformValue, MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior,
+    SamplerWrapFunction, UniformValue,
+};
+use std::rc::Rc;
 
+/// How the alpha of the base colour is interpreted. The discriminants match the `alpha_mode`
+/// uniform of the entity shader.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaMode {
+    /// Alpha is ignored.
+    Opaque = 0,
+    /// Fragments with an alpha below the alpha cutoff are discarded.
+    Mask = 1,
+    /// Alpha blended over whatever is behind.
+    Blend = 2,
+}
+
+/// How a material texture is wrapped and filtered, imported from gltf samplers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureSampler {
+    pub wrap_s: SamplerWrapFunction,
+    pub wrap_t: SamplerWrapFunction,
+    pub minify_filter: MinifySamplerFilter,
+    pub magnify_filter: MagnifySamplerFilter,
+}
+
+impl Default for TextureSampler {
+    /// Repeating with trilinear filtering, which is what gltf suggests when a sampler leaves the
+    /// filters undefined.
+    fn default() -> Self {
+        Self {
+            wrap_s: SamplerWrapFunction::Repeat,
+            wrap_t: SamplerWrapFunction::Repeat,
+            minify_filter: MinifySamplerFilter::LinearMipmapLinear,
+            magnify_filter: MagnifySamplerFilter::Linear,
+        }
+    }
+}
+
+impl TextureSampler {
+    /// `anisotropy` of 1 disables anisotropic filtering, levels above what the driver supports
+    /// are clamped.
+    pub fn behavior(&self, anisotropy: u16) -> SamplerBehavior {
+        SamplerBehavior {
+            wrap_function: (self.wrap_s, self.wrap_t, SamplerWrapFunction::Repeat),
+            minify_filter: self.minify_filter,
+            magnify_filter: self.magnify_filter,
+            max_anisotropy: anisotropy,
+            ..Default::default()
+        }
+    }
+}
+
+/// An srgb material texture, block compressed when it was loaded from a .dds or .ktx2 file in a
+/// format the driver can sample.
+pub enum MaterialSrgbTexture {
+    Uncompressed(SrgbTexture2d),
+    Compressed(CompressedSrgbTexture2d),
+}
+
+impl MaterialSrgbTexture {
+    /// Reads back the largest mip level.
+    pub fn read(&self) -> RawImage2d<'static, u8> {
+        match self {
+            MaterialSrgbTexture::Uncompressed(texture) => texture.read(),
+            MaterialSrgbTexture::Compressed(texture) => texture.read(),
+        }
+    }
+}
+
+/// A linear material texture, block compressed when it was loaded from a .dds or .ktx2 file in a
+/// format the driver can sample.
+pub enum MaterialTexture {
+    Uncompressed(Texture2d),
+    Compressed(CompressedTexture2d),
+}
+
+impl MaterialTexture {
+    /// Reads back the largest mip level.
+    pub fn read(&self) -> RawImage2d<'static, u8> {
+        match self {
+            MaterialTexture::Uncompressed(texture) => texture.read(),
+            MaterialTexture::Compressed(texture) => texture.read(),
+        }
+    }
+}
+
+/// A material texture with the sampler it is drawn with, like glium's `Sampler` for the textures
+/// above.
+pub struct MaterialSampler<'t, T>(pub &'t T, pub SamplerBehavior);
+
+impl AsUniformValue for MaterialSampler<'_, MaterialSrgbTexture> {
+    fn as_uniform_value(&self) -> UniformValue<'_> {
+        match self.0 {
+            MaterialSrgbTexture::Uncompressed(texture) => {
+                UniformValue::SrgbTexture2d(texture, Some(self.1))
+            }
+            MaterialSrgbTexture::Compressed(texture) => {
+                UniformValue::CompressedSrgbTexture2d(texture, Some(self.1))
+            }
+        }
+    }
+}
+
+impl AsUniformValue for MaterialSampler<'_, MaterialTexture> {
+    fn as_uniform_value(&self) -> UniformValue<'_> {
+        match self.0 {
+            MaterialTexture::Uncompressed(texture) => {
+                UniformValue::Texture2d(texture, Some(self.1))
+            }
+            MaterialTexture::Compressed(texture) => {
+                UniformValue::CompressedTexture2d(texture, Some(self.1))
+            }
+        }
+    }
+}
+
+/// Textures are shared with other materials using the same image, see `TextureCache`.
 pub struct Material {
-    pub diffuse_map: SrgbTexture2d,
-    pub occlusion_roughness_metal_map: Texture2d,
-    pub normal_map: Texture2d,
+    pub diffuse_map: Rc<MaterialSrgbTexture>,
+    /// Roughness in the green channel and metalness in the blue channel.
+    pub metallic_roughness_map: Rc<MaterialTexture>,
+    /// Occlusion in the red channel.
+    pub occlusion_map: Rc<MaterialTexture>,
+    pub normal_map: Rc<MaterialTexture>,
+    pub emissive_map: Rc<MaterialSrgbTexture>,
+    /// Set when the occlusion is stored in the red channel of the metallic roughness map instead
+    /// of the occlusion map.
+    pub packed_occlusion: bool,
+    /// Multiplied with the diffuse map, alpha included.
+    pub base_colour_factor: [f32; 4],
+    /// Multiplied with the metalness stored in the metallic roughness map.
+    pub metallic_factor: f32,
+    /// Multiplied with the roughness stored in the metallic roughness map.
+    pub roughness_factor: f32,
+    /// Scales the x and y components of the sampled tangent space normal.
+    pub normal_scale: f32,
+    /// How much of the occlusion is applied, 0.0 being none.
+    pub occlusion_strength: f32,
+    /// Multiplied with the emissive map. Black by default, meaning nothing is emitted.
+    pub emissive_factor: [f32; 3],
+    /// Scales the emitted light past the [0, 1] range of the emissive factor.
+    pub emissive_strength: f32,
+    pub alpha_mode: AlphaMode,
+    /// Only used by `AlphaMode::Mask`.
+    pub alpha_cutoff: f32,
+    /// Disables backface culling, back faces are lit with their normal flipped.
+    pub double_sided: bool,
+    /// Which uv set of the vertices each map is sampled with, either 0 or 1.
+    pub diffuse_uv_set: u32,
+    pub metallic_roughness_uv_set: u32,
+    pub occlusion_uv_set: u32,
+    pub normal_uv_set: u32,
+    pub emissive_uv_set: u32,
+    pub diffuse_sampler: TextureSampler,
+    pub metallic_roughness_sampler: TextureSampler,
+    pub occlusion_sampler: TextureSampler,
+    pub normal_sampler: TextureSampler,
+    pub emissive_sampler: TextureSampler,
 }
 
 impl Material {
     pub fn new(
-        diffuse_map: SrgbTexture2d,
-        occlusion_roughness_metal_map: Texture2d,
-        normal_map: Texture2d,
+        diffuse_map: Rc<MaterialSrgbTexture>,
+        metallic_roughness_map: Rc<MaterialTexture>,
+        occlusion_map: Rc<MaterialTexture>,
+        normal_map: Rc<MaterialTexture>,
+        emissive_map: Rc<MaterialSrgbTexture>,
     ) -> Self { Self {
             diffuse_map,
-            occlusion_roughness_metal_map,
+            metallic_roughness_map,
+            occlusion_map,
             normal_map,
+            emissive_map,
+            packed_occlusion: false,
+            base_colour_factor: [1.0, 1.0, 1.0, 1.0],
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            emissive_factor: [0.0, 0.0, 0.0],
+            emissive_strength: 1.0,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+            diffuse_uv_set: 0,
+            metallic_roughness_uv_set: 0,
+            occlusion_uv_set: 0,
+            normal_uv_set: 0,
+            emissive_uv_set: 0,
+            diffuse_sampler: TextureSampler::default(),
+            metallic_roughness_sampler: TextureSampler::default(),
+            occlusion_sampler: TextureSampler::default(),
+            normal_sampler: TextureSampler::default(),
+            emissive_sampler: TextureSampler::default(),
         }
     }
 
-    pub fn diffuse_map(&self) -> Sampler<SrgbTexture2d> {
-        self.diffuse_map
-            .sampled()
-            .wrap_function(glium::uniforms::SamplerWrapFunction::Repeat)
-//            .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest)
+    pub fn diffuse_map(&self, anisotropy: u16) -> MaterialSampler<MaterialSrgbTexture> {
+        MaterialSampler(&*self.diffuse_map, self.diffuse_sampler.behavior(anisotropy))
     }
 
-    pub fn orm_map(&self) -> Sampler<Texture2d> {
-        self.occlusion_roughness_metal_map
-            .sampled()
-            .wrap_function(glium::uniforms::SamplerWrapFunction::Repeat)
-//            .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest)
+    pub fn metallic_roughness_map(&self, anisotropy: u16) -> MaterialSampler<MaterialTexture> {
+        let behavior = self.metallic_roughness_sampler.behavior(anisotropy);
+        MaterialSampler(&*self.metallic_roughness_map, behavior)
     }
-    pub fn normal_map(&self) -> Sampler<Texture2d> {
-        self.normal_map
-            .sampled()
-            .wrap_function(glium::uniforms::SamplerWrapFunction::Repeat)
-//            .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest)
+
+    pub fn occlusion_map(&self, anisotropy: u16) -> MaterialSampler<MaterialTexture> {
+        MaterialSampler(&*self.occlusion_map, self.occlusion_sampler.behavior(anisotropy))
+    }
+
+    pub fn normal_map(&self, anisotropy: u16) -> MaterialSampler<MaterialTexture> {
+        MaterialSampler(&*self.normal_map, self.normal_sampler.behavior(anisotropy))
+    }
+
+    pub fn emissive_map(&self, anisotropy: u16) -> MaterialSampler<MaterialSrgbTexture> {
+        MaterialSampler(&*self.emissive_map, self.emissive_sampler.behavior(anisotropy))
     }
 }
@@ -1,11 +1,23 @@
 use crate::primitive::Primitive;
-use na::{Isometry3, Vector3};
+use crate::skeleton::JointBuffer;
+use na::{Isometry3, Matrix4, Vector3};
 
 pub struct Mesh {
     pub primitives: Vec<Primitive>,
     pub base_isometry: Isometry3<f32>,
     pub updated_isometry: Isometry3<f32>,
     pub scaling: Vector3<f32>,
+    /// Index of the node this mesh is attached to within its model.
+    pub node: Option<usize>,
+    /// Index of the skeleton within its model which deforms this mesh.
+    pub skeleton: Option<usize>,
+    /// Joint matrices of the skeleton's current pose, present whenever `skeleton` is.
+    pub joint_buffer: Option<JointBuffer>,
+    /// Morph target weights currently blended into the primitives.
+    pub morph_weights: Vec<f32>,
+    /// Weights set here take precedence over the weights of the mesh's node, e.g. to drive a
+    /// single blend shape from code while the rest is animated.
+    pub weight_overrides: Vec<Option<f32>>,
 }
 
 impl Mesh {
@@ -19,6 +31,11 @@ impl Mesh {
             base_isometry,
             updated_isometry: base_isometry,
             scaling,
+            node: None,
+            skeleton: None,
+            joint_buffer: None,
+            morph_weights: Vec::new(),
+            weight_overrides: Vec::new(),
         }
     }
 
@@ -27,13 +44,44 @@ impl Mesh {
     }
 
     pub fn transformation(&self) -> [[f32; 4]; 4] {
+        self.transformation_matrix().into()
+    }
+
+    pub fn transformation_matrix(&self) -> Matrix4<f32> {
         self.updated_isometry
             .to_homogeneous()
             .prepend_nonuniform_scaling(&self.scaling)
-            .into()
     }
 
     pub fn update_isometry(&mut self, new_isometry: Isometry3<f32>) {
         self.updated_isometry = new_isometry;
     }
+
+    /// Overrides the weight of a single morph target, `None` hands it back to the node.
+    pub fn set_weight_override(&mut self, morph_target: usize, weight: Option<f32>) {
+        if self.weight_overrides.len() <= morph_target {
+            self.weight_overrides.resize(morph_target + 1, None);
+        }
+        self.weight_overrides[morph_target] = weight;
+    }
+
+    /// Blends the primitives' morph targets with the given weights, after applying the weight
+    /// overrides. Nothing is uploaded if the weights didn't change.
+    pub fn update_morph_weights(&mut self, weights: &[f32]) {
+        let count = weights.len().max(self.weight_overrides.len());
+        let weights: Vec<f32> = (0..count)
+            .map(|i| match self.weight_overrides.get(i) {
+                Some(Some(weight)) => *weight,
+                _ => weights.get(i).copied().unwrap_or(0.0),
+            })
+            .collect();
+
+        if weights == self.morph_weights {
+            return;
+        }
+        for primitive in self.primitives.iter_mut() {
+            primitive.apply_morph_weights(&weights);
+        }
+        self.morph_weights = weights;
+    }
 }
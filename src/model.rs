@@ -1,14 +1,75 @@
+use crate::animation::Animation;
+use crate::camera::Camera;
 use crate::material::Material;
 use crate::mesh::Mesh;
+use crate::node::{self, Node};
+use crate::pose::Pose;
+use crate::skeleton::Skeleton;
 
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    pub nodes: Vec<Node>,
+    pub skeletons: Vec<Skeleton>,
+    pub animations: Vec<Animation>,
+    /// Cameras placed in the scene, named after the camera or else its node.
+    pub cameras: Vec<(Option<String>, Camera)>,
+    /// The pose the nodes were imported in, which animations start out from.
+    pub rest_pose: Pose,
 }
 
 impl Model {
-    pub fn new(meshes: Vec<Mesh>, materials: Vec<Material>) -> Self {
-        Self { meshes, materials }
+    pub fn new(
+        meshes: Vec<Mesh>,
+        materials: Vec<Material>,
+        nodes: Vec<Node>,
+        skeletons: Vec<Skeleton>,
+        animations: Vec<Animation>,
+        cameras: Vec<(Option<String>, Camera)>,
+    ) -> Self {
+        let rest_pose = Pose::from_nodes(&nodes);
+        Self {
+            meshes,
+            materials,
+            nodes,
+            skeletons,
+            animations,
+            cameras,
+            rest_pose,
+        }
+    }
+
+    /// Finds an imported camera by name.
+    pub fn camera(&self, name: &str) -> Option<Camera> {
+        self.cameras
+            .iter()
+            .find(|(camera_name, _)| camera_name.as_deref() == Some(name))
+            .map(|(_, camera)| *camera)
+    }
+
+    /// Recomputes the base transformation of every mesh, the joint matrices of every skinned mesh
+    /// and the morphed vertices of every mesh with morph targets from the node hierarchy. Should
+    /// be called after changing the transformation or weights of any node.
+    pub fn update_mesh_transformations(&mut self) {
+        let transformations = node::global_transformations(&self.nodes);
+        for (node, transformation) in self.nodes.iter().zip(transformations.iter()) {
+            if let Some(mesh_index) = node.mesh {
+                let (isometry, scaling) = node::decompose(transformation);
+                let mesh = &mut self.meshes[mesh_index];
+                mesh.base_isometry = isometry;
+                mesh.updated_isometry = isometry;
+                mesh.scaling = scaling;
+                mesh.update_morph_weights(&node.weights);
+
+                if let (Some(skeleton_index), Some(joint_buffer)) =
+                    (mesh.skeleton, mesh.joint_buffer.as_ref())
+                {
+                    let joint_matrices = self.skeletons[skeleton_index]
+                        .joint_matrices(&transformations, transformation);
+                    joint_buffer.write(&joint_matrices);
+                }
+            }
+        }
     }
 }
 
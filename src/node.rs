@@ -0,0 +1,132 @@
+use na::{Isometry3, Matrix3, Matrix4, Translation3, UnitQuaternion, Vector3};
+
+/// A node of a model's scene graph. Nodes are stored flat inside of a model and refer to each
+/// other by index.
+pub struct Node {
+    pub name: Option<String>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// Index into the model's meshes, if this node has one.
+    pub mesh: Option<usize>,
+    pub translation: Vector3<f32>,
+    pub rotation: UnitQuaternion<f32>,
+    pub scale: Vector3<f32>,
+    /// Weights of the morph targets of this node's mesh.
+    pub weights: Vec<f32>,
+}
+
+impl Node {
+    pub fn new(
+        name: Option<String>,
+        translation: Vector3<f32>,
+        rotation: UnitQuaternion<f32>,
+        scale: Vector3<f32>,
+    ) -> Self {
+        Self {
+            name,
+            parent: None,
+            children: Vec::new(),
+            mesh: None,
+            translation,
+            rotation,
+            scale,
+            weights: Vec::new(),
+        }
+    }
+
+    /// Transformation of this node relative to its parent.
+    pub fn local_transformation(&self) -> Matrix4<f32> {
+        Isometry3::from_parts(Translation3::from(self.translation), self.rotation)
+            .to_homogeneous()
+            .prepend_nonuniform_scaling(&self.scale)
+    }
+}
+
+/// Computes the transformation of every node relative to the model, by composing each node with
+/// all of its parents.
+pub fn global_transformations(nodes: &[Node]) -> Vec<Matrix4<f32>> {
+    let local_transformations: Vec<Matrix4<f32>> = nodes
+        .iter()
+        .map(|node| node.local_transformation())
+        .collect();
+    compose_transformations(nodes, &local_transformations)
+}
+
+/// Same as `global_transformations`, with the local transformation of every node given instead of
+/// taken from the nodes themselves.
+pub fn compose_transformations(
+    nodes: &[Node],
+    local_transformations: &[Matrix4<f32>],
+) -> Vec<Matrix4<f32>> {
+    let mut transformations: Vec<Option<Matrix4<f32>>> = vec![None; nodes.len()];
+
+    fn visit(
+        index: usize,
+        nodes: &[Node],
+        local_transformations: &[Matrix4<f32>],
+        transformations: &mut Vec<Option<Matrix4<f32>>>,
+    ) {
+        if transformations[index].is_some() {
+            return;
+        }
+        let local = local_transformations[index];
+        let global = match nodes[index].parent {
+            Some(parent) => {
+                visit(parent, nodes, local_transformations, transformations);
+                transformations[parent].unwrap() * local
+            }
+            None => local,
+        };
+        transformations[index] = Some(global);
+    }
+
+    for index in 0..nodes.len() {
+        visit(index, nodes, local_transformations, &mut transformations);
+    }
+
+    transformations
+        .into_iter()
+        .map(|transformation| transformation.unwrap())
+        .collect()
+}
+
+/// Splits a transformation matrix into an isometry and a scaling. Shearing is discarded.
+pub fn decompose(transformation: &Matrix4<f32>) -> (Isometry3<f32>, Vector3<f32>) {
+    let translation = Translation3::new(
+        transformation[(0, 3)],
+        transformation[(1, 3)],
+        transformation[(2, 3)],
+    );
+
+    let mut linear = Matrix3::new(
+        transformation[(0, 0)],
+        transformation[(0, 1)],
+        transformation[(0, 2)],
+        transformation[(1, 0)],
+        transformation[(1, 1)],
+        transformation[(1, 2)],
+        transformation[(2, 0)],
+        transformation[(2, 1)],
+        transformation[(2, 2)],
+    );
+    let mut scaling = Vector3::new(
+        linear.column(0).norm(),
+        linear.column(1).norm(),
+        linear.column(2).norm(),
+    );
+
+    // A negative determinant means the transformation mirrors, fold that into the scaling
+    if linear.determinant() < 0.0 {
+        scaling.x = -scaling.x;
+    }
+
+    for i in 0..3 {
+        if scaling[i] != 0.0 {
+            let column = linear.column(i) / scaling[i];
+            linear.set_column(i, &column);
+        }
+    }
+
+    let rotation = UnitQuaternion::from_matrix(&linear);
+    (Isometry3::from_parts(translation, rotation), scaling)
+}
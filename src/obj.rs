@@ -0,0 +1,578 @@
+//! Parsing of Wavefront .obj and .mtl files into plain data, turned into models by
+//! `import::model_from_obj`.
+
+use std::fmt;
+
+/// A line of an .obj or .mtl file which couldn't be parsed.
+#[derive(Debug)]
+pub struct ParseError {
+    /// Starting from 1.
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub type Result<T> = std::result::Result<T, ParseError>;
+
+/// A corner of a face, made of zero based indices into the positions, texture coordinates and
+/// normals of the file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FaceVertex {
+    pub position: usize,
+    pub texture_coord: Option<usize>,
+    pub normal: Option<usize>,
+}
+
+/// The triangles of a group drawn with the same material.
+#[derive(Debug)]
+pub struct ObjPrimitive {
+    pub material: Option<String>,
+    /// Three corners per triangle, faces with more corners are triangulated as fans.
+    pub corners: Vec<FaceVertex>,
+}
+
+/// An object or group of an .obj file.
+#[derive(Debug)]
+pub struct ObjMesh {
+    pub name: Option<String>,
+    pub primitives: Vec<ObjPrimitive>,
+}
+
+#[derive(Debug, Default)]
+pub struct ObjData {
+    pub positions: Vec<[f32; 3]>,
+    /// Only present if every position has a colour, written as `v x y z r g b`.
+    pub colours: Option<Vec<[f32; 3]>>,
+    /// With the v axis pointing down, the way textures are uploaded.
+    pub texture_coords: Vec<[f32; 2]>,
+    pub normals: Vec<[f32; 3]>,
+    pub meshes: Vec<ObjMesh>,
+    /// Paths of the material libraries, relative to the .obj file.
+    pub material_libraries: Vec<String>,
+}
+
+/// A material of an .mtl file. Only the statements which have a counterpart in `Material` are
+/// kept.
+#[derive(Debug)]
+pub struct MtlMaterial {
+    pub name: String,
+    /// `Kd`
+    pub diffuse: [f32; 3],
+    /// `d`, or one minus `Tr`.
+    pub dissolve: f32,
+    /// `Ke`
+    pub emissive: Option<[f32; 3]>,
+    /// `Ns`, used to estimate the roughness when `Pr` is missing.
+    pub specular_exponent: Option<f32>,
+    /// `Pr`
+    pub roughness: Option<f32>,
+    /// `Pm`
+    pub metallic: Option<f32>,
+    /// `map_Kd`
+    pub diffuse_map: Option<String>,
+    /// `map_Ke`
+    pub emissive_map: Option<String>,
+    /// `map_Pr`
+    pub roughness_map: Option<String>,
+    /// `map_Pm`
+    pub metallic_map: Option<String>,
+    /// `norm`, or `map_Bump`/`bump` which exporters commonly use for normal maps as well.
+    pub normal_map: Option<String>,
+    /// The `-bm` option of the normal map.
+    pub normal_scale: f32,
+}
+
+impl MtlMaterial {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            diffuse: [1.0, 1.0, 1.0],
+            dissolve: 1.0,
+            emissive: None,
+            specular_exponent: None,
+            roughness: None,
+            metallic: None,
+            diffuse_map: None,
+            emissive_map: None,
+            roughness_map: None,
+            metallic_map: None,
+            normal_map: None,
+            normal_scale: 1.0,
+        }
+    }
+}
+
+// Joins lines ending in a backslash with the next one and strips comments, keeping the number of
+// the line each statement starts on
+fn statements(source: &str) -> Vec<(usize, String)> {
+    let mut statements: Vec<(usize, String)> = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+
+    for (i, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let (line, continues) = match line.trim_end().strip_suffix('\\') {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+
+        let (number, mut statement) = pending.take().unwrap_or((i + 1, String::new()));
+        statement.push(' ');
+        statement.push_str(line);
+
+        if continues {
+            pending = Some((number, statement));
+        } else if !statement.trim().is_empty() {
+            statements.push((number, statement.trim().to_string()));
+        }
+    }
+
+    if let Some((number, statement)) = pending {
+        if !statement.trim().is_empty() {
+            statements.push((number, statement.trim().to_string()));
+        }
+    }
+    statements
+}
+
+fn parse_floats(line: usize, arguments: &[&str]) -> Result<Vec<f32>> {
+    arguments
+        .iter()
+        .map(|argument| {
+            argument.parse::<f32>().map_err(|_| ParseError {
+                line,
+                reason: format!("{:?} is not a number", argument),
+            })
+        })
+        .collect()
+}
+
+// Reads the first `count` arguments as numbers, any following arguments are ignored
+fn parse_leading_floats(line: usize, arguments: &[&str], count: usize) -> Result<Vec<f32>> {
+    if arguments.len() < count {
+        return Err(ParseError {
+            line,
+            reason: format!("expected {} numbers, found {}", count, arguments.len()),
+        });
+    }
+    parse_floats(line, &arguments[..count])
+}
+
+fn parse_vector(line: usize, arguments: &[&str]) -> Result<[f32; 3]> {
+    let values = parse_leading_floats(line, arguments, 3)?;
+    Ok([values[0], values[1], values[2]])
+}
+
+fn parse_scalar(line: usize, arguments: &[&str]) -> Result<f32> {
+    Ok(parse_leading_floats(line, arguments, 1)?[0])
+}
+
+// Indices are one based, negative indices count back from the last element read so far
+fn resolve_index(line: usize, index: &str, count: usize) -> Result<usize> {
+    let invalid = |reason: &str| ParseError {
+        line,
+        reason: format!("{} index {:?}", reason, index),
+    };
+
+    let value = index.parse::<i64>().map_err(|_| invalid("malformed"))?;
+    let resolved = if value < 0 {
+        count as i64 + value
+    } else {
+        value - 1
+    };
+
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(invalid("out of range"));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_face_vertex(line: usize, corner: &str, data: &ObjData) -> Result<FaceVertex> {
+    let mut parts = corner.split('/');
+    let position = resolve_index(line, parts.next().unwrap_or(""), data.positions.len())?;
+    let texture_coord = match parts.next() {
+        Some(index) if !index.is_empty() => {
+            Some(resolve_index(line, index, data.texture_coords.len())?)
+        }
+        _ => None,
+    };
+    let normal = match parts.next() {
+        Some(index) if !index.is_empty() => Some(resolve_index(line, index, data.normals.len())?),
+        _ => None,
+    };
+
+    Ok(FaceVertex {
+        position,
+        texture_coord,
+        normal,
+    })
+}
+
+/// Parses the geometry of an .obj file. Objects and groups each become a mesh, which is split
+/// into primitives wherever the material changes. Points, lines and smoothing groups are ignored.
+pub fn parse_obj(source: &str) -> Result<ObjData> {
+    let mut data = ObjData::default();
+    let mut colours: Vec<[f32; 3]> = Vec::new();
+    let mut material: Option<String> = None;
+
+    for (line, statement) in statements(source) {
+        let mut tokens = statement.split_whitespace();
+        let keyword = tokens.next().unwrap_or("");
+        let arguments: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                data.positions.push(parse_vector(line, &arguments)?);
+                if arguments.len() >= 6 {
+                    colours.push(parse_vector(line, &arguments[3..])?);
+                }
+            }
+            "vt" => {
+                // v is optional and the w of 3d textures is ignored
+                let u = parse_scalar(line, &arguments)?;
+                let v = match arguments.get(1) {
+                    Some(_) => parse_scalar(line, &arguments[1..])?,
+                    None => 0.0,
+                };
+                data.texture_coords.push([u, 1.0 - v]);
+            }
+            "vn" => data.normals.push(parse_vector(line, &arguments)?),
+            "f" => {
+                if arguments.len() < 3 {
+                    return Err(ParseError {
+                        line,
+                        reason: "face with less than three corners".to_string(),
+                    });
+                }
+                let corners = arguments
+                    .iter()
+                    .map(|corner| parse_face_vertex(line, corner, &data))
+                    .collect::<Result<Vec<FaceVertex>>>()?;
+
+                if data.meshes.is_empty() {
+                    data.meshes.push(ObjMesh {
+                        name: None,
+                        primitives: Vec::new(),
+                    });
+                }
+                let mesh = data.meshes.last_mut().unwrap();
+                let needs_primitive = match mesh.primitives.last() {
+                    Some(primitive) => primitive.material != material,
+                    None => true,
+                };
+                if needs_primitive {
+                    mesh.primitives.push(ObjPrimitive {
+                        material: material.clone(),
+                        corners: Vec::new(),
+                    });
+                }
+
+                let primitive = mesh.primitives.last_mut().unwrap();
+                for i in 1..corners.len() - 1 {
+                    primitive
+                        .corners
+                        .extend_from_slice(&[corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            "o" | "g" => {
+                let name = if arguments.is_empty() {
+                    None
+                } else {
+                    Some(arguments.join(" "))
+                };
+                // Consecutive names without any faces in between only rename the mesh
+                match data.meshes.last_mut() {
+                    Some(mesh) if mesh.primitives.is_empty() => mesh.name = name,
+                    _ => data.meshes.push(ObjMesh {
+                        name,
+                        primitives: Vec::new(),
+                    }),
+                }
+            }
+            "usemtl" => material = Some(arguments.join(" ")),
+            "mtllib" => data
+                .material_libraries
+                .extend(arguments.iter().map(|library| library.to_string())),
+            _ => {}
+        }
+    }
+
+    data.meshes.retain(|mesh| !mesh.primitives.is_empty());
+    if !colours.is_empty() && colours.len() == data.positions.len() {
+        data.colours = Some(colours);
+    }
+    Ok(data)
+}
+
+// Options of a texture statement with their numeric arguments, such as ("-bm", [0.5])
+type TextureOptions = Vec<(String, Vec<f32>)>;
+
+// Splits the arguments of a texture statement into its options and the file name, which may
+// contain spaces
+fn parse_texture(line: usize, arguments: &[&str]) -> Result<(TextureOptions, String)> {
+    let mut options: TextureOptions = Vec::new();
+    let mut i = 0;
+
+    while i < arguments.len() && arguments[i].starts_with('-') {
+        let option = arguments[i].to_string();
+        i += 1;
+        let mut values: Vec<f32> = Vec::new();
+        match option.as_str() {
+            // Up to three numbers
+            "-o" | "-s" | "-t" => {
+                while values.len() < 3 && i < arguments.len() {
+                    match arguments[i].parse::<f32>() {
+                        Ok(value) => values.push(value),
+                        Err(_) => break,
+                    }
+                    i += 1;
+                }
+            }
+            "-mm" => {
+                values = parse_floats(line, &arguments[i..(i + 2).min(arguments.len())])?;
+                i += 2;
+            }
+            "-bm" | "-boost" | "-texres" => {
+                values = parse_floats(line, &arguments[i..(i + 1).min(arguments.len())])?;
+                i += 1;
+            }
+            // Options with a single non numeric argument, such as "-clamp on"
+            _ => i += 1,
+        }
+        options.push((option, values));
+    }
+
+    if i >= arguments.len() {
+        return Err(ParseError {
+            line,
+            reason: "texture statement without a file name".to_string(),
+        });
+    }
+    Ok((options, arguments[i..].join(" ")))
+}
+
+/// Parses the materials of an .mtl file.
+pub fn parse_mtl(source: &str) -> Result<Vec<MtlMaterial>> {
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+
+    for (line, statement) in statements(source) {
+        let mut tokens = statement.split_whitespace();
+        let keyword = tokens.next().unwrap_or("");
+        let arguments: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            materials.push(MtlMaterial::new(&arguments.join(" ")));
+            continue;
+        }
+
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            // Statements before the first material don't belong to anything
+            None => continue,
+        };
+
+        match keyword {
+            "Kd" => material.diffuse = parse_vector(line, &arguments)?,
+            "d" => material.dissolve = parse_scalar(line, &arguments)?,
+            "Tr" => material.dissolve = 1.0 - parse_scalar(line, &arguments)?,
+            "Ke" => material.emissive = Some(parse_vector(line, &arguments)?),
+            "Ns" => material.specular_exponent = Some(parse_scalar(line, &arguments)?),
+            "Pr" => material.roughness = Some(parse_scalar(line, &arguments)?),
+            "Pm" => material.metallic = Some(parse_scalar(line, &arguments)?),
+            "map_Kd" => material.diffuse_map = Some(parse_texture(line, &arguments)?.1),
+            "map_Ke" => material.emissive_map = Some(parse_texture(line, &arguments)?.1),
+            "map_Pr" => material.roughness_map = Some(parse_texture(line, &arguments)?.1),
+            "map_Pm" => material.metallic_map = Some(parse_texture(line, &arguments)?.1),
+            "norm" | "map_Bump" | "map_bump" | "bump" => {
+                // A dedicated normal map wins over a bump map
+                if keyword != "norm" && material.normal_map.is_some() {
+                    continue;
+                }
+                let (options, file_name) = parse_texture(line, &arguments)?;
+                material.normal_map = Some(file_name);
+                if let Some((_, values)) = options.iter().find(|(option, _)| option == "-bm") {
+                    material.normal_scale = values.first().copied().unwrap_or(1.0);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corner(position: usize, texture_coord: Option<usize>, normal: Option<usize>) -> FaceVertex {
+        FaceVertex {
+            position,
+            texture_coord,
+            normal,
+        }
+    }
+
+    const TRIANGLE: &str = "
+        v 0 0 0
+        v 1 0 0
+        v 0 1 0
+        vt 0 0
+        vt 1 0
+        vt 0 1
+        vn 0 0 1
+    ";
+
+    #[test]
+    fn face_vertex_forms() {
+        let source = format!(
+            "{}\nf 1/1/1 2/2/1 3/3/1\nf 1//1 2//1 3//1\nf 1/1 2/2 3/3\nf 1 2 3",
+            TRIANGLE
+        );
+        let data = parse_obj(&source).unwrap();
+        let corners = &data.meshes[0].primitives[0].corners;
+
+        assert_eq!(corners.len(), 12);
+        assert_eq!(corners[1], corner(1, Some(1), Some(0)));
+        assert_eq!(corners[4], corner(1, None, Some(0)));
+        assert_eq!(corners[7], corner(1, Some(1), None));
+        assert_eq!(corners[10], corner(1, None, None));
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_last_element() {
+        let source = format!(
+            "{}\nf -3/-3/-1 -2/-2/-1 -1/-1/-1\nv 5 5 5\nf -4 -3 -1",
+            TRIANGLE
+        );
+        let data = parse_obj(&source).unwrap();
+        let corners = &data.meshes[0].primitives[0].corners;
+
+        assert_eq!(corners[0], corner(0, Some(0), Some(0)));
+        assert_eq!(corners[2], corner(2, Some(2), Some(0)));
+        // Relative to the positions read before the face
+        assert_eq!(corners[3], corner(0, None, None));
+        assert_eq!(corners[5], corner(3, None, None));
+    }
+
+    #[test]
+    fn out_of_range_indices_are_an_error() {
+        for face in [
+            "f 1 2 4",
+            "f 0 1 2",
+            "f -4 1 2",
+            "f 1/4 2 3",
+            "f 1//2 2 3",
+            "f a 2 3",
+        ]
+        .iter()
+        {
+            let error = parse_obj(&format!("{}\n{}", TRIANGLE, face)).unwrap_err();
+            assert_eq!(error.line, TRIANGLE.lines().count() + 1, "{}", face);
+        }
+        assert!(parse_obj(&format!("{}\nf 1 2", TRIANGLE)).is_err());
+    }
+
+    #[test]
+    fn polygons_are_triangulated_as_fans() {
+        let data = parse_obj("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv -1 1 0\nf 1 2 3 4 5").unwrap();
+        let positions: Vec<usize> = data.meshes[0].primitives[0]
+            .corners
+            .iter()
+            .map(|corner| corner.position)
+            .collect();
+
+        assert_eq!(positions, vec![0, 1, 2, 0, 2, 3, 0, 3, 4]);
+    }
+
+    #[test]
+    fn primitives_are_split_per_material() {
+        let source = format!(
+            "{}\nmtllib scene.mtl\no first\nusemtl red\nf 1 2 3\nusemtl blue\nf 1 2 3\nf 3 2 1\n\
+             usemtl red\nf 1 2 3\ng second\nf 1 2 3",
+            TRIANGLE
+        );
+        let data = parse_obj(&source).unwrap();
+
+        assert_eq!(data.material_libraries, vec!["scene.mtl".to_string()]);
+        assert_eq!(data.meshes.len(), 2);
+        let materials: Vec<Option<&str>> = data.meshes[0]
+            .primitives
+            .iter()
+            .map(|primitive| primitive.material.as_deref())
+            .collect();
+        assert_eq!(materials, vec![Some("red"), Some("blue"), Some("red")]);
+        assert_eq!(data.meshes[0].primitives[1].corners.len(), 6);
+        // The material carries over into the next group
+        assert_eq!(data.meshes[1].name.as_deref(), Some("second"));
+        assert_eq!(
+            data.meshes[1].primitives[0].material.as_deref(),
+            Some("red")
+        );
+    }
+
+    #[test]
+    fn texture_coordinates_are_flipped_and_colours_kept() {
+        let data = parse_obj("v 0 0 0 1 0 0\nv 1 0 0 0 1 0\nvt 0.25 0.25\nvt 0.5").unwrap();
+
+        assert_eq!(data.texture_coords, vec![[0.25, 0.75], [0.5, 1.0]]);
+        assert_eq!(data.colours, Some(vec![[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]));
+        assert!(data.meshes.is_empty());
+    }
+
+    #[test]
+    fn lines_are_continued_and_comments_stripped() {
+        let data = parse_obj("v 1 \\\n 2 3 # a comment\n# v 4 5 6").unwrap();
+        assert_eq!(data.positions, vec![[1.0, 2.0, 3.0]]);
+    }
+
+    #[test]
+    fn mtl_materials() {
+        let source = "
+            Kd 0 0 0
+            newmtl red paint
+            Kd 1 0 0
+            Tr 0.25
+            map_Kd -s 2 2 -clamp on textures/red paint.png
+            newmtl bumpy
+            map_Bump -bm 0.5 bump.png
+            norm normal.png
+        ";
+        let materials = parse_mtl(source).unwrap();
+
+        assert_eq!(materials.len(), 2);
+        assert_eq!(materials[0].name, "red paint");
+        assert_eq!(materials[0].diffuse, [1.0, 0.0, 0.0]);
+        assert_eq!(materials[0].dissolve, 0.75);
+        assert_eq!(
+            materials[0].diffuse_map.as_deref(),
+            Some("textures/red paint.png")
+        );
+        assert_eq!(materials[1].diffuse, [1.0, 1.0, 1.0]);
+        assert_eq!(materials[1].normal_map.as_deref(), Some("normal.png"));
+        assert_eq!(materials[1].normal_scale, 0.5);
+    }
+
+    #[test]
+    fn mtl_statements_with_missing_arguments_are_an_error() {
+        for statement in [
+            "Kd 1 0",
+            "Kd 1 0 red",
+            "d",
+            "map_Kd",
+            "map_Kd -s 1 1 1",
+            "map_Bump -mm 1 x a.png",
+        ]
+        .iter()
+        {
+            let error = parse_mtl(&format!("newmtl test\n{}", statement)).unwrap_err();
+            assert_eq!(error.line, 2, "{}", statement);
+        }
+    }
+}
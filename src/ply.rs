@@ -0,0 +1,511 @@
+//! Parsing of Stanford .ply files into plain data, turned into models by `import::model_from_ply`.
+
+use std::fmt;
+
+/// A .ply file which couldn't be parsed.
+#[derive(Debug)]
+pub struct ParseError {
+    pub reason: String,
+}
+
+impl ParseError {
+    fn new(reason: &str) -> Self {
+        Self {
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub type Result<T> = std::result::Result<T, ParseError>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "char" | "int8" => Ok(ScalarType::I8),
+            "uchar" | "uint8" => Ok(ScalarType::U8),
+            "short" | "int16" => Ok(ScalarType::I16),
+            "ushort" | "uint16" => Ok(ScalarType::U16),
+            "int" | "int32" => Ok(ScalarType::I32),
+            "uint" | "uint32" => Ok(ScalarType::U32),
+            "float" | "float32" => Ok(ScalarType::F32),
+            "double" | "float64" => Ok(ScalarType::F64),
+            _ => Err(ParseError {
+                reason: format!("unknown property type {:?}", name),
+            }),
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum PropertyType {
+    Scalar(ScalarType),
+    /// The type of the item count, then the type of the items.
+    List(ScalarType, ScalarType),
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    property_type: PropertyType,
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// The vertices and faces of a .ply file. Files without faces are point clouds.
+#[derive(Debug, Default)]
+pub struct PlyData {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Option<Vec<[f32; 3]>>,
+    /// Integer colours are mapped into [0, 1], alpha is 1.0 unless the file has it.
+    pub colours: Option<Vec<[f32; 4]>>,
+    /// With the v axis pointing down, the way textures are uploaded.
+    pub texture_coords: Option<Vec<[f32; 2]>>,
+    /// Three indices per triangle, faces with more corners are triangulated as fans.
+    pub indices: Vec<u32>,
+}
+
+// Reads the values of a file one at a time, either as whitespace separated text or packed binary
+struct Reader<'a> {
+    format: Format,
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, scalar_type: ScalarType) -> Result<f64> {
+        if self.format == Format::Ascii {
+            return self.read_ascii();
+        }
+
+        let size = scalar_type.size();
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + size)
+            .ok_or_else(|| ParseError::new("unexpected end of file"))?;
+        self.position += size;
+
+        let mut buffer = [0u8; 8];
+        buffer[..size].copy_from_slice(bytes);
+        if self.format == Format::BinaryBigEndian {
+            buffer[..size].reverse();
+        }
+
+        let value = match scalar_type {
+            ScalarType::I8 => f64::from(buffer[0] as i8),
+            ScalarType::U8 => f64::from(buffer[0]),
+            ScalarType::I16 => f64::from(i16::from_le_bytes([buffer[0], buffer[1]])),
+            ScalarType::U16 => f64::from(u16::from_le_bytes([buffer[0], buffer[1]])),
+            ScalarType::I32 => f64::from(i32::from_le_bytes([
+                buffer[0], buffer[1], buffer[2], buffer[3],
+            ])),
+            ScalarType::U32 => f64::from(u32::from_le_bytes([
+                buffer[0], buffer[1], buffer[2], buffer[3],
+            ])),
+            ScalarType::F32 => f64::from(f32::from_le_bytes([
+                buffer[0], buffer[1], buffer[2], buffer[3],
+            ])),
+            ScalarType::F64 => f64::from_le_bytes(buffer),
+        };
+        Ok(value)
+    }
+
+    fn read_ascii(&mut self) -> Result<f64> {
+        while self.position < self.bytes.len() && self.bytes[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+        let start = self.position;
+        while self.position < self.bytes.len() && !self.bytes[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+
+        if start == self.position {
+            return Err(ParseError::new("unexpected end of file"));
+        }
+        let token = String::from_utf8_lossy(&self.bytes[start..self.position]);
+        token.parse::<f64>().map_err(|_| ParseError {
+            reason: format!("{:?} is not a number", token),
+        })
+    }
+}
+
+// Parses the header up to and including "end_header", returning the format, the elements and
+// where the body starts
+fn parse_header(bytes: &[u8]) -> Result<(Format, Vec<Element>, usize)> {
+    let mut format: Option<Format> = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut position = 0;
+    let mut first_line = true;
+
+    loop {
+        let end = bytes[position..]
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .map(|end| position + end)
+            .ok_or_else(|| ParseError::new("header isn't terminated by end_header"))?;
+        let line = String::from_utf8_lossy(&bytes[position..end]);
+        position = end + 1;
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if first_line {
+            if tokens.first() != Some(&"ply") {
+                return Err(ParseError::new("file doesn't start with \"ply\""));
+            }
+            first_line = false;
+            continue;
+        }
+
+        match tokens.as_slice() {
+            ["format", name, ..] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => {
+                        return Err(ParseError {
+                            reason: format!("unknown format {:?}", name),
+                        })
+                    }
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| ParseError::new("malformed element count"))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count_type, item_type, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| ParseError::new("property outside of an element"))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    property_type: PropertyType::List(
+                        ScalarType::from_name(count_type)?,
+                        ScalarType::from_name(item_type)?,
+                    ),
+                });
+            }
+            ["property", scalar_type, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| ParseError::new("property outside of an element"))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    property_type: PropertyType::Scalar(ScalarType::from_name(scalar_type)?),
+                });
+            }
+            ["end_header", ..] => break,
+            // Comments, obj_info and blank lines
+            _ => {}
+        }
+    }
+
+    let format = format.ok_or_else(|| ParseError::new("header has no format"))?;
+    Ok((format, elements, position))
+}
+
+/// Parses an ascii or binary .ply file. Only the vertex and face elements are kept, the
+/// properties used are x/y/z, nx/ny/nz, red/green/blue/alpha, u/v (or s/t) and
+/// vertex_indices (or vertex_index).
+pub fn parse_ply(bytes: &[u8]) -> Result<PlyData> {
+    let (format, elements, body) = parse_header(bytes)?;
+    let mut reader = Reader {
+        format,
+        bytes,
+        position: body,
+    };
+
+    let mut data = PlyData::default();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut colours: Vec<[f32; 4]> = Vec::new();
+    let mut texture_coords: Vec<[f32; 2]> = Vec::new();
+
+    for element in elements.iter() {
+        let has_property = |names: &[&str]| {
+            element
+                .properties
+                .iter()
+                .any(|property| names.contains(&property.name.as_str()))
+        };
+        let has_normals = has_property(&["nx"]);
+        let has_colours = has_property(&["red", "r", "diffuse_red"]);
+        let has_texture_coords = has_property(&["u", "s", "texture_u"]);
+
+        for _ in 0..element.count {
+            let mut position = [0.0; 3];
+            let mut normal = [0.0; 3];
+            let mut colour = [1.0; 4];
+            let mut texture_coord = [0.0; 2];
+
+            for property in element.properties.iter() {
+                let scalar_type = match property.property_type {
+                    PropertyType::Scalar(scalar_type) => scalar_type,
+                    PropertyType::List(count_type, item_type) => {
+                        let count = reader.read(count_type)? as usize;
+                        let mut items: Vec<u32> = Vec::new();
+                        for _ in 0..count {
+                            items.push(reader.read(item_type)? as u32);
+                        }
+
+                        let is_face = element.name == "face"
+                            && (property.name == "vertex_indices"
+                                || property.name == "vertex_index");
+                        if is_face && count >= 3 {
+                            for i in 1..count - 1 {
+                                data.indices
+                                    .extend_from_slice(&[items[0], items[i], items[i + 1]]);
+                            }
+                        }
+                        continue;
+                    }
+                };
+
+                let value = reader.read(scalar_type)?;
+                // Integer colours use their whole range
+                let colour_value = match scalar_type {
+                    ScalarType::U8 => value / 255.0,
+                    ScalarType::U16 => value / 65535.0,
+                    _ => value,
+                } as f32;
+
+                match (element.name.as_str(), property.name.as_str()) {
+                    ("vertex", "x") => position[0] = value as f32,
+                    ("vertex", "y") => position[1] = value as f32,
+                    ("vertex", "z") => position[2] = value as f32,
+                    ("vertex", "nx") => normal[0] = value as f32,
+                    ("vertex", "ny") => normal[1] = value as f32,
+                    ("vertex", "nz") => normal[2] = value as f32,
+                    ("vertex", "red") | ("vertex", "r") | ("vertex", "diffuse_red") => {
+                        colour[0] = colour_value
+                    }
+                    ("vertex", "green") | ("vertex", "g") | ("vertex", "diffuse_green") => {
+                        colour[1] = colour_value
+                    }
+                    ("vertex", "blue") | ("vertex", "b") | ("vertex", "diffuse_blue") => {
+                        colour[2] = colour_value
+                    }
+                    ("vertex", "alpha") | ("vertex", "a") => colour[3] = colour_value,
+                    ("vertex", "u") | ("vertex", "s") | ("vertex", "texture_u") => {
+                        texture_coord[0] = value as f32
+                    }
+                    ("vertex", "v") | ("vertex", "t") | ("vertex", "texture_v") => {
+                        texture_coord[1] = 1.0 - value as f32
+                    }
+                    _ => {}
+                }
+            }
+
+            if element.name == "vertex" {
+                data.positions.push(position);
+                if has_normals {
+                    normals.push(normal);
+                }
+                if has_colours {
+                    colours.push(colour);
+                }
+                if has_texture_coords {
+                    texture_coords.push(texture_coord);
+                }
+            }
+        }
+    }
+
+    let vertex_count = data.positions.len() as u32;
+    if data.indices.iter().any(|index| *index >= vertex_count) {
+        return Err(ParseError::new(
+            "face refers to a vertex which does not exist",
+        ));
+    }
+
+    data.normals = Some(normals).filter(|normals| !normals.is_empty());
+    data.colours = Some(colours).filter(|colours| !colours.is_empty());
+    data.texture_coords = Some(texture_coords).filter(|texture_coords| !texture_coords.is_empty());
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary(header: &str, body: &[u8]) -> Vec<u8> {
+        let mut bytes = header.as_bytes().to_vec();
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    const TRIANGLE_HEADER: &str = "ply
+format binary_little_endian 1.0
+element vertex 3
+property float x
+property float y
+property float z
+element face 1
+property list uchar uint vertex_indices
+end_header
+";
+
+    fn triangle_vertices() -> Vec<u8> {
+        [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]
+            .iter()
+            .flat_map(|value| value.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn ascii_with_vertex_attributes_and_polygons() {
+        let source = "ply
+format ascii 1.0
+comment made by hand
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property uchar red
+property uchar green
+property uchar blue
+property float s
+property float t
+element face 1
+property list uchar int vertex_index
+end_header
+0 0 0 0 0 1 255 0 0 0 0
+1 0 0 0 0 1 0 255 0 1 0
+1 1 0 0 0 1 0 0 255 1 1
+0 1 0 0 0 1 255 255 255 0 0.25
+4 0 1 2 3
+";
+        let data = parse_ply(source.as_bytes()).unwrap();
+
+        assert_eq!(data.positions.len(), 4);
+        assert_eq!(data.positions[2], [1.0, 1.0, 0.0]);
+        assert_eq!(data.normals.unwrap()[0], [0.0, 0.0, 1.0]);
+        let colours = data.colours.unwrap();
+        assert_eq!(colours[1], [0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(data.texture_coords.unwrap()[3], [0.0, 0.75]);
+        assert_eq!(data.indices, vec![0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn binary_little_endian() {
+        let mut body = triangle_vertices();
+        body.push(3);
+        for index in [2u32, 1, 0].iter() {
+            body.extend_from_slice(&index.to_le_bytes());
+        }
+        let data = parse_ply(&binary(TRIANGLE_HEADER, &body)).unwrap();
+
+        assert_eq!(data.positions[1], [1.0, 0.0, 0.0]);
+        assert_eq!(data.indices, vec![2, 1, 0]);
+        assert!(data.normals.is_none());
+        assert!(data.colours.is_none());
+    }
+
+    #[test]
+    fn binary_big_endian() {
+        let header = TRIANGLE_HEADER.replace("binary_little_endian", "binary_big_endian");
+        let mut body: Vec<u8> = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]
+            .iter()
+            .flat_map(|value| value.to_be_bytes().to_vec())
+            .collect();
+        body.push(3);
+        for index in [0u32, 1, 2].iter() {
+            body.extend_from_slice(&index.to_be_bytes());
+        }
+        let data = parse_ply(&binary(&header, &body)).unwrap();
+
+        assert_eq!(data.positions[2], [0.0, 1.0, 0.0]);
+        assert_eq!(data.indices, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn list_counts_beyond_the_end_of_the_file_are_an_error() {
+        let header = TRIANGLE_HEADER.replace("list uchar", "list uint");
+        let mut body = triangle_vertices();
+        body.extend_from_slice(&u32::MAX.to_le_bytes());
+        body.extend_from_slice(&[0; 12]);
+        assert!(parse_ply(&binary(&header, &body)).is_err());
+
+        let source =
+            "ply\nformat ascii 1.0\nelement face 1\nproperty list uchar int vertex_index\n\
+                      end_header\n1e30 0 1 2\n";
+        assert!(parse_ply(source.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn faces_referring_to_missing_vertices_are_an_error() {
+        let mut body = triangle_vertices();
+        body.push(3);
+        for index in [0u32, 1, 3].iter() {
+            body.extend_from_slice(&index.to_le_bytes());
+        }
+        let error = parse_ply(&binary(TRIANGLE_HEADER, &body)).unwrap_err();
+        assert_eq!(error.reason, "face refers to a vertex which does not exist");
+    }
+
+    #[test]
+    fn malformed_headers_are_an_error() {
+        assert!(parse_ply(b"ply\nformat ascii 1.0\nelement vertex 0\n").is_err());
+        assert!(parse_ply(b"PLY\nformat ascii 1.0\nend_header\n").is_err());
+        assert!(parse_ply(b"ply\nend_header\n").is_err());
+        assert!(parse_ply(b"ply\nformat utf8 1.0\nend_header\n").is_err());
+        assert!(parse_ply(b"ply\nformat ascii 1.0\nelement vertex -1\nend_header\n").is_err());
+        assert!(parse_ply(b"ply\nformat ascii 1.0\nproperty float x\nend_header\n").is_err());
+        assert!(parse_ply(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty half x\n").is_err());
+    }
+
+    #[test]
+    fn truncated_bodies_are_an_error() {
+        let mut body = triangle_vertices();
+        body.push(3);
+        body.extend_from_slice(&[0; 11]);
+        assert!(parse_ply(&binary(TRIANGLE_HEADER, &body)).is_err());
+
+        let source = "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nend_header\n1\n";
+        assert!(parse_ply(source.as_bytes()).is_err());
+    }
+}
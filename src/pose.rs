@@ -0,0 +1,369 @@
+use crate::node::{self, Node};
+use na::{Isometry3, Matrix4, Translation3, UnitQuaternion, Vector3};
+
+/// The local transformation and morph weights of a single node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodePose {
+    pub translation: Vector3<f32>,
+    pub rotation: UnitQuaternion<f32>,
+    pub scale: Vector3<f32>,
+    pub weights: Vec<f32>,
+}
+
+impl NodePose {
+    pub fn from_node(node: &Node) -> Self {
+        Self {
+            translation: node.translation,
+            rotation: node.rotation,
+            scale: node.scale,
+            weights: node.weights.clone(),
+        }
+    }
+
+    /// Transformation of the node relative to its parent.
+    pub fn local_transformation(&self) -> Matrix4<f32> {
+        Isometry3::from_parts(Translation3::from(self.translation), self.rotation)
+            .to_homogeneous()
+            .prepend_nonuniform_scaling(&self.scale)
+    }
+}
+
+/// A snapshot of every node of a model. Poses can be sampled from animations and blended on the
+/// CPU before being applied to the model.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pose {
+    pub nodes: Vec<NodePose>,
+}
+
+impl Pose {
+    /// The pose the nodes are currently in.
+    pub fn from_nodes(nodes: &[Node]) -> Self {
+        Self {
+            nodes: nodes.iter().map(NodePose::from_node).collect(),
+        }
+    }
+
+    /// Moves the nodes into this pose. Call `Model::update_mesh_transformations` afterwards for the
+    /// meshes to follow.
+    pub fn apply(&self, nodes: &mut [Node]) {
+        for (node, node_pose) in nodes.iter_mut().zip(self.nodes.iter()) {
+            node.translation = node_pose.translation;
+            node.rotation = node_pose.rotation;
+            node.scale = node_pose.scale;
+            node.weights = node_pose.weights.clone();
+        }
+    }
+
+    /// Interpolates between this pose and another, `factor` 0.0 being this pose and 1.0 the other.
+    pub fn blend(&self, other: &Pose, factor: f32) -> Pose {
+        let nodes = self
+            .nodes
+            .iter()
+            .zip(other.nodes.iter())
+            .map(|(a, b)| NodePose {
+                translation: a.translation.lerp(&b.translation, factor),
+                rotation: slerp(&a.rotation, &b.rotation, factor),
+                scale: a.scale.lerp(&b.scale, factor),
+                weights: lerp_weights(&a.weights, &b.weights, factor),
+            })
+            .collect();
+
+        Pose { nodes }
+    }
+
+    /// Layers the difference between `additive` and `reference` on top of this pose, scaled by
+    /// `weight`. Used for additive animations such as breathing or aiming offsets.
+    pub fn add(&self, additive: &Pose, reference: &Pose, weight: f32) -> Pose {
+        let nodes = self
+            .nodes
+            .iter()
+            .zip(additive.nodes.iter())
+            .zip(reference.nodes.iter())
+            .map(|((base, additive), reference)| {
+                let rotation_difference = additive.rotation * reference.rotation.inverse();
+                let scale_difference = Vector3::new(
+                    scale_ratio(additive.scale.x, reference.scale.x),
+                    scale_ratio(additive.scale.y, reference.scale.y),
+                    scale_ratio(additive.scale.z, reference.scale.z),
+                );
+                let weight_difference: Vec<f32> = additive
+                    .weights
+                    .iter()
+                    .zip(reference.weights.iter())
+                    .map(|(additive, reference)| additive - reference)
+                    .collect();
+
+                NodePose {
+                    translation: base.translation
+                        + (additive.translation - reference.translation) * weight,
+                    rotation: slerp(&UnitQuaternion::identity(), &rotation_difference, weight)
+                        * base.rotation,
+                    scale: base.scale.component_mul(
+                        &Vector3::new(1.0, 1.0, 1.0).lerp(&scale_difference, weight),
+                    ),
+                    weights: base
+                        .weights
+                        .iter()
+                        .enumerate()
+                        .map(|(i, base)| {
+                            base + weight_difference.get(i).copied().unwrap_or(0.0) * weight
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+
+        Pose { nodes }
+    }
+
+    /// Computes the transformation of every node relative to the model in this pose, using the
+    /// hierarchy of `nodes`.
+    pub fn global_transformations(&self, nodes: &[Node]) -> Vec<Matrix4<f32>> {
+        let local_transformations: Vec<Matrix4<f32>> = self
+            .nodes
+            .iter()
+            .map(|node_pose| node_pose.local_transformation())
+            .collect();
+        node::compose_transformations(nodes, &local_transformations)
+    }
+}
+
+/// Spherical interpolation along the shortest path between two rotations.
+pub fn slerp(a: &UnitQuaternion<f32>, b: &UnitQuaternion<f32>, t: f32) -> UnitQuaternion<f32> {
+    let b = if a.coords.dot(&b.coords) < 0.0 {
+        UnitQuaternion::new_unchecked(-b.into_inner())
+    } else {
+        *b
+    };
+    a.try_slerp(&b, t, 1.0e-6).unwrap_or_else(|| a.nlerp(&b, t))
+}
+
+// Weights missing from either side count as zero
+fn lerp_weights(a: &[f32], b: &[f32], t: f32) -> Vec<f32> {
+    (0..a.len().max(b.len()))
+        .map(|i| {
+            let a = a.get(i).copied().unwrap_or(0.0);
+            let b = b.get(i).copied().unwrap_or(0.0);
+            a + (b - a) * t
+        })
+        .collect()
+}
+
+fn scale_ratio(scale: f32, reference: f32) -> f32 {
+    if reference != 0.0 {
+        scale / reference
+    } else {
+        1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn node_pose(
+        translation: [f32; 3],
+        rotation: UnitQuaternion<f32>,
+        scale: [f32; 3],
+    ) -> NodePose {
+        NodePose {
+            translation: Vector3::from(translation),
+            rotation,
+            scale: Vector3::from(scale),
+            weights: Vec::new(),
+        }
+    }
+
+    fn assert_matrix_eq(a: &Matrix4<f32>, b: &Matrix4<f32>) {
+        assert!(
+            a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1.0e-5),
+            "{} != {}",
+            a,
+            b
+        );
+    }
+
+    fn rotation_y(angle: f32) -> UnitQuaternion<f32> {
+        UnitQuaternion::from_axis_angle(&Vector3::y_axis(), angle)
+    }
+
+    #[test]
+    fn blend_interpolates_every_property() {
+        let a = Pose {
+            nodes: vec![NodePose {
+                weights: vec![0.0, 1.0],
+                ..node_pose([0.0, 0.0, 0.0], rotation_y(0.0), [1.0, 1.0, 1.0])
+            }],
+        };
+        let b = Pose {
+            nodes: vec![NodePose {
+                weights: vec![1.0, 0.0],
+                ..node_pose([4.0, 0.0, 0.0], rotation_y(FRAC_PI_2), [3.0, 3.0, 3.0])
+            }],
+        };
+
+        let blended = a.blend(&b, 0.5);
+        let expected = node_pose(
+            [2.0, 0.0, 0.0],
+            rotation_y(FRAC_PI_2 / 2.0),
+            [2.0, 2.0, 2.0],
+        );
+        assert_matrix_eq(
+            &blended.nodes[0].local_transformation(),
+            &expected.local_transformation(),
+        );
+        assert_eq!(blended.nodes[0].weights, vec![0.5, 0.5]);
+    }
+
+    #[test]
+    fn blend_endpoints_are_the_blended_poses() {
+        let a = Pose {
+            nodes: vec![node_pose([1.0, 2.0, 3.0], rotation_y(0.3), [1.0, 2.0, 1.0])],
+        };
+        let b = Pose {
+            nodes: vec![node_pose(
+                [-1.0, 0.0, 5.0],
+                rotation_y(-1.2),
+                [2.0, 1.0, 1.0],
+            )],
+        };
+
+        assert_matrix_eq(
+            &a.blend(&b, 0.0).nodes[0].local_transformation(),
+            &a.nodes[0].local_transformation(),
+        );
+        assert_matrix_eq(
+            &a.blend(&b, 1.0).nodes[0].local_transformation(),
+            &b.nodes[0].local_transformation(),
+        );
+    }
+
+    #[test]
+    fn blend_takes_the_shortest_rotation() {
+        let a = Pose {
+            nodes: vec![node_pose([0.0; 3], rotation_y(0.0), [1.0; 3])],
+        };
+        // The same rotation as a quarter turn, stored on the other hemisphere
+        let negated = UnitQuaternion::new_unchecked(-rotation_y(FRAC_PI_2).into_inner());
+        let b = Pose {
+            nodes: vec![node_pose([0.0; 3], negated, [1.0; 3])],
+        };
+
+        let blended = a.blend(&b, 0.5);
+        assert!(
+            blended.nodes[0]
+                .rotation
+                .angle_to(&rotation_y(FRAC_PI_2 / 2.0))
+                < 1.0e-4
+        );
+    }
+
+    #[test]
+    fn blend_treats_missing_weights_as_zero() {
+        let a = Pose {
+            nodes: vec![NodePose {
+                weights: vec![1.0],
+                ..node_pose([0.0; 3], rotation_y(0.0), [1.0; 3])
+            }],
+        };
+        let b = Pose {
+            nodes: vec![NodePose {
+                weights: vec![1.0, 1.0],
+                ..node_pose([0.0; 3], rotation_y(0.0), [1.0; 3])
+            }],
+        };
+
+        assert_eq!(a.blend(&b, 0.5).nodes[0].weights, vec![1.0, 0.5]);
+    }
+
+    #[test]
+    fn add_layers_the_difference_from_the_reference() {
+        let base = Pose {
+            nodes: vec![NodePose {
+                weights: vec![0.25],
+                ..node_pose([1.0, 0.0, 0.0], rotation_y(0.0), [2.0, 2.0, 2.0])
+            }],
+        };
+        let reference = Pose {
+            nodes: vec![NodePose {
+                weights: vec![0.0],
+                ..node_pose([0.0, 1.0, 0.0], rotation_y(0.2), [1.0, 1.0, 1.0])
+            }],
+        };
+        let additive = Pose {
+            nodes: vec![NodePose {
+                weights: vec![0.5],
+                ..node_pose(
+                    [0.0, 3.0, 0.0],
+                    rotation_y(0.2 + FRAC_PI_2),
+                    [3.0, 1.0, 1.0],
+                )
+            }],
+        };
+
+        let layered = base.add(&additive, &reference, 0.5);
+        let expected = node_pose(
+            [1.0, 1.0, 0.0],
+            rotation_y(FRAC_PI_2 / 2.0),
+            [4.0, 2.0, 2.0],
+        );
+        assert_matrix_eq(
+            &layered.nodes[0].local_transformation(),
+            &expected.local_transformation(),
+        );
+        assert_eq!(layered.nodes[0].weights, vec![0.5]);
+    }
+
+    #[test]
+    fn add_without_weight_keeps_the_base() {
+        let base = Pose {
+            nodes: vec![node_pose([1.0, 2.0, 3.0], rotation_y(0.7), [1.0, 2.0, 3.0])],
+        };
+        let reference = Pose {
+            nodes: vec![node_pose([0.0; 3], rotation_y(0.0), [1.0; 3])],
+        };
+        let additive = Pose {
+            nodes: vec![node_pose([5.0; 3], rotation_y(1.0), [2.0; 3])],
+        };
+
+        assert_matrix_eq(
+            &base.add(&additive, &reference, 0.0).nodes[0].local_transformation(),
+            &base.nodes[0].local_transformation(),
+        );
+    }
+
+    #[test]
+    fn global_transformations_follow_the_hierarchy() {
+        let mut parent = Node::new(
+            None,
+            Vector3::zeros(),
+            rotation_y(0.0),
+            Vector3::repeat(1.0),
+        );
+        parent.children = vec![1];
+        let mut child = Node::new(
+            None,
+            Vector3::zeros(),
+            rotation_y(0.0),
+            Vector3::repeat(1.0),
+        );
+        child.parent = Some(0);
+        let nodes = vec![parent, child];
+
+        let pose = Pose {
+            nodes: vec![
+                node_pose([0.0, 0.0, 0.0], rotation_y(FRAC_PI_2), [1.0; 3]),
+                node_pose([1.0, 0.0, 0.0], rotation_y(0.0), [1.0; 3]),
+            ],
+        };
+
+        let transformations = pose.global_transformations(&nodes);
+        // The child's offset along x is turned onto -z by its parent
+        assert_matrix_eq(
+            &transformations[1],
+            &(Matrix4::new_translation(&Vector3::new(0.0, 0.0, -1.0))
+                * rotation_y(FRAC_PI_2).to_homogeneous()),
+        );
+    }
+}
@@ -1,14 +1,40 @@
-use crate::vertex::Vertex;
+use crate::vertex::{SkinVertex, Vertex};
 use glium::backend::Facade;
+use glium::index::PrimitiveType;
 use glium::IndexBuffer;
 use glium::VertexBuffer;
 
+/// Offsets of every vertex of a primitive, blended in by the morph target's weight. Attributes
+/// the target doesn't move are left empty.
+pub struct MorphTarget {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tangents: Vec<[f32; 3]>,
+}
+
+impl MorphTarget {
+    pub fn new(positions: Vec<[f32; 3]>, normals: Vec<[f32; 3]>, tangents: Vec<[f32; 3]>) -> Self {
+        Self {
+            positions,
+            normals,
+            tangents,
+        }
+    }
+}
+
 pub struct Primitive {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
+    pub primitive_type: PrimitiveType,
     pub material_index: usize,
+    /// Centre of the bounding box of the vertices.
+    pub centre: [f32; 3],
     pub vbo: VertexBuffer<Vertex>,
     pub ibo: IndexBuffer<u32>,
+    /// Joints and weights of every vertex, only present on skinned primitives.
+    pub skin_vertices: Vec<SkinVertex>,
+    pub skin_vbo: Option<VertexBuffer<SkinVertex>>,
+    pub morph_targets: Vec<MorphTarget>,
 }
 
 impl Primitive {
@@ -16,22 +42,120 @@ impl Primitive {
         facade: &F,
         vertices: Vec<Vertex>,
         indices: Vec<u32>,
+        primitive_type: PrimitiveType,
         material_index: usize,
     ) -> Self
     where
         F: Facade,
     {
+        let centre = Self::bounding_box_centre(&vertices);
         let vbo = glium::VertexBuffer::new(facade, &vertices).unwrap();
-        let ibo =
-            glium::IndexBuffer::new(facade, glium::index::PrimitiveType::TrianglesList, &indices)
-                .unwrap();
+        let ibo = glium::IndexBuffer::new(facade, primitive_type, &indices).unwrap();
 
         Self {
             vertices,
             indices,
+            primitive_type,
             material_index,
+            centre,
             vbo,
             ibo,
+            skin_vertices: Vec::new(),
+            skin_vbo: None,
+            morph_targets: Vec::new(),
         }
     }
+
+    /// Makes the primitive skinned. There has to be one skin vertex for every vertex.
+    pub fn set_skin_vertices<F: ?Sized>(&mut self, facade: &F, skin_vertices: Vec<SkinVertex>)
+    where
+        F: Facade,
+    {
+        self.skin_vbo = Some(glium::VertexBuffer::new(facade, &skin_vertices).unwrap());
+        self.skin_vertices = skin_vertices;
+    }
+
+    /// Blends the morph targets into the vertices on the CPU and uploads the result. `vertices`
+    /// keeps the unmorphed vertices, so weights can be changed any number of times.
+    pub fn apply_morph_weights(&mut self, weights: &[f32]) {
+        if self.morph_targets.is_empty() {
+            return;
+        }
+
+        let mut position_deltas = vec![[0.0; 3]; self.vertices.len()];
+        let mut normal_deltas = vec![[0.0; 3]; self.vertices.len()];
+        let mut tangent_deltas = vec![[0.0; 3]; self.vertices.len()];
+
+        let accumulate = |deltas: &mut [[f32; 3]], target_deltas: &[[f32; 3]], weight: f32| {
+            for (delta, target_delta) in deltas.iter_mut().zip(target_deltas.iter()) {
+                for (component, target_component) in delta.iter_mut().zip(target_delta.iter()) {
+                    *component += target_component * weight;
+                }
+            }
+        };
+
+        for (morph_target, weight) in self.morph_targets.iter().zip(weights.iter()) {
+            if *weight == 0.0 {
+                continue;
+            }
+            accumulate(&mut position_deltas, &morph_target.positions, *weight);
+            accumulate(&mut normal_deltas, &morph_target.normals, *weight);
+            accumulate(&mut tangent_deltas, &morph_target.tangents, *weight);
+        }
+
+        let vertices: Vec<Vertex> = self
+            .vertices
+            .iter()
+            .enumerate()
+            .map(|(i, vertex)| {
+                vertex.displaced(position_deltas[i], normal_deltas[i], tangent_deltas[i])
+            })
+            .collect();
+        self.vbo.write(&vertices);
+    }
+
+    fn bounding_box_centre(vertices: &[Vertex]) -> [f32; 3] {
+        if vertices.is_empty() {
+            return [0.0, 0.0, 0.0];
+        }
+
+        let mut min = [std::f32::MAX; 3];
+        let mut max = [std::f32::MIN; 3];
+        for vertex in vertices {
+            let position = vertex.position();
+            for i in 0..3 {
+                min[i] = min[i].min(position[i]);
+                max[i] = max[i].max(position[i]);
+            }
+        }
+
+        [
+            (min[0] + max[0]) / 2.0,
+            (min[1] + max[1]) / 2.0,
+            (min[2] + max[2]) / 2.0,
+        ]
+    }
+}
+
+/// Converts the indices of a triangle strip into a triangle list, keeping the winding of every
+/// triangle consistent.
+pub fn triangle_strip_to_list(indices: &[u32]) -> Vec<u32> {
+    let mut list = Vec::with_capacity(indices.len().saturating_sub(2) * 3);
+    for i in 0..indices.len().saturating_sub(2) {
+        if i % 2 == 0 {
+            list.extend_from_slice(&[indices[i], indices[i + 1], indices[i + 2]]);
+        } else {
+            list.extend_from_slice(&[indices[i], indices[i + 2], indices[i + 1]]);
+        }
+    }
+    list
+}
+
+/// Converts the indices of a triangle fan into a triangle list.
+pub fn triangle_fan_to_list(indices: &[u32]) -> Vec<u32> {
+    let mut list = Vec::with_capacity(indices.len().saturating_sub(2) * 3);
+    for i in 0..indices.len().saturating_sub(2) {
+        list.extend_from_slice(&[indices[i + 1], indices[i + 2], indices[0]]);
+    }
+    list
 }
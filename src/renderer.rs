@@ -1,8 +1,10 @@
 use crate::camera::Camera;
 use crate::light::Light;
+use crate::material::{AlphaMode, Material};
 use crate::model::{Model, ModelHandle};
 use crate::map::Map;
 use crate::skybox::Skybox;
+use crate::texture_cache::TextureCache;
 use glium::backend::Facade;
 use glium::draw_parameters;
 use glium::glutin::dpi::PhysicalSize;
@@ -12,23 +14,92 @@ use glium::glutin::ContextBuilder;
 use glium::uniforms::UniformBuffer;
 use glium::Display;
 use glium::Frame;
-use glium::{DrawParameters, Program, Surface};
+use glium::{Blend, DrawParameters, Program, Surface};
+use na::Point3;
 
 use legion::*;
 
-const MAX_LIGHT_COUNT: usize = 512;
+/// The most lights drawn at once, matching the light arrays of the entity shader.
+pub const MAX_LIGHT_COUNT: usize = 512;
+
+/// The anisotropic filtering level material textures are sampled with unless changed.
+pub const DEFAULT_ANISOTROPY: u16 = 16;
+
+type LightBuffer = UniformBuffer<[[f32; 4]; MAX_LIGHT_COUNT]>;
+
+/// Every light packed into uniform blocks for the entity shader.
+struct LightBuffers {
+    count: i32,
+    positions: LightBuffer,
+    /// Colour premultiplied with the intensity.
+    colours: LightBuffer,
+    /// Direction with the light kind in w.
+    directions: LightBuffer,
+    /// Range (0.0 meaning unlimited), cosine of the inner and cosine of the outer cone angle.
+    parameters: LightBuffer,
+    /// The lights currently in the buffers.
+    lights: Vec<Light>,
+}
+
+/// An alpha blended primitive waiting to be drawn after all opaque geometry.
+struct BlendedPrimitive {
+    model: usize,
+    mesh: usize,
+    primitive: usize,
+    distance: f32,
+}
+
+/// Opaque and masked materials write depth, blended ones only test against it. Double sided
+/// materials are drawn without backface culling.
+fn material_draw_parameters(material: &Material) -> DrawParameters<'static> {
+    let backface_culling = if material.double_sided {
+        draw_parameters::BackfaceCullingMode::CullingDisabled
+    } else {
+        draw_parameters::BackfaceCullingMode::CullClockwise
+    };
+
+    let blend = match material.alpha_mode {
+        AlphaMode::Blend => Blend::alpha_blending(),
+        AlphaMode::Opaque | AlphaMode::Mask => Blend::default(),
+    };
+
+    glium::DrawParameters {
+        backface_culling,
+        blend,
+        depth: glium::Depth {
+            test: draw_parameters::DepthTest::IfLessOrEqual,
+            write: material.alpha_mode != AlphaMode::Blend,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
 
 pub struct RendererState {
     pub camera: Camera,
     draw_parameters: DrawParameters<'static>,
     model_program: Program,
+    skinned_model_program: Program,
     skybox_program: Program,
-    lights: [Light; MAX_LIGHT_COUNT],
+    /// Lights in world space, only the first `MAX_LIGHT_COUNT` are drawn.
+    pub lights: Vec<Light>,
+    /// Material textures shared by the models, filled while importing.
+    pub textures: TextureCache,
+    /// Anisotropic filtering level of material textures, 1 turns it off.
+    pub anisotropy: u16,
     models: Vec<Model>,
+    blended_primitives: Vec<BlendedPrimitive>,
+    // Created by the first draw, then rewritten whenever the lights change
+    light_buffers: Option<LightBuffers>,
 }
 
 impl RendererState {
-    pub fn new(camera: Camera, model_program: Program, skybox_program: Program) -> Self {
+    pub fn new(
+        camera: Camera,
+        model_program: Program,
+        skinned_model_program: Program,
+        skybox_program: Program,
+    ) -> Self {
         let draw_parameters = glium::DrawParameters {
             backface_culling: draw_parameters::BackfaceCullingMode::CullClockwise,
             depth: glium::Depth {
@@ -38,75 +109,243 @@ impl RendererState {
             },
             ..Default::default()
         };
-        let mut lights: [Light; MAX_LIGHT_COUNT] = [Light::default(); MAX_LIGHT_COUNT];
-        lights[0] = Light::new(glm::vec3(-0.5, 0.1, -0.5), None);
+        let lights = Vec::new();
         let models = Vec::new();
 
         Self {
             camera,
             draw_parameters,
             model_program,
+            skinned_model_program,
             skybox_program,
             lights,
+            textures: TextureCache::new(),
+            anisotropy: DEFAULT_ANISOTROPY,
             models,
+            blended_primitives: Vec::new(),
+            light_buffers: None,
+        }
+    }
+
+    // Uploads the lights into the light buffers, unless they are already there
+    fn update_light_buffers<F>(&mut self, facade: &F)
+    where
+        F: Facade,
+    {
+        let lights = &self.lights[..self.lights.len().min(MAX_LIGHT_COUNT)];
+        if let Some(light_buffers) = &self.light_buffers {
+            if light_buffers.lights.as_slice() == lights {
+                return;
+            }
+        }
+
+        let mut light_positions = [[0.0; 4]; MAX_LIGHT_COUNT];
+        let mut light_colours = [[0.0; 4]; MAX_LIGHT_COUNT];
+        let mut light_directions = [[0.0; 4]; MAX_LIGHT_COUNT];
+        let mut light_parameters = [[0.0; 4]; MAX_LIGHT_COUNT];
+
+        for (i, light) in lights.iter().enumerate() {
+            let [x, y, z] = light.position;
+            light_positions[i] = [x, y, z, 1.0];
+            let [r, g, b] = light.colour;
+            light_colours[i] = [
+                r * light.intensity,
+                g * light.intensity,
+                b * light.intensity,
+                1.0,
+            ];
+            let [x, y, z] = light.direction;
+            light_directions[i] = [x, y, z, light.kind as i32 as f32];
+            light_parameters[i] = [
+                light.range.unwrap_or(0.0),
+                light.inner_cone_angle.cos(),
+                light.outer_cone_angle.cos(),
+                0.0,
+            ];
+        }
+
+        match &mut self.light_buffers {
+            Some(light_buffers) => {
+                light_buffers.count = lights.len() as i32;
+                light_buffers.positions.write(&light_positions);
+                light_buffers.colours.write(&light_colours);
+                light_buffers.directions.write(&light_directions);
+                light_buffers.parameters.write(&light_parameters);
+                light_buffers.lights = lights.to_vec();
+            }
+            None => {
+                self.light_buffers = Some(LightBuffers {
+                    count: lights.len() as i32,
+                    positions: UniformBuffer::new(facade, light_positions).unwrap(),
+                    colours: UniformBuffer::new(facade, light_colours).unwrap(),
+                    directions: UniformBuffer::new(facade, light_directions).unwrap(),
+                    parameters: UniformBuffer::new(facade, light_parameters).unwrap(),
+                    lights: lights.to_vec(),
+                });
+            }
         }
     }
 
+    /// Forgets the alpha blended primitives queued up by the previous frame. Call it before the
+    /// first `draw_model` of every frame, whether or not `draw_blended` runs.
+    pub fn begin_frame(&mut self) {
+        self.blended_primitives.clear();
+    }
+
+    /// Draws every opaque and alpha masked primitive of a model. Alpha blended primitives are
+    /// queued up and only drawn by `draw_blended`, once everything opaque is on screen. The queue
+    /// is emptied by `draw_blended` or the next `begin_frame`.
     pub fn draw_model<F, S>(
-        &self,
+        &mut self,
         facade: &F,
         surface: &mut S,
-        model: &ModelHandle,
+        model_handle: &ModelHandle,
         skybox: &Skybox,
     ) where
         F: Facade,
         S: Surface,
     {
-        let model = self.get_model(model);
-        let mut light_colours: [[f32; 3]; MAX_LIGHT_COUNT] =
-            [[std::f32::MAX, std::f32::MAX, std::f32::MAX]; MAX_LIGHT_COUNT];
-        let mut light_positions: [[f32; 3]; MAX_LIGHT_COUNT] =
-            [[std::f32::MAX, std::f32::MAX, std::f32::MAX]; MAX_LIGHT_COUNT];
-
-        for i in 0..self.lights.len() {
-            light_positions[i] = self.lights[i].position;
-            light_colours[i] = self.lights[i].colour;
-        }
-
-        let light_positions = UniformBuffer::new(facade, light_positions).unwrap();
-        let light_colours = UniformBuffer::new(facade, light_colours).unwrap();
+        self.update_light_buffers(facade);
+        let light_buffers = self.light_buffers.as_ref().unwrap();
+        let camera_position = Point3::from(self.camera.position());
+        let model = &self.models[model_handle.index()];
 
-        for mesh in model.meshes.iter() {
-            for primitive in mesh.primitives.iter() {
+        for (mesh_index, mesh) in model.meshes.iter().enumerate() {
+            for (primitive_index, primitive) in mesh.primitives.iter().enumerate() {
                 let material = &model.materials[primitive.material_index];
-                let uniforms = uniform! {
-                    irradiance_map : skybox.irradiance_map.sampled().magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
-                    prefiltered_map : skybox.prefiltered_map.sampled().magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
-                    brdf_integration : skybox.brdf_integration.sampled().magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
-                    model_matrix : mesh.transformation(),
-                    view_matrix : self.camera.view_matrix(),
-                    projection_matrix : self.camera.projection_matrix(),
-                    diffuse_map : material.diffuse_map(),
-                    occlusion_roughness_metal_map : material.orm_map(),
-                    normal_map : material.normal_map(),
-                    view_position : self.camera.position(),
-                    light_positions : &light_positions,
-                    light_colours : &light_colours
-                };
-
-                surface
-                    .draw(
-                        &primitive.vbo,
-                        &primitive.ibo,
-                        &self.model_program,
-                        &uniforms,
-                        &self.draw_parameters,
-                    )
-                    .unwrap();
+                if material.alpha_mode == AlphaMode::Blend {
+                    let centre = mesh
+                        .transformation_matrix()
+                        .transform_point(&Point3::from(primitive.centre));
+                    self.blended_primitives.push(BlendedPrimitive {
+                        model: model_handle.index(),
+                        mesh: mesh_index,
+                        primitive: primitive_index,
+                        distance: na::distance(&centre, &camera_position),
+                    });
+                    continue;
+                }
+
+                self.draw_primitive(
+                    surface,
+                    model,
+                    mesh_index,
+                    primitive_index,
+                    skybox,
+                    light_buffers,
+                );
             }
         }
     }
 
+    /// Draws all alpha blended primitives queued up by `draw_model` this frame, furthest from the
+    /// camera first so that they blend over each other correctly.
+    pub fn draw_blended<F, S>(&mut self, facade: &F, surface: &mut S, skybox: &Skybox)
+    where
+        F: Facade,
+        S: Surface,
+    {
+        let mut blended_primitives = std::mem::take(&mut self.blended_primitives);
+        blended_primitives.sort_by(|a, b| {
+            b.distance
+                .partial_cmp(&a.distance)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        self.update_light_buffers(facade);
+        let light_buffers = self.light_buffers.as_ref().unwrap();
+        for blended_primitive in blended_primitives.iter() {
+            self.draw_primitive(
+                surface,
+                &self.models[blended_primitive.model],
+                blended_primitive.mesh,
+                blended_primitive.primitive,
+                skybox,
+                light_buffers,
+            );
+        }
+
+        // Reusing the allocation for the next frame
+        blended_primitives.clear();
+        self.blended_primitives = blended_primitives;
+    }
+
+    fn draw_primitive<S>(
+        &self,
+        surface: &mut S,
+        model: &Model,
+        mesh_index: usize,
+        primitive_index: usize,
+        skybox: &Skybox,
+        light_buffers: &LightBuffers,
+    ) where
+        S: Surface,
+    {
+        let mesh = &model.meshes[mesh_index];
+        let primitive = &mesh.primitives[primitive_index];
+        let material = &model.materials[primitive.material_index];
+        let uniforms = uniform! {
+            irradiance_map : skybox.irradiance_map.sampled().magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
+            prefiltered_map : skybox.prefiltered_map.sampled().magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
+            brdf_integration : skybox.brdf_integration.sampled().magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
+            model_matrix : mesh.transformation(),
+            view_matrix : self.camera.view_matrix(),
+            projection_matrix : self.camera.projection_matrix(),
+            diffuse_map : material.diffuse_map(self.anisotropy),
+            metallic_roughness_map : material.metallic_roughness_map(self.anisotropy),
+            occlusion_map : material.occlusion_map(self.anisotropy),
+            normal_map : material.normal_map(self.anisotropy),
+            packed_occlusion : material.packed_occlusion,
+            base_colour_factor : material.base_colour_factor,
+            metallic_factor : material.metallic_factor,
+            roughness_factor : material.roughness_factor,
+            normal_scale : material.normal_scale,
+            occlusion_strength : material.occlusion_strength,
+            emissive_map : material.emissive_map(self.anisotropy),
+            emissive_factor : material.emissive_factor,
+            emissive_strength : material.emissive_strength,
+            alpha_mode : material.alpha_mode as i32,
+            alpha_cutoff : material.alpha_cutoff,
+            diffuse_uv_set : material.diffuse_uv_set as i32,
+            metallic_roughness_uv_set : material.metallic_roughness_uv_set as i32,
+            occlusion_uv_set : material.occlusion_uv_set as i32,
+            normal_uv_set : material.normal_uv_set as i32,
+            emissive_uv_set : material.emissive_uv_set as i32,
+            view_position : self.camera.position(),
+            light_count : light_buffers.count,
+            light_positions : &light_buffers.positions,
+            light_colours : &light_buffers.colours,
+            light_directions : &light_buffers.directions,
+            light_parameters : &light_buffers.parameters
+        };
+
+        let draw_parameters = material_draw_parameters(material);
+
+        // Skinned primitives read their joints and weights from a second vertex buffer
+        if let (Some(skin_vbo), Some(joint_buffer)) = (&primitive.skin_vbo, &mesh.joint_buffer) {
+            let uniforms = uniforms.add("joint_matrices", joint_buffer);
+            surface
+                .draw(
+                    (&primitive.vbo, skin_vbo),
+                    &primitive.ibo,
+                    &self.skinned_model_program,
+                    &uniforms,
+                    &draw_parameters,
+                )
+                .unwrap();
+        } else {
+            surface
+                .draw(
+                    &primitive.vbo,
+                    &primitive.ibo,
+                    &self.model_program,
+                    &uniforms,
+                    &draw_parameters,
+                )
+                .unwrap();
+        }
+    }
+
     pub fn push_model(&mut self, model: Model) -> ModelHandle {
         self.models.push(model);
         ModelHandle::new(self.models.len() - 1)
@@ -142,6 +381,11 @@ impl RendererState {
     //    }
 }
 
+#[system]
+pub fn begin_frame(#[resource] rs: &mut RendererState) {
+    rs.begin_frame();
+}
+
 #[system(for_each)]
 pub fn render_models(
     model_handle: &ModelHandle,
@@ -162,6 +406,15 @@ pub fn render_map(
     rs.draw_model(&ds.display, ds.target.as_mut().unwrap(), &map.model, skybox);
 }
 
+#[system]
+pub fn render_blended(
+    #[resource] rs: &mut RendererState,
+    #[resource] ds: &mut DisplayState,
+    #[resource] skybox: &Skybox,
+) {
+    rs.draw_blended(&ds.display, ds.target.as_mut().unwrap(), skybox);
+}
+
 #[system]
 pub fn render_skybox(
     #[resource] rs: &RendererState,
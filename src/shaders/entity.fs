@@ -1,8 +1,10 @@
 #version 330 core
 in vec3 frag_position;
 in vec2 frag_texture_coord;
+in vec2 frag_texture_coord_1;
 in vec3 frag_normal;
 in mat3 frag_TBN;
+in vec4 frag_colour;
 
 out vec4 color;
 
@@ -11,11 +13,38 @@ const float PI = 3.14159265359;
 const float MAX_REFLECTION_LOD = 6.0;
 
 uniform vec3 view_position;
-uniform light_positions { vec3 light_positions_array[512]; };
-uniform light_colours { vec3 light_colours_array[512]; };
+// MAX_LIGHT_COUNT in renderer.rs
+uniform int light_count;
+uniform light_positions { vec4 light_positions_array[512]; };
+// Colour premultiplied with the intensity
+uniform light_colours { vec4 light_colours_array[512]; };
+// Direction in xyz, kind in w: 0 = point, 1 = directional, 2 = spot
+uniform light_directions { vec4 light_directions_array[512]; };
+// Range (0 meaning unlimited), cosine of the inner and of the outer cone angle
+uniform light_parameters { vec4 light_parameters_array[512]; };
 uniform sampler2D diffuse_map;
-uniform sampler2D occlusion_roughness_metal_map;
+uniform sampler2D metallic_roughness_map;
+uniform sampler2D occlusion_map;
 uniform sampler2D normal_map;
+// Occlusion is in the red channel of metallic_roughness_map instead of occlusion_map
+uniform bool packed_occlusion;
+uniform vec4 base_colour_factor;
+uniform float metallic_factor;
+uniform float roughness_factor;
+uniform float normal_scale;
+uniform float occlusion_strength;
+uniform sampler2D emissive_map;
+uniform vec3 emissive_factor;
+uniform float emissive_strength;
+// 0 = opaque, 1 = mask, 2 = blend
+uniform int alpha_mode;
+uniform float alpha_cutoff;
+// Which uv set each map is sampled with
+uniform int diffuse_uv_set;
+uniform int metallic_roughness_uv_set;
+uniform int occlusion_uv_set;
+uniform int normal_uv_set;
+uniform int emissive_uv_set;
 uniform samplerCube irradiance_map;
 uniform samplerCube prefiltered_map;
 uniform sampler2D brdf_integration;
@@ -42,10 +71,15 @@ struct PBR_data {
     vec3 F0;
 };
 
+vec2 uv_set(int set) {
+    return set == 1 ? frag_texture_coord_1 : frag_texture_coord;
+}
+
 vec3 sample_normalmap() {
-    return normalize(
-        frag_TBN *
-        (texture(normal_map, frag_texture_coord).rgb * 2.0 - vec3(1.0)));
+    vec3 tangent_normal =
+        texture(normal_map, uv_set(normal_uv_set)).rgb * 2.0 - vec3(1.0);
+    tangent_normal.xy *= normal_scale;
+    return normalize(frag_TBN * tangent_normal);
 }
 
 // Probably safe to delete
@@ -68,13 +102,13 @@ vec3 test_sample_normalmap() {
 vec4 legacyshader() {
     // 1.0 is used for ambient colour RGB. Can be any colour.
     float ambient_strength = 0.2;
-    vec3 ambient = ambient_strength * light_colours_array[0];
+    vec3 ambient = ambient_strength * light_colours_array[0].rgb;
 
     vec3 frag_normal = texture(normal_map, frag_texture_coord).rgb;
     frag_normal = frag_normal * 2.0 - 1.0;
     frag_normal = normalize(frag_TBN * frag_normal);
 
-    vec3 frag_light_vec = normalize(light_positions_array[0] - frag_position);
+    vec3 frag_light_vec = normalize(light_positions_array[0].xyz - frag_position);
 
     // 1.0 is used for light colour RGB. Can be any colour.
     float diffuse_strength = max(dot(frag_normal, frag_light_vec), 0.0);
@@ -83,7 +117,7 @@ vec4 legacyshader() {
     vec4 frag_colour = (vec4(ambient, 1.0) + vec4(diffuse, 1.0)) *
                        texture(diffuse_map, frag_texture_coord);
     return texture(diffuse_map, frag_texture_coord) *
-           texture(occlusion_roughness_metal_map, frag_texture_coord).r;
+           texture(occlusion_map, frag_texture_coord).r;
     // return frag_colour;
 }
 
@@ -120,19 +154,33 @@ float geometry_smith(float NdotV, float NdotL, float k) {
 }
 
 void main() {
-    vec3 albedo = texture(diffuse_map, frag_texture_coord).rgb;
-    vec3 orm_vector =
-        texture(occlusion_roughness_metal_map, frag_texture_coord).rgb;
+    vec4 base_colour = texture(diffuse_map, uv_set(diffuse_uv_set)) *
+                       base_colour_factor * frag_colour;
+    vec3 albedo = base_colour.rgb;
+    float alpha = alpha_mode == 2 ? base_colour.a : 1.0;
+    if (alpha_mode == 1 && base_colour.a < alpha_cutoff) {
+        discard;
+    }
+    // Following the gltf layout, roughness is in green and metalness in blue
+    vec3 metallic_roughness =
+        texture(metallic_roughness_map, uv_set(metallic_roughness_uv_set)).rgb;
+    float occlusion = packed_occlusion
+        ? texture(metallic_roughness_map, uv_set(occlusion_uv_set)).r
+        : texture(occlusion_map, uv_set(occlusion_uv_set)).r;
 
     PBR_data pbr_data;
     pbr_data.N = sample_normalmap();
+    // Back faces are only visible on double sided materials, light them from their side
+    if (!gl_FrontFacing) {
+        pbr_data.N = -pbr_data.N;
+    }
     pbr_data.V = normalize(view_position - frag_position);
     pbr_data.R = reflect(-pbr_data.V, pbr_data.N);
-    pbr_data.occlusion = orm_vector.r;
-    pbr_data.roughness = 1 - orm_vector.g;
+    pbr_data.occlusion = 1.0 + occlusion_strength * (occlusion - 1.0);
+    pbr_data.roughness = metallic_roughness.g * roughness_factor;
     // Using value from UE4 instead of learnOpenGL
     pbr_data.roughness_remapped = (pbr_data.roughness + 1) / 2;
-    pbr_data.metalness = orm_vector.b;
+    pbr_data.metalness = metallic_roughness.b * metallic_factor;
     pbr_data.a = pbr_data.roughness * pbr_data.roughness;
     pbr_data.a2 = pbr_data.a * pbr_data.a;
     pbr_data.k =
@@ -144,37 +192,54 @@ void main() {
 
     vec3 final_color = vec3(0.0);
 
-    //for (int i = 0; i < 512; i++) {
-    //    if (light_positions_array[i].x > 10000.0) {
-    //        break;
-    //    }
-    //    vec3 L_unnormalized = light_positions_array[i] - frag_position;
-    //    pbr_data.L = normalize(L_unnormalized);
-    //    pbr_data.H = normalize(pbr_data.L + pbr_data.V);
-    //    pbr_data.NdotL = clamp(dot(pbr_data.N, pbr_data.L), 0.000001, 1.0);
-    //    pbr_data.HdotV = clamp(dot(pbr_data.H, pbr_data.V), 0.000001, 1.0);
-    //    pbr_data.HdotN = clamp(dot(pbr_data.H, pbr_data.N), 0.000001, 1.0);
-    //    pbr_data.HdotN2 = pbr_data.HdotN * pbr_data.HdotN;
-    //
-    //    float D = distribution_GGX(pbr_data.HdotN2, pbr_data.a2);
-    //    vec3 F = fresnel_schlick(pbr_data.HdotV, pbr_data.F0);
-    //    float G = geometry_smith(pbr_data.NdotV, pbr_data.NdotL, pbr_data.k);
-    //
-    //    vec3 numerator = D * F * G;
-    //    float denominator = 4.0 * pbr_data.NdotV * pbr_data.NdotL;
-    //    vec3 specular = numerator / denominator;
-    //
-    //    float distance = length(L_unnormalized);
-    //    float attentuation = 1.0 / (distance * distance);
-    //    vec3 radiance = light_colours_array[i] * attentuation;
-    //
-    //    vec3 k_s = F;
-    //    vec3 k_d = vec3(1.0) - k_s;
-    //    k_d *= 1.0 - pbr_data.metalness;
-    //
-    //    final_color +=
-    //        (k_d * pbr_data.albedo / PI + specular) * radiance * pbr_data.NdotL;
-    //}
+    for (int i = 0; i < light_count; i++) {
+        int kind = int(light_directions_array[i].w);
+        float attenuation = 1.0;
+        if (kind == 1) {
+            pbr_data.L = normalize(-light_directions_array[i].xyz);
+        } else {
+            vec3 L_unnormalized = light_positions_array[i].xyz - frag_position;
+            float distance = length(L_unnormalized);
+            pbr_data.L = L_unnormalized / max(distance, 0.000001);
+            attenuation = 1.0 / max(distance * distance, 0.000001);
+
+            // Smooth falloff to zero at the range, as recommended by KHR_lights_punctual
+            float range = light_parameters_array[i].x;
+            if (range > 0.0) {
+                attenuation *= clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0);
+            }
+
+            if (kind == 2) {
+                float cos_angle =
+                    dot(normalize(light_directions_array[i].xyz), -pbr_data.L);
+                attenuation *= smoothstep(light_parameters_array[i].z,
+                                          light_parameters_array[i].y, cos_angle);
+            }
+        }
+
+        pbr_data.H = normalize(pbr_data.L + pbr_data.V);
+        pbr_data.NdotL = clamp(dot(pbr_data.N, pbr_data.L), 0.000001, 1.0);
+        pbr_data.HdotV = clamp(dot(pbr_data.H, pbr_data.V), 0.000001, 1.0);
+        pbr_data.HdotN = clamp(dot(pbr_data.H, pbr_data.N), 0.000001, 1.0);
+        pbr_data.HdotN2 = pbr_data.HdotN * pbr_data.HdotN;
+
+        float D = distribution_GGX(pbr_data.HdotN2, pbr_data.a2);
+        vec3 F = fresnel_schlick(pbr_data.HdotV, pbr_data.F0);
+        float G = geometry_smith(pbr_data.NdotV, pbr_data.NdotL, pbr_data.k);
+
+        vec3 numerator = D * F * G;
+        float denominator = 4.0 * pbr_data.NdotV * pbr_data.NdotL;
+        vec3 specular = numerator / denominator;
+
+        vec3 radiance = light_colours_array[i].rgb * attenuation;
+
+        vec3 k_s = F;
+        vec3 k_d = vec3(1.0) - k_s;
+        k_d *= 1.0 - pbr_data.metalness;
+
+        final_color +=
+            (k_d * pbr_data.albedo / PI + specular) * radiance * pbr_data.NdotL;
+    }
 
     // color = vec4(final_color, 1.0);
 
@@ -196,10 +261,13 @@ void main() {
 
     vec3 ambient = (IBL_k_d * diffuse + specular) * pbr_data.occlusion;
 
-    final_color = ambient + final_color;
+    vec3 emissive = texture(emissive_map, uv_set(emissive_uv_set)).rgb *
+                    emissive_factor * emissive_strength;
+
+    final_color = ambient + final_color + emissive;
     final_color = final_color / (final_color + vec3(1.0));
     final_color = pow(final_color, vec3(1.0 / 2.2));
-    color = vec4(final_color, 1.0);
+    color = vec4(final_color, alpha);
     // color = vec4(textureLod(prefiltered_map, pbr_data.R, pbr_data.roughness * MAX_REFLECTION_LOD).rgb, 1.0);
     // color = vec4(texture(brdf_integration, frag_texture_coord).rg, 0.0, 1.0);
     // color = vec4(specular, 1.0);
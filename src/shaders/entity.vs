@@ -1,13 +1,17 @@
 #version 330
 in vec3 position;
 in vec2 texture_coord;
+in vec2 texture_coord_1;
 in vec3 normal;
-in vec3 tangent;
+in vec4 tangent;
+in vec4 colour;
 
 out vec3 frag_position;
 out vec2 frag_texture_coord;
+out vec2 frag_texture_coord_1;
 out vec3 frag_normal;
 out mat3 frag_TBN;
+out vec4 frag_colour;
 
 //uniform mat4 mvp_matrix;
 uniform mat4 model_matrix;
@@ -17,14 +21,17 @@ uniform mat4 projection_matrix;
 void main() {
     frag_position = (model_matrix * vec4(position, 1.0)).xyz;
     frag_texture_coord = texture_coord;
+    frag_texture_coord_1 = texture_coord_1;
+    frag_colour = colour;
 
     mat3 normal_matrix = inverse(transpose(mat3(model_matrix)));
     frag_normal = normalize(normal_matrix * normal);
 
     vec3 vertex_normal = normalize(normal_matrix * normal);
-    vec3 vertex_tangent = normalize(normal_matrix * tangent);
+    vec3 vertex_tangent = normalize(mat3(model_matrix) * tangent.xyz);
     vertex_tangent = normalize(vertex_tangent - dot(vertex_tangent, vertex_normal) * vertex_normal);
-    vec3 vertex_bitangent = cross(vertex_normal, vertex_tangent);
+    // w holds the handedness of the uv mapping
+    vec3 vertex_bitangent = cross(vertex_normal, vertex_tangent) * tangent.w;
     frag_TBN = mat3(vertex_tangent, vertex_bitangent, vertex_normal);
 
     gl_Position = projection_matrix * view_matrix * model_matrix * vec4(position, 1.0);
@@ -0,0 +1,47 @@
+#version 330
+in vec3 position;
+in vec2 texture_coord;
+in vec2 texture_coord_1;
+in vec3 normal;
+in vec4 tangent;
+in vec4 colour;
+in uvec4 joints;
+in vec4 weights;
+
+out vec3 frag_position;
+out vec2 frag_texture_coord;
+out vec2 frag_texture_coord_1;
+out vec3 frag_normal;
+out mat3 frag_TBN;
+out vec4 frag_colour;
+
+uniform mat4 model_matrix;
+uniform mat4 view_matrix;
+uniform mat4 projection_matrix;
+// MAX_JOINT_COUNT in skeleton.rs
+uniform joint_matrices { mat4 joint_matrices_array[128]; };
+
+void main() {
+    mat4 skin_matrix = weights.x * joint_matrices_array[joints.x] +
+                       weights.y * joint_matrices_array[joints.y] +
+                       weights.z * joint_matrices_array[joints.z] +
+                       weights.w * joint_matrices_array[joints.w];
+    mat4 skinned_model_matrix = model_matrix * skin_matrix;
+
+    frag_position = (skinned_model_matrix * vec4(position, 1.0)).xyz;
+    frag_texture_coord = texture_coord;
+    frag_texture_coord_1 = texture_coord_1;
+    frag_colour = colour;
+
+    mat3 normal_matrix = inverse(transpose(mat3(skinned_model_matrix)));
+    frag_normal = normalize(normal_matrix * normal);
+
+    vec3 vertex_normal = normalize(normal_matrix * normal);
+    vec3 vertex_tangent = normalize(mat3(skinned_model_matrix) * tangent.xyz);
+    vertex_tangent = normalize(vertex_tangent - dot(vertex_tangent, vertex_normal) * vertex_normal);
+    // w holds the handedness of the uv mapping
+    vec3 vertex_bitangent = cross(vertex_normal, vertex_tangent) * tangent.w;
+    frag_TBN = mat3(vertex_tangent, vertex_bitangent, vertex_normal);
+
+    gl_Position = projection_matrix * view_matrix * skinned_model_matrix * vec4(position, 1.0);
+}
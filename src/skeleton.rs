@@ -0,0 +1,65 @@
+use glium::uniforms::UniformBuffer;
+use na::Matrix4;
+
+/// The most joints a single skinned mesh can be drawn with, matching `skinned_entity.vs`.
+pub const MAX_JOINT_COUNT: usize = 128;
+
+pub type JointMatrices = [[[f32; 4]; 4]; MAX_JOINT_COUNT];
+pub type JointBuffer = UniformBuffer<JointMatrices>;
+
+/// The joints a skinned mesh is bound to. Joints are ordinary nodes of the model, vertices refer
+/// to them by their position in `joints`.
+pub struct Skeleton {
+    pub name: Option<String>,
+    /// Node index of every joint.
+    pub joints: Vec<usize>,
+    /// Transforms a vertex from model space into the space of each joint at bind time.
+    pub inverse_bind_matrices: Vec<Matrix4<f32>>,
+    /// Node the skeleton hangs off of, if the asset specifies one.
+    pub root: Option<usize>,
+}
+
+impl Skeleton {
+    pub fn new(
+        name: Option<String>,
+        joints: Vec<usize>,
+        inverse_bind_matrices: Vec<Matrix4<f32>>,
+        root: Option<usize>,
+    ) -> Self {
+        Self {
+            name,
+            joints,
+            inverse_bind_matrices,
+            root,
+        }
+    }
+
+    /// Computes the matrix of every joint for a mesh in its current pose. The matrices are
+    /// relative to the mesh, which still gets its own transformation applied when drawn.
+    pub fn joint_matrices(
+        &self,
+        global_transformations: &[Matrix4<f32>],
+        mesh_transformation: &Matrix4<f32>,
+    ) -> JointMatrices {
+        let identity: [[f32; 4]; 4] = Matrix4::<f32>::identity().into();
+        let mut joint_matrices = [identity; MAX_JOINT_COUNT];
+
+        let inverse_mesh_transformation = mesh_transformation
+            .try_inverse()
+            .unwrap_or_else(Matrix4::identity);
+
+        for (i, (joint, inverse_bind_matrix)) in self
+            .joints
+            .iter()
+            .zip(self.inverse_bind_matrices.iter())
+            .take(MAX_JOINT_COUNT)
+            .enumerate()
+        {
+            let joint_matrix =
+                inverse_mesh_transformation * global_transformations[*joint] * inverse_bind_matrix;
+            joint_matrices[i] = joint_matrix.into();
+        }
+
+        joint_matrices
+    }
+}
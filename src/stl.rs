@@ -0,0 +1,212 @@
+//! Parsing of .stl files into plain data, turned into models by `import::model_from_stl`.
+
+use std::fmt;
+
+/// An .stl file which couldn't be parsed.
+#[derive(Debug)]
+pub struct ParseError {
+    pub reason: String,
+}
+
+impl ParseError {
+    fn new(reason: &str) -> Self {
+        Self {
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub type Result<T> = std::result::Result<T, ParseError>;
+
+// Binary files start with an 80 byte header followed by the triangle count
+const BINARY_HEADER_SIZE: usize = 84;
+// Normal, three corners and a two byte attribute
+const BINARY_TRIANGLE_SIZE: usize = 50;
+
+/// A triangle along with the normal stored for it, which may be zero.
+#[derive(Clone, Copy, Debug)]
+pub struct Facet {
+    pub normal: [f32; 3],
+    pub positions: [[f32; 3]; 3],
+}
+
+fn read_vector(bytes: &[u8]) -> [f32; 3] {
+    let component = |i: usize| {
+        f32::from_le_bytes([
+            bytes[i * 4],
+            bytes[i * 4 + 1],
+            bytes[i * 4 + 2],
+            bytes[i * 4 + 3],
+        ])
+    };
+    [component(0), component(1), component(2)]
+}
+
+fn parse_binary(bytes: &[u8]) -> Result<Vec<Facet>> {
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+    if bytes.len() < BINARY_HEADER_SIZE + count * BINARY_TRIANGLE_SIZE {
+        return Err(ParseError::new("file is shorter than its triangle count"));
+    }
+
+    Ok(bytes[BINARY_HEADER_SIZE..]
+        .chunks_exact(BINARY_TRIANGLE_SIZE)
+        .take(count)
+        .map(|triangle| Facet {
+            normal: read_vector(&triangle[0..12]),
+            positions: [
+                read_vector(&triangle[12..24]),
+                read_vector(&triangle[24..36]),
+                read_vector(&triangle[36..48]),
+            ],
+        })
+        .collect())
+}
+
+fn parse_ascii(source: &str) -> Result<Vec<Facet>> {
+    let mut facets: Vec<Facet> = Vec::new();
+    let mut normal = [0.0; 3];
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+
+    let read_numbers = |arguments: &[&str]| -> Result<[f32; 3]> {
+        let mut vector = [0.0; 3];
+        for (i, component) in vector.iter_mut().enumerate() {
+            let argument = arguments
+                .get(i)
+                .ok_or_else(|| ParseError::new("expected three numbers"))?;
+            *component = argument.parse().map_err(|_| ParseError {
+                reason: format!("{:?} is not a number", argument),
+            })?;
+        }
+        Ok(vector)
+    };
+
+    for line in source.lines() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["facet", "normal", arguments @ ..] => {
+                normal = read_numbers(arguments)?;
+                positions.clear();
+            }
+            ["vertex", arguments @ ..] => positions.push(read_numbers(arguments)?),
+            ["endfacet", ..] => {
+                if positions.len() != 3 {
+                    return Err(ParseError::new("facet doesn't have exactly three vertices"));
+                }
+                facets.push(Facet {
+                    normal,
+                    positions: [positions[0], positions[1], positions[2]],
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(facets)
+}
+
+/// Parses an ascii or binary .stl file. Binary files may start with "solid" as well, so the size
+/// of the file decides.
+pub fn parse_stl(bytes: &[u8]) -> Result<Vec<Facet>> {
+    if bytes.len() >= BINARY_HEADER_SIZE {
+        let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+        if bytes.len() == BINARY_HEADER_SIZE + count * BINARY_TRIANGLE_SIZE {
+            return parse_binary(bytes);
+        }
+    }
+
+    if bytes.starts_with(b"solid") {
+        parse_ascii(&String::from_utf8_lossy(bytes))
+    } else if bytes.len() >= BINARY_HEADER_SIZE {
+        parse_binary(bytes)
+    } else {
+        Err(ParseError::new("file is too short to be an stl file"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary_stl(header: &[u8], triangles: &[[[f32; 3]; 4]]) -> Vec<u8> {
+        let mut bytes = vec![0; 80];
+        bytes[..header.len()].copy_from_slice(header);
+        bytes.extend_from_slice(&(triangles.len() as u32).to_le_bytes());
+        for triangle in triangles.iter() {
+            for value in triangle.iter().flatten() {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            bytes.extend_from_slice(&[0, 0]);
+        }
+        bytes
+    }
+
+    const TRIANGLE: [[f32; 3]; 4] = [
+        [0.0, 0.0, 1.0],
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+    ];
+
+    #[test]
+    fn ascii() {
+        let source = "solid triangle
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid triangle
+";
+        let facets = parse_stl(source.as_bytes()).unwrap();
+
+        assert_eq!(facets.len(), 1);
+        assert_eq!(facets[0].normal, [0.0, 0.0, 1.0]);
+        assert_eq!(facets[0].positions[2], [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn binary() {
+        let facets = parse_stl(&binary_stl(b"exported", &[TRIANGLE, TRIANGLE])).unwrap();
+
+        assert_eq!(facets.len(), 2);
+        assert_eq!(facets[1].normal, [0.0, 0.0, 1.0]);
+        assert_eq!(facets[1].positions[1], [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn binary_starting_with_solid_is_read_as_binary() {
+        let facets = parse_stl(&binary_stl(b"solid exported", &[TRIANGLE])).unwrap();
+
+        assert_eq!(facets.len(), 1);
+        assert_eq!(facets[0].positions[2], [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn truncated_binary_is_an_error() {
+        let mut bytes = binary_stl(b"exported", &[TRIANGLE, TRIANGLE]);
+        bytes.truncate(bytes.len() - 1);
+        assert!(parse_stl(&bytes).is_err());
+
+        assert!(parse_stl(&[0; 10]).is_err());
+    }
+
+    #[test]
+    fn malformed_ascii_is_an_error() {
+        let source = "solid\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nendloop\n\
+                      endfacet\nendsolid\n";
+        assert!(parse_stl(source.as_bytes()).is_err());
+
+        assert!(parse_stl(b"solid\nfacet normal 0 0\nendsolid\n").is_err());
+        assert!(parse_stl(b"solid\nfacet normal 0 0 1\nvertex 0 x 0\nendsolid\n").is_err());
+    }
+}
@@ -0,0 +1,209 @@
+//! Shares material textures between materials and models, so an image used in many places is
+//! uploaded once.
+
+use crate::import::{self, Result, MISSING_TEXTURE_PATH};
+use crate::material::{MaterialSrgbTexture, MaterialTexture};
+use glium::backend::Facade;
+use glium::texture::{MipmapsOption, RawImage2d, SrgbTexture2d, Texture2d};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Where the image of a cached texture comes from.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TextureSource {
+    /// An image file, by its canonical path, also when a gltf document refers to it by uri.
+    File(PathBuf),
+    /// An image stored inside of a gltf document, as a data uri or in a buffer, by the canonical
+    /// path of the document and the image index.
+    GltfImage { document: PathBuf, image: usize },
+    /// A 1x1 texture of a single colour.
+    Solid([u8; 4]),
+    /// Separate roughness and metalness images packed into the green and blue channels.
+    MetallicRoughness {
+        roughness: Option<PathBuf>,
+        metallic: Option<PathBuf>,
+    },
+}
+
+impl TextureSource {
+    pub fn file<P>(path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        TextureSource::File(canonical_path(path))
+    }
+}
+
+/// The same path however it is written, as long as the file exists.
+pub fn canonical_path<P>(path: P) -> PathBuf
+where
+    P: AsRef<Path>,
+{
+    fs::canonicalize(&path).unwrap_or_else(|_| path.as_ref().to_path_buf())
+}
+
+/// Textures are uploaded with a full mip chain, apart from .dds and .ktx2 files which keep the mip
+/// levels they were saved with. Srgb and linear textures are cached separately, an image sampled
+/// both ways is uploaded once for each.
+#[derive(Default)]
+pub struct TextureCache {
+    srgb_textures: HashMap<TextureSource, Rc<MaterialSrgbTexture>>,
+    textures: HashMap<TextureSource, Rc<MaterialTexture>>,
+}
+
+impl TextureCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the srgb texture uploaded for `source`, uploading the image `load` returns if
+    /// there is none yet.
+    pub fn srgb_texture<'a, F: ?Sized, L>(
+        &mut self,
+        facade: &F,
+        source: TextureSource,
+        load: L,
+    ) -> Result<Rc<MaterialSrgbTexture>>
+    where
+        F: Facade,
+        L: FnOnce() -> Result<RawImage2d<'a, u8>>,
+    {
+        self.cached_srgb_texture(source, || {
+            let texture =
+                SrgbTexture2d::with_mipmaps(facade, load()?, MipmapsOption::AutoGeneratedMipmaps)?;
+            Ok(MaterialSrgbTexture::Uncompressed(texture))
+        })
+    }
+
+    /// Returns the linear texture uploaded for `source`, uploading the image `load` returns if
+    /// there is none yet.
+    pub fn texture<'a, F: ?Sized, L>(
+        &mut self,
+        facade: &F,
+        source: TextureSource,
+        load: L,
+    ) -> Result<Rc<MaterialTexture>>
+    where
+        F: Facade,
+        L: FnOnce() -> Result<RawImage2d<'a, u8>>,
+    {
+        self.cached_texture(source, || {
+            let texture =
+                Texture2d::with_mipmaps(facade, load()?, MipmapsOption::AutoGeneratedMipmaps)?;
+            Ok(MaterialTexture::Uncompressed(texture))
+        })
+    }
+
+    // Returns the texture cached for `source`, or caches the one `upload` creates
+    fn cached_srgb_texture<U>(
+        &mut self,
+        source: TextureSource,
+        upload: U,
+    ) -> Result<Rc<MaterialSrgbTexture>>
+    where
+        U: FnOnce() -> Result<MaterialSrgbTexture>,
+    {
+        if let Some(texture) = self.srgb_textures.get(&source) {
+            return Ok(Rc::clone(texture));
+        }
+        let texture = Rc::new(upload()?);
+        self.srgb_textures.insert(source, Rc::clone(&texture));
+        Ok(texture)
+    }
+
+    fn cached_texture<U>(&mut self, source: TextureSource, upload: U) -> Result<Rc<MaterialTexture>>
+    where
+        U: FnOnce() -> Result<MaterialTexture>,
+    {
+        if let Some(texture) = self.textures.get(&source) {
+            return Ok(Rc::clone(texture));
+        }
+        let texture = Rc::new(upload()?);
+        self.textures.insert(source, Rc::clone(&texture));
+        Ok(texture)
+    }
+
+    /// .dds and .ktx2 files are uploaded by `import::upload_srgb_texture_file`, other images are
+    /// decoded through `image`. Files which can't be read or decoded are replaced by the missing
+    /// texture.
+    pub fn srgb_file<F: ?Sized, P>(
+        &mut self,
+        facade: &F,
+        path: P,
+    ) -> Result<Rc<MaterialSrgbTexture>>
+    where
+        F: Facade,
+        P: AsRef<Path>,
+    {
+        let source = TextureSource::file(&path);
+        let texture = if import::is_texture_file(&path) {
+            self.cached_srgb_texture(source, || {
+                import::upload_srgb_texture_file(facade, &import::decode_texture_file(path)?)
+            })
+        } else {
+            self.srgb_texture(facade, source, || import::try_load_rawimage_rgba(path))
+        };
+        texture.or_else(|error| {
+            eprintln!("{}, using the missing texture instead", error);
+            self.srgb_texture(facade, TextureSource::file(MISSING_TEXTURE_PATH), || {
+                import::try_load_rawimage_rgba(MISSING_TEXTURE_PATH)
+            })
+        })
+    }
+
+    /// .dds and .ktx2 files are uploaded by `import::upload_texture_file`, other images are
+    /// decoded through `image`. Files which can't be read or decoded are replaced by the missing
+    /// texture.
+    pub fn file<F: ?Sized, P>(&mut self, facade: &F, path: P) -> Result<Rc<MaterialTexture>>
+    where
+        F: Facade,
+        P: AsRef<Path>,
+    {
+        let source = TextureSource::file(&path);
+        let texture = if import::is_texture_file(&path) {
+            self.cached_texture(source, || {
+                import::upload_texture_file(facade, &import::decode_texture_file(path)?)
+            })
+        } else {
+            self.texture(facade, source, || import::try_load_rawimage_rgba(path))
+        };
+        texture.or_else(|error| {
+            eprintln!("{}, using the missing texture instead", error);
+            self.texture(facade, TextureSource::file(MISSING_TEXTURE_PATH), || {
+                import::try_load_rawimage_rgba(MISSING_TEXTURE_PATH)
+            })
+        })
+    }
+
+    pub fn solid_srgb<F: ?Sized>(
+        &mut self,
+        facade: &F,
+        colour: [u8; 4],
+    ) -> Result<Rc<MaterialSrgbTexture>>
+    where
+        F: Facade,
+    {
+        self.srgb_texture(facade, TextureSource::Solid(colour), || {
+            Ok(RawImage2d::from_raw_rgba(colour.to_vec(), (1, 1)))
+        })
+    }
+
+    pub fn solid<F: ?Sized>(&mut self, facade: &F, colour: [u8; 4]) -> Result<Rc<MaterialTexture>>
+    where
+        F: Facade,
+    {
+        self.texture(facade, TextureSource::Solid(colour), || {
+            Ok(RawImage2d::from_raw_rgba(colour.to_vec(), (1, 1)))
+        })
+    }
+
+    /// Drops the textures which nothing but the cache holds on to anymore.
+    pub fn remove_unused(&mut self) {
+        self.srgb_textures
+            .retain(|_, texture| Rc::strong_count(texture) > 1);
+        self.textures
+            .retain(|_, texture| Rc::strong_count(texture) > 1);
+    }
+}
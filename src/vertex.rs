@@ -1,66 +1,218 @@
 use glium::backend::Facade;
 use glium::index::PrimitiveType;
 use glium::{IndexBuffer, VertexBuffer};
+use na::Vector3;
+use std::collections::HashMap;
 
 #[derive(Copy, Clone)]
 pub struct Vertex {
     position: [f32; 3],
     texture_coord: [f32; 2],
+    texture_coord_1: [f32; 2],
     normal: [f32; 3],
-    tangent: [f32; 3],
+    tangent: [f32; 4],
+    colour: [f32; 4],
 }
 
 impl Vertex {
+    /// The w component of the tangent is the handedness of the bitangent, either 1.0 or -1.0.
+    /// `texture_coord_1` is the second uv set, only sampled by textures which select it.
+    /// `colour` is linear and multiplied with the base colour, white leaves it unchanged.
     pub fn new(
         position: [f32; 3],
         texture_coord: [f32; 2],
+        texture_coord_1: [f32; 2],
         normal: [f32; 3],
-        tangent: [f32; 3],
+        tangent: [f32; 4],
+        colour: [f32; 4],
     ) -> Self {
         Self {
             position,
             texture_coord,
+            texture_coord_1,
             normal,
             tangent,
+            colour,
         }
     }
 
     pub fn position(&self) -> [f32; 3] {
         self.position
     }
+
+    pub fn texture_coord(&self) -> [f32; 2] {
+        self.texture_coord
+    }
+
+    pub fn texture_coord_1(&self) -> [f32; 2] {
+        self.texture_coord_1
+    }
+
+    pub fn normal(&self) -> [f32; 3] {
+        self.normal
+    }
+
+    pub fn tangent(&self) -> [f32; 4] {
+        self.tangent
+    }
+
+    pub fn colour(&self) -> [f32; 4] {
+        self.colour
+    }
+
+    /// Offsets the position, normal and tangent of the vertex, keeping the normal and tangent
+    /// normalized. A normal or tangent displaced to zero length is left undisplaced. Used to apply
+    /// morph targets.
+    pub fn displaced(
+        &self,
+        position_delta: [f32; 3],
+        normal_delta: [f32; 3],
+        tangent_delta: [f32; 3],
+    ) -> Self {
+        let position = Vector3::from(self.position) + Vector3::from(position_delta);
+        let normal = (Vector3::from(self.normal) + Vector3::from(normal_delta))
+            .try_normalize(std::f32::EPSILON)
+            .unwrap_or_else(|| Vector3::from(self.normal));
+        let undisplaced_tangent = Vector3::new(self.tangent[0], self.tangent[1], self.tangent[2]);
+        let tangent = (undisplaced_tangent + Vector3::from(tangent_delta))
+            .try_normalize(std::f32::EPSILON)
+            .unwrap_or(undisplaced_tangent);
+
+        Self {
+            position: position.into(),
+            normal: normal.into(),
+            tangent: [tangent.x, tangent.y, tangent.z, self.tangent[3]],
+            ..*self
+        }
+    }
+}
+
+/// Generates smooth per-vertex normals for an indexed triangle list, weighting each face by its
+/// area. Vertices which aren't part of any triangle point up.
+pub fn calculate_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    let mut normals: Vec<Vector3<f32>> = vec![Vector3::zeros(); positions.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let index1 = triangle[0] as usize;
+        let index2 = triangle[1] as usize;
+        let index3 = triangle[2] as usize;
+
+        let position1 = Vector3::from(positions[index1]);
+        let edge1 = Vector3::from(positions[index2]) - position1;
+        let edge2 = Vector3::from(positions[index3]) - position1;
+
+        // The cross product's length is twice the triangle's area
+        let normal = edge1.cross(&edge2);
+        for index in [index1, index2, index3].iter() {
+            normals[*index] += normal;
+        }
+    }
+
+    normals
+        .into_iter()
+        .map(|normal| {
+            if normal.norm_squared() <= std::f32::EPSILON {
+                [0.0, 1.0, 0.0]
+            } else {
+                let normal = normal.normalize();
+                [normal.x, normal.y, normal.z]
+            }
+        })
+        .collect()
+}
+
+/// Generates one tangent per vertex for an indexed triangle list. The tangents of every triangle
+/// sharing a vertex are accumulated, then orthogonalized against the vertex normal. The w
+/// component stores the handedness of the uv mapping so the bitangent can be rebuilt in the
+/// shader as `cross(normal, tangent.xyz) * tangent.w`.
+pub fn calculate_tangents(
+    positions: &[[f32; 3]],
+    texture_coords: &[[f32; 2]],
+    normals: &[[f32; 3]],
+    indices: &[u32],
+) -> Vec<[f32; 4]> {
+    let mut tangents: Vec<Vector3<f32>> = vec![Vector3::zeros(); positions.len()];
+    let mut bitangents: Vec<Vector3<f32>> = vec![Vector3::zeros(); positions.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let index1 = triangle[0] as usize;
+        let index2 = triangle[1] as usize;
+        let index3 = triangle[2] as usize;
+
+        let position1 = Vector3::from(positions[index1]);
+        let edge1 = Vector3::from(positions[index2]) - position1;
+        let edge2 = Vector3::from(positions[index3]) - position1;
+
+        let delta_u1 = texture_coords[index2][0] - texture_coords[index1][0];
+        let delta_v1 = texture_coords[index2][1] - texture_coords[index1][1];
+        let delta_u2 = texture_coords[index3][0] - texture_coords[index1][0];
+        let delta_v2 = texture_coords[index3][1] - texture_coords[index1][1];
+
+        let determinant = delta_u1 * delta_v2 - delta_u2 * delta_v1;
+        // Degenerate uvs don't give any information about the tangent space
+        if determinant.abs() <= std::f32::EPSILON {
+            continue;
+        }
+
+        // Not normalizing here weights each triangle's contribution by its area
+        let inverse_coeffecient = 1.0 / determinant;
+        let tangent = (edge1 * delta_v2 - edge2 * delta_v1) * inverse_coeffecient;
+        let bitangent = (edge2 * delta_u1 - edge1 * delta_u2) * inverse_coeffecient;
+
+        for index in [index1, index2, index3].iter() {
+            tangents[*index] += tangent;
+            bitangents[*index] += bitangent;
+        }
+    }
+
+    tangents
+        .into_iter()
+        .zip(bitangents.into_iter())
+        .zip(normals.iter())
+        .map(|((tangent, bitangent), normal)| {
+            let normal = Vector3::from(*normal);
+
+            // Gram-Schmidt orthogonalization against the normal
+            let mut orthogonal = tangent - normal * normal.dot(&tangent);
+            if orthogonal.norm_squared() <= std::f32::EPSILON {
+                // No usable uvs, any vector perpendicular to the normal will do
+                let axis = if normal.x.abs() < 0.9 {
+                    Vector3::x()
+                } else {
+                    Vector3::y()
+                };
+                orthogonal = axis - normal * normal.dot(&axis);
+            }
+            let orthogonal = orthogonal.normalize();
+
+            let handedness = if normal.cross(&orthogonal).dot(&bitangent) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+
+            [orthogonal.x, orthogonal.y, orthogonal.z, handedness]
+        })
+        .collect()
+}
+
+/// Second vertex stream of skinned primitives, drawn alongside the regular vertices.
+#[derive(Copy, Clone)]
+pub struct SkinVertex {
+    /// Indices into the joints of the mesh's skeleton.
+    joints: [u32; 4],
+    /// How much each joint influences the vertex, summing up to 1.0.
+    weights: [f32; 4],
 }
 
-pub fn calculate_tangent(
-    position1: [f32; 3],
-    position2: [f32; 3],
-    position3: [f32; 3],
-    texture_coord1: [f32; 2],
-    texture_coord2: [f32; 2],
-    texture_coord3: [f32; 2],
-) -> [f32; 3] {
-    let edge1 = [
-        position2[0] - position1[0],
-        position2[1] - position1[1],
-        position2[2] - position1[2],
-    ];
-
-    let edge2 = [
-        position3[0] - position1[0],
-        position3[1] - position1[1],
-        position3[2] - position1[2],
-    ];
-
-    let delta_u1 = texture_coord2[0] - texture_coord1[0];
-    let delta_v1 = texture_coord2[1] - texture_coord1[1];
-    let delta_u2 = texture_coord3[0] - texture_coord1[0];
-    let delta_v2 = texture_coord3[1] - texture_coord1[1];
-
-    let inverse_coeffecient = 1.0 / (delta_u1 * delta_v2 - delta_u2 * delta_v1);
-    let tangent_x = inverse_coeffecient * (delta_v2 * edge1[0] - delta_v1 * edge2[0]);
-    let tangent_y = inverse_coeffecient * (delta_v2 * edge1[1] - delta_v1 * edge2[1]);
-    let tangent_z = inverse_coeffecient * (delta_v2 * edge1[2] - delta_v1 * edge2[2]);
-
-    [tangent_x, tangent_y, tangent_z]
+impl SkinVertex {
+    pub fn new(joints: [u32; 4], weights: [f32; 4]) -> Self {
+        Self { joints, weights }
+    }
+
+    pub fn joints(&self) -> [u32; 4] {
+        self.joints
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -111,71 +263,74 @@ impl QuadVertex {
     }
 }
 
-//#[derive(Copy, Clone, Eq, PartialEq, Hash)]
-//struct VertexKey {
-//    position: [NotNan<f32>; 3],
-//    texture_coord: [NotNan<f32>; 2],
-//    normal: [NotNan<f32>; 3],
-//}
-//
-//impl VertexKey {
-//    pub fn new(position: [f32; 3], texture_coord: [f32; 2], normal: [f32; 3]) -> Self {
-//        let position_notnan = [
-//            NotNan::new(position[0]).unwrap(),
-//            NotNan::new(position[1]).unwrap(),
-//            NotNan::new(position[2]).unwrap(),
-//        ];
-//
-//        let texture_coord_notnan = [
-//            NotNan::new(texture_coord[0]).unwrap(),
-//            NotNan::new(texture_coord[1]).unwrap(),
-//        ];
-//        let normal_notnan = [
-//            NotNan::new(normal[0]).unwrap(),
-//            NotNan::new(normal[1]).unwrap(),
-//            NotNan::new(normal[2]).unwrap(),
-//        ];
-//        Self {
-//            position: position_notnan,
-//            texture_coord: texture_coord_notnan,
-//            normal: normal_notnan,
-//        }
-//    }
-//}
-
-//pub fn load_raw_vertex_data(
-//    indices: &[(usize, usize, usize)],
-//    positions: &[[f32; 3]],
-//    texture_coords: &[[f32; 2]],
-//    normals: &[[f32; 3]],
-//) -> (Vec<u32>, Vec<Vertex>) {
-//    let mut indices_vec: Vec<u32> = Vec::new();
-//    let mut vertex_vec: Vec<Vertex> = Vec::new();
-//
-//    let mut data_to_indices_map: HashMap<VertexKey, u32> = HashMap::new();
-//
-//    let mut count: u32 = 0;
-//    for (position_index, texture_coord_index, normal_index) in indices {
-//        let position = positions[*position_index];
-//        let texture_coord = texture_coords[*texture_coord_index];
-//        let normal = normals[*normal_index];
-//
-//        let temp_vertex_key = VertexKey::new(position, texture_coord, normal);
-//
-//        match data_to_indices_map.get(&temp_vertex_key) {
-//            Some(index) => indices_vec.push(*index),
-//            None => {
-//                indices_vec.push(count);
-//                let temp_vertex = Vertex::new(position, texture_coord, normal, tange);
-//                vertex_vec.push(temp_vertex);
-//                data_to_indices_map.insert(temp_vertex_key, count);
-//                count += 1;
-//            }
-//        }
-//    }
-//    (indices_vec, vertex_vec)
-//}
-
-implement_vertex!(Vertex, position, texture_coord, normal, tangent);
+/// The attributes of a vertex before tangents are generated, e.g. a corner of an OBJ face.
+#[derive(Copy, Clone)]
+pub struct RawVertex {
+    pub position: [f32; 3],
+    pub texture_coord: [f32; 2],
+    pub normal: [f32; 3],
+    pub colour: [f32; 4],
+}
+
+// Floats compared by their bits, so vertices only merge when they are exactly the same
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+struct VertexKey {
+    position: [u32; 3],
+    texture_coord: [u32; 2],
+    normal: [u32; 3],
+    colour: [u32; 4],
+}
+
+impl VertexKey {
+    pub fn new(vertex: &RawVertex) -> Self {
+        let bits = |values: &[f32]| {
+            values
+                .iter()
+                .map(|value| value.to_bits())
+                .collect::<Vec<u32>>()
+        };
+        let position = bits(&vertex.position);
+        let texture_coord = bits(&vertex.texture_coord);
+        let normal = bits(&vertex.normal);
+        let colour = bits(&vertex.colour);
+        Self {
+            position: [position[0], position[1], position[2]],
+            texture_coord: [texture_coord[0], texture_coord[1]],
+            normal: [normal[0], normal[1], normal[2]],
+            colour: [colour[0], colour[1], colour[2], colour[3]],
+        }
+    }
+}
+
+/// Merges identical vertices of a triangle list. Returns an index for every input vertex along
+/// with the unique vertices, in the order they first appear.
+pub fn load_raw_vertex_data(raw_vertices: &[RawVertex]) -> (Vec<u32>, Vec<RawVertex>) {
+    let mut indices_vec: Vec<u32> = Vec::with_capacity(raw_vertices.len());
+    let mut vertex_vec: Vec<RawVertex> = Vec::new();
+
+    let mut data_to_indices_map: HashMap<VertexKey, u32> = HashMap::new();
+
+    for raw_vertex in raw_vertices {
+        let index = *data_to_indices_map
+            .entry(VertexKey::new(raw_vertex))
+            .or_insert_with(|| {
+                vertex_vec.push(*raw_vertex);
+                (vertex_vec.len() - 1) as u32
+            });
+        indices_vec.push(index);
+    }
+    (indices_vec, vertex_vec)
+}
+
+implement_vertex!(
+    Vertex,
+    position,
+    texture_coord,
+    texture_coord_1,
+    normal,
+    tangent,
+    colour
+);
+implement_vertex!(SkinVertex, joints, weights);
 implement_vertex!(SkyboxVertex, position);
 implement_vertex!(QuadVertex, position, texture_coord);